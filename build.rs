@@ -0,0 +1,43 @@
+use std::{
+    env,
+    fs,
+    path::Path,
+};
+
+/// Size of the symbol table embedded into the kernel, must match KSYMS_SIZE in src/backtrace.rs
+const KSYMS_SIZE: usize = 256 * 1024;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RADIUS_OS_KSYMS");
+
+    if env::var_os("CARGO_FEATURE_KSYMS").is_some() {
+        embed_ksyms();
+    }
+}
+
+/// Writes symbol table for backtraces into OUT_DIR/ksyms.bin
+///
+/// Symbols are taken from `nm -n` output of a previous build, which path is passed in RADIUS_OS_KSYMS.
+/// Table is always padded to KSYMS_SIZE, so kernel layout stays the same whether it is empty or not
+fn embed_ksyms() {
+    let mut table = match env::var("RADIUS_OS_KSYMS") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            fs::read(&path).unwrap_or_else(|err| panic!("failed to read symbol table {}: {}", path, err))
+        }
+        Err(_)   => Vec::new(),
+    };
+
+    if table.len() > KSYMS_SIZE {
+        println!("cargo:warning=symbol table does not fit into {} bytes, it is truncated", KSYMS_SIZE);
+
+        // Cut at the line boundary, so the last symbol is not broken
+        let end = table[..KSYMS_SIZE].iter().rposition(|&byte| byte == b'\n').map_or(0, |pos| pos + 1);
+        table.truncate(end);
+    }
+    table.resize(KSYMS_SIZE, 0);
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&out_dir).join("ksyms.bin"), table).expect("failed to write symbol table");
+}
@@ -0,0 +1,299 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    mem,
+    ptr,
+    slice,
+};
+use x86_64::PhysAddr;
+
+use crate::memory::phys_to_virt;
+
+/// Root System Description Pointer (ACPI 1.0 part of it)
+///
+/// Entry point into ACPI tables, placed by firmware somewhere in the BIOS area
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)] // To ensure memory layout is exactly as firmware wrote it
+struct Rsdp {
+    signature:    [u8; 8],
+    checksum:     u8,
+    oem_id:       [u8; 6],
+    revision:     u8,
+    rsdt_address: u32,
+}
+
+/// ACPI 2.0+ extension of the RSDP, which adds 64-bit XSDT pointer
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Xsdp {
+    rsdp:              Rsdp,
+    length:            u32,
+    xsdt_address:      u64,
+    extended_checksum: u8,
+    _reserved:         [u8; 3],
+}
+
+/// Header that every System Description Table starts with
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature:        [u8; 4],
+    pub length:           u32,
+    pub revision:         u8,
+    pub checksum:         u8,
+    pub oem_id:           [u8; 6],
+    pub oem_table_id:     [u8; 8],
+    pub oem_revision:     u32,
+    pub creator_id:       u32,
+    pub creator_revision: u32,
+}
+
+/// Describes where a register block lives (memory, I/O ports, etc)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space_id:    u8, // 0 - system memory, 1 - system I/O
+    pub register_bit_width:  u8,
+    pub register_bit_offset: u8,
+    pub access_size:         u8,
+    pub address:             u64,
+}
+
+/// High Precision Event Timer Description Table ("HPET")
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct HpetTable {
+    pub header:               SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address:         GenericAddress,
+    pub hpet_number:          u8,
+    pub minimum_tick:         u16,
+    pub page_protection:      u8,
+}
+
+/// Fixed part of Multiple APIC Description Table ("APIC"), variable length entries follow it
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct MadtHeader {
+    header:             SdtHeader,
+    local_apic_address: u32,
+    flags:              u32,
+}
+
+// Types of MADT entries that are looked at, every entry starts with type & length bytes
+const MADT_LOCAL_APIC:          u8 = 0;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+
+// Local APIC entry flags
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct MadtLocalApic {
+    kind:         u8,
+    length:       u8,
+    processor_id: u8,
+    apic_id:      u8,
+    flags:        u32,
+}
+
+/// 64-bit address of Local APIC, replaces the 32-bit one from MADT header
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct MadtLocalApicOverride {
+    kind:      u8,
+    length:    u8,
+    _reserved: u16,
+    address:   u64,
+}
+
+/// Processor as MADT lists it, identified by ID of its Local APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApic {
+    pub processor_id: u8,
+    pub apic_id:      u8,
+    /// Processor could be started, disabled ones must not be touched
+    pub enabled:      bool,
+}
+
+/// What kernel needs out of Multiple APIC Description Table
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// Physical address of Local APIC registers, same for every processor (each sees its own APIC there)
+    pub local_apic_address: PhysAddr,
+    pub local_apics:        Vec<LocalApic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    RsdpNotFound,
+    InvalidChecksum([u8; 4]),
+    TableNotFound([u8; 4]),
+}
+
+/// RSDT holds 32-bit pointers to other tables, XSDT (ACPI 2.0+) holds 64-bit ones
+#[derive(Debug, Clone, Copy)]
+enum RootTable {
+    Rsdt(PhysAddr),
+    Xsdt(PhysAddr),
+}
+
+static ROOT_TABLE: OnceCell<RootTable> = OnceCell::uninit();
+
+/// Looks up RSDP and remembers root table, so other tables could be found later on
+///
+/// Requires `memory::init` to be called beforehand, as tables are read through
+/// the physical memory mapping
+pub fn init() -> Result<(), AcpiError> {
+    if ROOT_TABLE.is_initialized() {
+        return Ok(());
+    }
+
+    let rsdp_addr = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+    let rsdp: Rsdp = unsafe { read_phys(rsdp_addr) };
+
+    let root_table = if rsdp.revision >= 2 {
+        let xsdp: Xsdp = unsafe { read_phys(rsdp_addr) };
+        if checksum(rsdp_addr, xsdp.length as usize) != 0 {
+            return Err(AcpiError::InvalidChecksum(*b"RSDP"));
+        }
+        RootTable::Xsdt(PhysAddr::new(xsdp.xsdt_address))
+    } else {
+        RootTable::Rsdt(PhysAddr::new(u64::from(rsdp.rsdt_address)))
+    };
+
+    let root_addr = match root_table {
+        RootTable::Rsdt(addr) | RootTable::Xsdt(addr) => addr,
+    };
+    validate_table(root_addr)?;
+
+    ROOT_TABLE.init_once(|| root_table);
+    Ok(())
+}
+
+/// Returns physical address of the first table with given signature, e.g. `b"HPET"`
+pub fn find_table(signature: &[u8; 4]) -> Result<PhysAddr, AcpiError> {
+    let root_table = ROOT_TABLE.try_get().expect("ACPI is not initialised");
+
+    let (root_addr, entry_size) = match *root_table {
+        RootTable::Rsdt(addr) => (addr, mem::size_of::<u32>()),
+        RootTable::Xsdt(addr) => (addr, mem::size_of::<u64>()),
+    };
+
+    let root_header: SdtHeader = unsafe { read_phys(root_addr) };
+    let entries_start = root_addr + mem::size_of::<SdtHeader>();
+    let entries_count = (root_header.length as usize - mem::size_of::<SdtHeader>()) / entry_size;
+
+    for i in 0..entries_count {
+        let entry_addr = entries_start + i * entry_size;
+        let table_addr = match *root_table {
+            RootTable::Rsdt(_) => PhysAddr::new(u64::from(unsafe { read_phys::<u32>(entry_addr) })),
+            RootTable::Xsdt(_) => PhysAddr::new(unsafe { read_phys::<u64>(entry_addr) }),
+        };
+
+        let header: SdtHeader = unsafe { read_phys(table_addr) };
+        if &header.signature == signature {
+            validate_table(table_addr)?;
+            return Ok(table_addr);
+        }
+    }
+
+    Err(AcpiError::TableNotFound(*signature))
+}
+
+/// Returns a copy of HPET description table
+pub fn hpet_table() -> Result<HpetTable, AcpiError> {
+    let addr = find_table(b"HPET")?;
+
+    Ok(unsafe { read_phys(addr) })
+}
+
+/// Parses Multiple APIC Description Table, i.e. finds out which processors there are
+///
+/// Entries other than Local APIC ones (I/O APICs, interrupt overrides, x2APIC) are skipped for now
+pub fn madt() -> Result<Madt, AcpiError> {
+    let addr  = find_table(b"APIC")?;
+    let table: MadtHeader = unsafe { read_phys(addr) };
+
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::new(u64::from(table.local_apic_address)),
+        local_apics:        Vec::new(),
+    };
+
+    let end       = addr + table.header.length as usize;
+    let mut entry = addr + mem::size_of::<MadtHeader>();
+    while entry + 2usize <= end {
+        let kind: u8   = unsafe { read_phys(entry) };
+        let length: u8 = unsafe { read_phys(entry + 1usize) };
+
+        // Malformed entry would make the loop spin forever
+        if length < 2 {
+            break;
+        }
+
+        match kind {
+            MADT_LOCAL_APIC          => {
+                let local_apic: MadtLocalApic = unsafe { read_phys(entry) };
+                madt.local_apics.push(LocalApic {
+                    processor_id: local_apic.processor_id,
+                    apic_id:      local_apic.apic_id,
+                    enabled:      local_apic.flags & LOCAL_APIC_ENABLED != 0,
+                });
+            }
+            MADT_LOCAL_APIC_OVERRIDE => {
+                let address_override: MadtLocalApicOverride = unsafe { read_phys(entry) };
+                madt.local_apic_address = PhysAddr::new(address_override.address);
+            }
+            _                        => {}
+        }
+
+        entry += u64::from(length);
+    }
+
+    Ok(madt)
+}
+
+/// # Safety
+/// Reads `T` from given physical address
+///
+/// This function is unsafe because the caller must guarantee that there is
+/// a valid `T` at that address. Tables are packed, thus read is unaligned
+pub unsafe fn read_phys<T: Copy>(addr: PhysAddr) -> T {
+    ptr::read_unaligned(phys_to_virt(addr).as_ptr::<T>())
+}
+
+/// Searches for RSDP signature in places where BIOS is allowed to put it:
+/// first KiB of Extended BIOS Data Area and 0xE0000..0xFFFFF region
+fn find_rsdp() -> Option<PhysAddr> {
+    // EBDA segment is stored at 0x40E by BIOS
+    let ebda_start = u64::from(unsafe { read_phys::<u16>(PhysAddr::new(0x40e)) }) << 4;
+
+    let ebda_area = ebda_start..(ebda_start + 1024);
+    let bios_area = 0xe_0000..0x10_0000;
+
+    ebda_area
+        .step_by(16) // RSDP is always 16 bytes aligned
+        .chain(bios_area.step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let rsdp: Rsdp = unsafe { read_phys(addr) };
+            &rsdp.signature == b"RSD PTR " && checksum(addr, mem::size_of::<Rsdp>()) == 0
+        })
+}
+
+fn validate_table(addr: PhysAddr) -> Result<(), AcpiError> {
+    let header: SdtHeader = unsafe { read_phys(addr) };
+
+    if checksum(addr, header.length as usize) == 0 {
+        Ok(())
+    } else {
+        Err(AcpiError::InvalidChecksum(header.signature))
+    }
+}
+
+/// All bytes of a valid ACPI structure add up to zero (mod 256)
+fn checksum(addr: PhysAddr, length: usize) -> u8 {
+    let bytes = unsafe { slice::from_raw_parts(phys_to_virt(addr).as_ptr::<u8>(), length) };
+
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
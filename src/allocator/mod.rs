@@ -7,7 +7,6 @@ use bump_allocator::BumpAllocator as Allocator;
 use linked_allocator::LinkedListAllocator as Allocator;
 
 use alloc::alloc::Layout;
-use spin::{ Mutex, MutexGuard };
 use x86_64::{
     structures::paging::{
         mapper::MapToError,
@@ -20,6 +19,8 @@ use x86_64::{
     VirtAddr
 };
 
+use crate::spinlock::{ IrqSpinLock, IrqSpinLockGuard };
+
 #[global_allocator]
 static ALLOCATOR: LockedAllocator<Allocator> = LockedAllocator::new(Allocator::new());
 
@@ -58,19 +59,22 @@ pub fn init_heap(
 
 
 /// Wrapper for a custom Allocator to allow trait implementation on
-/// Mutex<BumpAllocator/LinkedListAllocator>
+/// IrqSpinLock<BumpAllocator/LinkedListAllocator>
 pub struct LockedAllocator<A> {
-    alloc: Mutex<A>,
+    alloc: IrqSpinLock<A>,
 }
 
 impl<A> LockedAllocator<A> {
     pub const fn new(alloc: A) -> Self {
         LockedAllocator {
-            alloc: Mutex::new(alloc)
+            alloc: IrqSpinLock::named("allocator::ALLOCATOR", alloc)
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<A> {
+    /// Interrupts stay disabled while the guard is alive: otherwise a thread could be preempted
+    /// while holding the lock and every other thread that allocates would spin until it runs again
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, A> {
         self.alloc.lock()
     }
 }
@@ -0,0 +1,132 @@
+use conquer_once::spin::OnceCell;
+use core::ptr;
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    structures::paging::{
+        mapper::MapToError,
+        FrameAllocator,
+        Mapper,
+        Size4KiB,
+    },
+    PhysAddr,
+    VirtAddr,
+};
+
+use crate::memory;
+
+// Offsets of Local APIC registers from its base address
+const ID_REG:       usize = 0x020;
+const EOI_REG:      usize = 0x0b0;
+const SPURIOUS_REG: usize = 0x0f0;
+const ICR_LOW_REG:  usize = 0x300;
+const ICR_HIGH_REG: usize = 0x310;
+
+// Size of the register block that has to be mapped
+const REGISTERS_SIZE: u64 = 4096;
+
+// Spurious Interrupt Vector register: APIC is software enabled
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/*
+ * Interrupt Command Register (low half) bits, high half holds destination APIC ID in bits 24-31
+ * | Bits  | Meaning                                           |
+ * | 0-7   | Vector (page of the startup code for Startup IPI) |
+ * | 8-10  | Delivery mode                                     |
+ * | 12    | Delivery status: 1 - IPI has not been sent yet    |
+ * | 14    | Level: 1 - assert, 0 - de-assert (INIT only)      |
+ * | 15    | Trigger mode: 1 - level, 0 - edge                 |
+ */
+const ICR_FIXED:            u32 = 0b000 << 8;
+const ICR_INIT:             u32 = 0b101 << 8;
+const ICR_STARTUP:          u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT:     u32 = 1 << 14;
+const ICR_TRIGGER_LEVEL:    u32 = 1 << 15;
+
+/// Vector APIC raises when interrupt it was about to deliver has gone, must not be acknowledged
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+// Registers are at the same address for every CPU, each CPU sees its own APIC there
+static BASE: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Maps Local APIC registers & enables APIC of the calling (boot) CPU
+///
+/// Interrupts are still delivered by PICs, APIC is used for inter-processor interrupts (IPIs) only
+pub fn init(
+    phys_addr:       PhysAddr,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), MapToError<Size4KiB>> {
+    if BASE.is_initialized() {
+        return Ok(());
+    }
+
+    let base = memory::map_mmio(phys_addr, REGISTERS_SIZE, mapper, frame_allocator)?;
+    BASE.init_once(|| base);
+
+    enable();
+    Ok(())
+}
+
+pub fn is_initialised() -> bool {
+    BASE.is_initialized()
+}
+
+/// Software enables APIC of the calling CPU, every application processor has to do so on its own
+pub fn enable() {
+    write(SPURIOUS_REG, APIC_SOFTWARE_ENABLE | u32::from(SPURIOUS_VECTOR));
+}
+
+/// Returns APIC ID of the calling CPU
+pub fn id() -> u8 {
+    (read(ID_REG) >> 24) as u8
+}
+
+/// Acknowledges interrupt delivered by APIC (e.g. IPI), has to be done before handler returns
+pub fn eoi() {
+    write(EOI_REG, 0);
+}
+
+/// Sends INIT IPI, which resets given CPU & leaves it waiting for Startup IPI
+pub fn send_init(apic_id: u8) {
+    send(apic_id, ICR_INIT | ICR_LEVEL_ASSERT | ICR_TRIGGER_LEVEL);
+    // De-assert is only required by old (pre-Pentium 4) CPUs, others ignore it
+    send(apic_id, ICR_INIT | ICR_TRIGGER_LEVEL);
+}
+
+/// Sends Startup IPI, CPU starts in real mode at `page` * 4096 (so code has to be below 1 MiB)
+pub fn send_startup(apic_id: u8, page: u8) {
+    send(apic_id, ICR_STARTUP | u32::from(page));
+}
+
+/// Raises `vector` on given CPU
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    send(apic_id, ICR_FIXED | u32::from(vector));
+}
+
+fn send(apic_id: u8, command: u32) {
+    // Both halves have to be written by the same CPU without anything else sent in between
+    without_interrupts(|| {
+        write(ICR_HIGH_REG, u32::from(apic_id) << 24);
+        // IPI is sent once low half is written
+        write(ICR_LOW_REG, command);
+
+        while read(ICR_LOW_REG) & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    });
+}
+
+fn read(register: usize) -> u32 {
+    let base = BASE.try_get().expect("APIC is not initialised");
+
+    // Safe because registers are mapped by init() & reading them has no side effects
+    unsafe { ptr::read_volatile((*base + register).as_ptr::<u32>()) }
+}
+
+fn write(register: usize, value: u32) {
+    let base = BASE.try_get().expect("APIC is not initialised");
+
+    // Safe because registers are mapped by init(), they are only written through this module
+    unsafe { ptr::write_volatile((*base + register).as_mut_ptr::<u32>(), value) };
+}
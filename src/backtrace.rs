@@ -0,0 +1,255 @@
+use core::{
+    arch::asm,
+    fmt,
+};
+use x86_64::{
+    structures::idt::InterruptStackFrame,
+    VirtAddr,
+};
+
+use crate::memory;
+
+/// Maximum number of frames that are captured
+pub const MAX_DEPTH: usize = 32;
+
+/// Return addresses of the call chain, collected by walking frame pointers
+///
+/// Kernel is built with frame pointers (see `frame-pointer` in target JSON), thus every
+/// function starts with `push rbp; mov rbp, rsp` and its frame looks like this:
+/// ```text
+///   [rbp + 8] -> return address into the caller
+///   [rbp]     -> rbp of the caller (next frame)
+/// ```
+#[derive(Clone)]
+pub struct Backtrace {
+    frames: [u64; MAX_DEPTH],
+    len:    usize,
+}
+
+impl Backtrace {
+    /// Captures call chain of the function that calls it
+    #[inline(never)] // Must have its own frame, so the first return address points into the caller
+    pub fn capture() -> Backtrace {
+        let rbp: u64;
+        // Unsafe because of inline assembly, though reading rbp has no side effects
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+        Self::walk(rbp)
+    }
+
+    /// Captures call chain of exception handler that calls it
+    ///
+    /// Frame of the handler is followed by interrupt stack frame rather than return address
+    /// (with error code on top of it for some exceptions), so instruction pointer
+    /// of the interrupted code is taken from `stack_frame`
+    #[inline(never)]
+    pub fn capture_exception(stack_frame: &InterruptStackFrame) -> Backtrace {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+        let mut backtrace = Self::walk(rbp);
+        if backtrace.len > 1 {
+            backtrace.frames[1] = stack_frame.instruction_pointer.as_u64();
+        }
+        backtrace
+    }
+
+    /// Returns captured return addresses, the innermost first
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+
+    fn walk(mut rbp: u64) -> Backtrace {
+        let mut backtrace = Backtrace {
+            frames: [0; MAX_DEPTH],
+            len:    0,
+        };
+
+        while backtrace.len < MAX_DEPTH && is_valid_frame(rbp) {
+            // Safe because is_valid_frame() checked that both words are mapped
+            let (next_rbp, return_addr) = unsafe {
+                let frame = rbp as *const u64;
+                (*frame, *frame.add(1))
+            };
+
+            if return_addr == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_addr;
+            backtrace.len += 1;
+
+            // Frame pointing to itself would loop forever
+            if next_rbp == rbp {
+                break;
+            }
+            rbp = next_rbp;
+        }
+
+        backtrace
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+        if self.len == 0 {
+            return writeln!(f, "  <unavailable>");
+        }
+
+        for (i, &addr) in self.frames().iter().enumerate() {
+            match ksyms::resolve(addr) {
+                Some((name, offset)) => writeln!(f, "  #{:<2} {:#018x}  {}+{:#x}", i, addr, name, offset)?,
+                None                 => writeln!(f, "  #{:<2} {:#018x}", i, addr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Frame is only followed if both rbp & return address could be read without page fault
+fn is_valid_frame(rbp: u64) -> bool {
+    // Frames are always 8 bytes aligned
+    if rbp == 0 || rbp & 0b111 != 0 {
+        return false;
+    }
+
+    // Return address slot of a frame at the very top of address space would wrap around
+    let return_addr = match rbp.checked_add(8) {
+        Some(return_addr) => return_addr,
+        None              => return false,
+    };
+
+    match (VirtAddr::try_new(rbp), VirtAddr::try_new(return_addr)) {
+        (Ok(frame), Ok(return_addr)) => memory::translate_addr(frame).is_some() && memory::translate_addr(return_addr).is_some(),
+        _                            => false,
+    }
+}
+
+/// Symbol table embedded into the kernel at build time (see build.rs)
+mod ksyms {
+    /// Size of embedded table, must match KSYMS_SIZE in build.rs
+    #[cfg(feature = "ksyms")]
+    const KSYMS_SIZE: usize = 256 * 1024;
+
+    // Table has fixed size, so embedding real symbols does not move any code around.
+    // Contents is `nm -n` output: "<address> <type> <name>" lines sorted by address, padded with zeros
+    #[cfg(feature = "ksyms")]
+    static KSYMS: [u8; KSYMS_SIZE] = *include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.bin"));
+
+    /// Returns name of the function that contains given address and offset from its start
+    pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+        lookup(table()?, addr)
+    }
+
+    #[cfg(feature = "ksyms")]
+    fn table() -> Option<&'static str> {
+        let len = KSYMS.iter().position(|&byte| byte == 0).unwrap_or(KSYMS_SIZE);
+        core::str::from_utf8(&KSYMS[..len]).ok()
+    }
+
+    #[cfg(not(feature = "ksyms"))]
+    fn table() -> Option<&'static str> {
+        None
+    }
+
+    /// Looks given address up in `nm -n` formatted table
+    pub fn lookup(table: &str, addr: u64) -> Option<(&str, u64)> {
+        let mut found = None;
+        for line in table.lines() {
+            let mut parts = line.splitn(3, ' ');
+            let (sym_addr, sym_type, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(sym_addr), Some(sym_type), Some(name)) => (sym_addr, sym_type, name),
+                _                                            => continue,
+            };
+
+            // Only code symbols are of interest
+            if sym_type != "T" && sym_type != "t" {
+                continue;
+            }
+
+            match u64::from_str_radix(sym_addr, 16) {
+                Ok(sym_addr) if sym_addr <= addr => found = Some((name, addr - sym_addr)),
+                Ok(_)                            => break, // Table is sorted, so no closer symbol would follow
+                Err(_)                           => continue,
+            }
+        }
+
+        found
+    }
+}
+
+// Generous upper bound of the size of test_frame_* functions, return address into one of them lies within
+#[cfg(test)]
+const TEST_FN_SIZE: u64 = 0x400;
+
+// Result passes through black_box, so calls are not turned into tail jumps (which would leave no frame behind)
+#[cfg(test)]
+#[inline(never)]
+fn test_frame_outer() -> Backtrace {
+    core::hint::black_box(test_frame_middle())
+}
+
+#[cfg(test)]
+#[inline(never)]
+fn test_frame_middle() -> Backtrace {
+    core::hint::black_box(test_frame_inner())
+}
+
+#[cfg(test)]
+#[inline(never)]
+fn test_frame_inner() -> Backtrace {
+    core::hint::black_box(Backtrace::capture())
+}
+
+#[test_case]
+fn test_capture_walks_nested_calls() {
+    let backtrace = test_frame_outer();
+    let frames    = backtrace.frames();
+    // Test runner & entry point are further up the chain
+    assert!(frames.len() > 3, "only {} frames captured", frames.len());
+
+    let callers = [
+        VirtAddr::from_ptr(test_frame_inner as *const ()).as_u64(),
+        VirtAddr::from_ptr(test_frame_middle as *const ()).as_u64(),
+        VirtAddr::from_ptr(test_frame_outer as *const ()).as_u64(),
+    ];
+    for (&addr, &function) in frames.iter().zip(callers.iter()) {
+        assert!(
+            addr > function && addr - function < TEST_FN_SIZE,
+            "{:#x} is not a return address into function at {:#x}", addr, function
+        );
+    }
+}
+
+#[test_case]
+fn test_frame_validation() {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    assert!(is_valid_frame(rbp));
+
+    assert!(!is_valid_frame(0));
+    assert!(!is_valid_frame(rbp + 4));
+    // Non-canonical & at the very top of address space (where rbp + 8 overflows)
+    assert!(!is_valid_frame(0x8000_0000_0000_0000));
+    assert!(!is_valid_frame(0xffff_ffff_ffff_fff8));
+}
+
+#[test_case]
+fn test_symbol_lookup() {
+    const TABLE: &str = "\
+                 U undefined
+0000000000201000 T _start
+0000000000201100 t helper
+0000000000201180 D DATA
+not-an-address T broken
+0000000000201200 T kernel_main
+";
+
+    assert_eq!(ksyms::lookup(TABLE, 0x20_1000), Some(("_start", 0)));
+    assert_eq!(ksyms::lookup(TABLE, 0x20_1142), Some(("helper", 0x42)));
+    // Data symbols & malformed lines are skipped
+    assert_eq!(ksyms::lookup(TABLE, 0x20_11f0), Some(("helper", 0xf0)));
+    assert_eq!(ksyms::lookup(TABLE, 0x20_1210), Some(("kernel_main", 0x10)));
+    assert_eq!(ksyms::lookup(TABLE, 0x20_0fff), None);
+    assert_eq!(ksyms::lookup("", 0x20_1000), None);
+}
@@ -0,0 +1,414 @@
+use x86_64::{
+    registers::model_specific::{ Efer, EferFlags },
+    structures::paging::{
+        mapper::{ MapToError, TranslateResult },
+        FrameAllocator,
+        Mapper,
+        OffsetPageTable,
+        Page,
+        PageTableFlags,
+        PhysFrame,
+        Size4KiB,
+        Translate,
+    },
+    VirtAddr,
+};
+
+use crate::memory::{ self, AddressSpace };
+
+const ELF_MAGIC:   [u8; 4] = *b"\x7fELF";
+const ELFCLASS64:  u8      = 2;
+const ELFDATA2LSB: u8      = 1; // Little endian
+const EV_CURRENT:  u8      = 1;
+const ET_EXEC:     u16     = 2; // Statically linked executable
+const EM_X86_64:   u16     = 62;
+
+const HEADER_SIZE:         usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+
+// Segment permissions
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+// Auxiliary vector entry types
+const AT_NULL:   u64 = 0;
+const AT_PHDR:   u64 = 3;
+const AT_PHENT:  u64 = 4;
+const AT_PHNUM:  u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY:  u64 = 9;
+
+/// Initial stack pointer of user programs, stack grows down from here
+pub const USER_STACK_TOP:   u64   = memory::USER_END - 4096;
+/// Size of user stack in pages, the page below it is left unmapped
+pub const USER_STACK_PAGES: usize = 16;
+/// Maximum number of arguments passed to a program
+pub const MAX_ARGS:         usize = 32;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    NotElf64,
+    NotLittleEndian,
+    BadVersion,
+    NotExecutable,
+    WrongMachine(u16),
+    BadProgramHeaders,
+    /// Entry point is not inside of executable segment
+    BadEntry(u64),
+    /// Segment does not fit into the file or into user region
+    BadSegment(u64),
+    TooManyArguments,
+    ArgumentsTooLong,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for ElfError {
+    fn from(err: MapToError<Size4KiB>) -> ElfError {
+        ElfError::Map(err)
+    }
+}
+
+/// Program header (segment) of ELF file
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type:   u32,
+    pub flags:    u32,
+    pub offset:   u64,
+    pub vaddr:    u64,
+    pub filesz:   u64,
+    pub memsz:    u64,
+}
+
+impl ProgramHeader {
+    fn contains(&self, addr: u64) -> bool {
+        self.vaddr <= addr && addr - self.vaddr < self.memsz
+    }
+}
+
+/// ELF64 executable parsed from in-memory image
+///
+/// Only headers are parsed & validated upfront, segments are read straight from the image when loaded
+pub struct ElfFile<'a> {
+    data:      &'a [u8],
+    entry:     u64,
+    phoff:     usize,
+    phentsize: usize,
+    phnum:     usize,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Validates headers of the statically linked x86_64 executable
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64 {
+            return Err(ElfError::NotElf64);
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if data[6] != EV_CURRENT {
+            return Err(ElfError::BadVersion);
+        }
+        if read_u16(data, 16) != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        let machine = read_u16(data, 18);
+        if machine != EM_X86_64 {
+            return Err(ElfError::WrongMachine(machine));
+        }
+
+        let elf = ElfFile {
+            data,
+            entry:     read_u64(data, 24),
+            phoff:     read_u64(data, 32) as usize,
+            phentsize: usize::from(read_u16(data, 54)),
+            phnum:     usize::from(read_u16(data, 56)),
+        };
+
+        let table_size = elf.phentsize.checked_mul(elf.phnum);
+        let table_end  = table_size.and_then(|size| elf.phoff.checked_add(size));
+        if elf.phentsize < PROGRAM_HEADER_SIZE || table_end.filter(|&end| end <= data.len()).is_none() {
+            return Err(ElfError::BadProgramHeaders);
+        }
+
+        for ph in elf.program_headers().filter(|ph| ph.p_type == PT_LOAD) {
+            let file_end = ph.offset.checked_add(ph.filesz);
+            let mem_end  = ph.vaddr.checked_add(ph.memsz);
+
+            let in_file = file_end.filter(|&end| end <= data.len() as u64).is_some();
+            let in_user = ph.vaddr >= memory::USER_START && mem_end.filter(|&end| end <= memory::USER_END).is_some();
+            if !in_file || !in_user || ph.filesz > ph.memsz {
+                return Err(ElfError::BadSegment(ph.vaddr));
+            }
+        }
+
+        let entry_is_code = elf.program_headers()
+            .any(|ph| ph.p_type == PT_LOAD && ph.flags & PF_X != 0 && ph.contains(elf.entry));
+        if !entry_is_code {
+            return Err(ElfError::BadEntry(elf.entry));
+        }
+
+        Ok(elf)
+    }
+
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.phnum).map(move |i| {
+            let header = &self.data[self.phoff + i * self.phentsize..];
+
+            ProgramHeader {
+                p_type: read_u32(header, 0),
+                flags:  read_u32(header, 4),
+                offset: read_u64(header, 8),
+                vaddr:  read_u64(header, 16),
+                filesz: read_u64(header, 32),
+                memsz:  read_u64(header, 40),
+            }
+        })
+    }
+
+    /// Address of program headers in loaded program, if they are part of any segment
+    fn program_headers_addr(&self) -> Option<u64> {
+        let phoff = self.phoff as u64;
+
+        self.program_headers()
+            .find(|ph| ph.p_type == PT_LOAD && ph.offset <= phoff && phoff - ph.offset < ph.filesz)
+            .map(|ph| ph.vaddr + (phoff - ph.offset))
+    }
+}
+
+/// Where to start program that has been loaded
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedProgram {
+    pub entry:         VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads executable into user region of `address_space` and sets up its stack
+///
+/// Stack follows System V ABI: argc, argv pointers, empty environment & auxiliary vector,
+/// with argument strings above them
+pub fn load(
+    data:            &[u8],
+    args:            &[&str],
+    address_space:   &mut AddressSpace,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<LoadedProgram, ElfError> {
+    let elf        = ElfFile::parse(data)?;
+    let mut mapper = address_space.mapper();
+
+    // Without NXE bit in EFER, NO_EXECUTE is a reserved bit, so setting it would fault
+    let nx_enabled = Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE);
+
+    for ph in elf.program_headers().filter(|ph| ph.p_type == PT_LOAD && ph.memsz > 0) {
+        load_segment(data, &ph, nx_enabled, &mut mapper, frame_allocator)?;
+    }
+
+    let stack_pointer = setup_stack(&elf, args, nx_enabled, &mut mapper, frame_allocator)?;
+
+    Ok(LoadedProgram { entry: elf.entry(), stack_pointer })
+}
+
+fn load_segment(
+    data:            &[u8],
+    ph:              &ProgramHeader,
+    nx_enabled:      bool,
+    mapper:          &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), ElfError> {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if ph.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if ph.flags & PF_X == 0 && nx_enabled {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let file_end  = ph.vaddr + ph.filesz;
+    let mem_end   = ph.vaddr + ph.memsz;
+    let first     = Page::<Size4KiB>::containing_address(VirtAddr::new(ph.vaddr));
+    let last      = Page::<Size4KiB>::containing_address(VirtAddr::new(mem_end - 1));
+
+    for page in Page::range_inclusive(first, last) {
+        let frame = map_user_page(page, flags, mapper, frame_allocator)?;
+
+        let page_start = page.start_address().as_u64();
+        let page_end   = page_start + PAGE_SIZE;
+        let page_data  = unsafe { &mut *memory::phys_to_virt(frame.start_address()).as_mut_ptr::<[u8; 4096]>() };
+
+        // Part of the page that is backed by the file...
+        let copy_start = ph.vaddr.max(page_start);
+        let copy_end   = file_end.min(page_end);
+        if copy_start < copy_end {
+            let src = (ph.offset + (copy_start - ph.vaddr)) as usize;
+            let dst = (copy_start - page_start) as usize;
+            let len = (copy_end - copy_start) as usize;
+            page_data[dst..dst + len].copy_from_slice(&data[src..src + len]);
+        }
+
+        // ...and the rest (.bss) is zero, even if page is shared with another segment
+        let zero_start = file_end.max(page_start);
+        let zero_end   = mem_end.min(page_end);
+        if zero_start < zero_end {
+            page_data[(zero_start - page_start) as usize..(zero_end - page_start) as usize].fill(0);
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps zeroed page, or reuses already mapped one (segments may share a page) granting it permissions of both
+fn map_user_page(
+    page:            Page<Size4KiB>,
+    flags:           PageTableFlags,
+    mapper:          &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<PhysFrame, ElfError> {
+    if let TranslateResult::Mapped { frame, flags: old_flags, .. } = mapper.translate(page.start_address()) {
+        let mut merged = (old_flags | flags) - PageTableFlags::NO_EXECUTE;
+        if old_flags.contains(PageTableFlags::NO_EXECUTE) && flags.contains(PageTableFlags::NO_EXECUTE) {
+            merged |= PageTableFlags::NO_EXECUTE;
+        }
+
+        // Address space is not active yet, so there is nothing to flush from TLB
+        unsafe { mapper.update_flags(page, merged).map_err(|_| ElfError::BadSegment(page.start_address().as_u64()))?.ignore() };
+        return Ok(PhysFrame::containing_address(frame.start_address()));
+    }
+
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    unsafe { (*memory::phys_to_virt(frame.start_address()).as_mut_ptr::<[u8; 4096]>()).fill(0) };
+
+    // Parent tables are always writable, so read-only segment does not make its neighbours read-only
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    unsafe { mapper.map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator)?.ignore() };
+
+    Ok(frame)
+}
+
+fn setup_stack(
+    elf:             &ElfFile,
+    args:            &[&str],
+    nx_enabled:      bool,
+    mapper:          &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<VirtAddr, ElfError> {
+    if args.len() > MAX_ARGS {
+        return Err(ElfError::TooManyArguments);
+    }
+
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    if nx_enabled {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let bottom     = USER_STACK_TOP - (USER_STACK_PAGES as u64) * PAGE_SIZE;
+    let mut frames = [None; USER_STACK_PAGES];
+    for (i, frame) in frames.iter_mut().enumerate() {
+        let page = Page::containing_address(VirtAddr::new(bottom + i as u64 * PAGE_SIZE));
+        *frame   = Some(map_user_page(page, flags, mapper, frame_allocator)?);
+    }
+
+    let mut stack = StackWriter { frames, bottom, sp: USER_STACK_TOP };
+
+    // Strings go first (highest), pointers to them are collected for argv
+    let mut argv = [0u64; MAX_ARGS];
+    for (i, arg) in args.iter().enumerate().rev() {
+        stack.push_bytes(&[0])?;
+        stack.push_bytes(arg.as_bytes())?;
+        argv[i] = stack.sp;
+    }
+    stack.sp &= !0xf;
+
+    let mut auxv = [(AT_NULL, 0); 6];
+    let mut auxc = 0;
+    let mut add_aux = |key, value| {
+        auxv[auxc] = (key, value);
+        auxc += 1;
+    };
+    if let Some(phdr) = elf.program_headers_addr() {
+        add_aux(AT_PHDR, phdr);
+    }
+    add_aux(AT_PHENT, elf.phentsize as u64);
+    add_aux(AT_PHNUM, elf.phnum as u64);
+    add_aux(AT_PAGESZ, PAGE_SIZE);
+    add_aux(AT_ENTRY, elf.entry);
+    add_aux(AT_NULL, 0);
+
+    // argc, argv & NULL, envp NULL, auxv pairs - rsp must be 16 bytes aligned at argc
+    let words = 1 + (args.len() + 1) + 1 + auxc * 2;
+    if words & 1 != 0 {
+        stack.push_u64(0)?;
+    }
+
+    for &(key, value) in auxv[..auxc].iter().rev() {
+        stack.push_u64(value)?;
+        stack.push_u64(key)?;
+    }
+    stack.push_u64(0)?; // envp terminator
+    stack.push_u64(0)?; // argv terminator
+    for &arg in argv[..args.len()].iter().rev() {
+        stack.push_u64(arg)?;
+    }
+    stack.push_u64(args.len() as u64)?;
+
+    Ok(VirtAddr::new(stack.sp))
+}
+
+/// Writes into user stack through physical memory mapping, as its address space is not active
+struct StackWriter {
+    frames: [Option<PhysFrame>; USER_STACK_PAGES],
+    bottom: u64,
+    sp:     u64,
+}
+
+impl StackWriter {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), ElfError> {
+        let sp = self.sp
+            .checked_sub(bytes.len() as u64)
+            .filter(|&sp| sp >= self.bottom)
+            .ok_or(ElfError::ArgumentsTooLong)?;
+
+        for (addr, &byte) in (sp..).zip(bytes) {
+            let offset = addr - self.bottom;
+            let frame  = self.frames[(offset / PAGE_SIZE) as usize].expect("user stack page is not mapped");
+
+            unsafe { *memory::phys_to_virt(frame.start_address() + offset % PAGE_SIZE).as_mut_ptr::<u8>() = byte };
+        }
+
+        self.sp = sp;
+        Ok(())
+    }
+
+    fn push_u64(&mut self, value: u64) -> Result<(), ElfError> {
+        self.push_bytes(&value.to_le_bytes())
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().expect("slice of 2 bytes"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().expect("slice of 4 bytes"))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().expect("slice of 8 bytes"))
+}
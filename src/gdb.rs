@@ -0,0 +1,748 @@
+use core::{
+    arch::global_asm,
+    ptr,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+};
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::{
+    registers::control::{ Cr0, Cr0Flags },
+    VirtAddr,
+};
+
+use crate::{
+    interrupts::{ self, InterruptIndex },
+    memory,
+    println,
+    serial_uart::SERIAL2,
+};
+
+/// Size of packet buffers, advertised to gdb in reply to qSupported
+const PACKET_SIZE: usize = 4096;
+/// Maximum number of software breakpoints that could be set at once
+const MAX_BREAKPOINTS: usize = 32;
+
+const INT3:      u8  = 0xcc;
+const CTRL_C:    u8  = 0x03;
+const TRAP_FLAG: u64 = 1 << 8; // RFLAGS.TF - CPU raises debug exception after every instruction
+
+const BREAKPOINT_VECTOR: u64 = 3;
+
+// Registers in the order of gdb's amd64 'g' packet: 8 bytes each for rax..r15 & rip, 4 bytes for the rest
+const GENERAL_REGISTERS: usize = 17;
+const REGISTERS:         usize = 24; // + eflags, cs, ss, ds, es, fs, gs
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STUB:    Mutex<Stub> = Mutex::new(Stub::new());
+
+/// Registers of the interrupted code, as they are laid out on the stack by trap entry below
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15:    u64,
+    pub r14:    u64,
+    pub r13:    u64,
+    pub r12:    u64,
+    pub r11:    u64,
+    pub r10:    u64,
+    pub r9:     u64,
+    pub r8:     u64,
+    pub rbp:    u64,
+    pub rdi:    u64,
+    pub rsi:    u64,
+    pub rdx:    u64,
+    pub rcx:    u64,
+    pub rbx:    u64,
+    pub rax:    u64,
+    pub vector: u64,
+    // Pushed by CPU
+    pub rip:    u64,
+    pub cs:     u64,
+    pub rflags: u64,
+    pub rsp:    u64,
+    pub ss:     u64,
+}
+
+// x86-interrupt handlers do not expose general purpose registers, which debugger has to read & write,
+// so debug & breakpoint exceptions enter through these stubs, which save all of them into TrapFrame.
+// Stack is 16 bytes aligned by CPU before it pushes its 5 words, then vector & 15 registers
+// are pushed, so another 8 bytes are needed to keep it aligned for the call
+global_asm!(r#"
+.global gdb_debug_entry
+gdb_debug_entry:
+    push 1
+    jmp gdb_trap_entry
+
+.global gdb_breakpoint_entry
+gdb_breakpoint_entry:
+    push 3
+    jmp gdb_trap_entry
+
+gdb_trap_entry:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    sub rsp, 8
+    cld
+    call gdb_handle_trap
+    add rsp, 8
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 8
+    iretq
+"#);
+
+extern "C" {
+    fn gdb_debug_entry();
+    fn gdb_breakpoint_entry();
+}
+
+/// Returns address of debug exception entry, to be set in IDT
+pub fn debug_entry() -> VirtAddr {
+    VirtAddr::from_ptr(gdb_debug_entry as *const ())
+}
+
+/// Returns address of breakpoint exception entry, to be set in IDT
+pub fn breakpoint_entry() -> VirtAddr {
+    VirtAddr::from_ptr(gdb_breakpoint_entry as *const ())
+}
+
+/// Starts GDB Remote Serial Protocol stub on COM2
+///
+/// From now on breakpoints & single steps hand control over to gdb and Ctrl-C sent
+/// from gdb interrupts running kernel
+pub fn init() {
+    // Make sure COM2 is initialised before its interrupt could fire
+    drop(SERIAL2.lock());
+    ENABLED.store(true, Ordering::Release);
+
+    interrupts::register_irq(InterruptIndex::SerialPort2, serial_interrupt_handler)
+        .expect("COM2 interrupt handler is already registered");
+}
+
+/// Stops the kernel and waits for debugger commands
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Registered for COM2 IRQ line - gdb sends Ctrl-C when user wants to interrupt running kernel
+fn serial_interrupt_handler(_index: InterruptIndex) {
+    let byte = SERIAL2.lock().receive();
+
+    // Lock must be released before stub takes over the port
+    if byte == CTRL_C {
+        breakpoint();
+    }
+}
+
+/// Called by trap entry on debug & breakpoint exceptions
+#[no_mangle]
+extern "C" fn gdb_handle_trap(frame: &mut TrapFrame) {
+    interrupts::count_interrupt(frame.vector as u8);
+
+    // Single step (if requested) is completed by now
+    frame.rflags &= !TRAP_FLAG;
+
+    if !ENABLED.load(Ordering::Acquire) {
+        // Without debugger breakpoint is reported the same way breakpoint_hanlder does it
+        if frame.vector == BREAKPOINT_VECTOR {
+            println!("EXCEPTION: BREAKPOINT\n{:#x?}", frame);
+        }
+        return;
+    }
+
+    STUB.lock().session(frame);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    addr:          u64,
+    original_byte: u8,
+}
+
+/// What should happen once packet is handled
+enum Action {
+    Reply,
+    Resume,
+}
+
+struct Stub {
+    // Kept apart from the rest of the state, so received packet is borrowed (not copied) while it is handled
+    packet: [u8; PACKET_SIZE],
+    target: Target,
+}
+
+/// Everything gdb could see or change, besides registers of the interrupted code
+struct Target {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    // gdb is only told why kernel stopped once it has talked to us
+    connected:   bool,
+    response:    Response,
+}
+
+impl Stub {
+    const fn new() -> Stub {
+        Stub {
+            packet: [0; PACKET_SIZE],
+            target: Target {
+                breakpoints: [None; MAX_BREAKPOINTS],
+                connected:   false,
+                response:    Response::new(),
+            },
+        }
+    }
+
+    /// Talks to gdb until it asks to continue or to make a single step
+    fn session(&mut self, frame: &mut TrapFrame) {
+        let Stub { packet, target } = self;
+
+        if target.connected {
+            target.response.clear();
+            target.response.push_bytes(b"S05"); // Stopped with SIGTRAP
+            send_packet(target.response.as_bytes());
+        }
+
+        loop {
+            let len = receive_packet(packet);
+            target.connected = true;
+            target.response.clear();
+
+            match target.handle_packet(&packet[..len], frame) {
+                Action::Reply  => send_packet(target.response.as_bytes()),
+                Action::Resume => return,
+            }
+        }
+    }
+}
+
+impl Target {
+    fn handle_packet(&mut self, packet: &[u8], frame: &mut TrapFrame) -> Action {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None                  => return Action::Reply,
+        };
+
+        match command {
+            b'?' => self.response.push_bytes(b"S05"),
+            b'g' => {
+                for n in 0..REGISTERS {
+                    let (value, size) = register(frame, n);
+                    self.response.push_hex_le(value, size);
+                }
+            }
+            b'G' => {
+                let mut values = args;
+                for n in 0..REGISTERS {
+                    let size = register(frame, n).1;
+                    if values.len() < size * 2 {
+                        break;
+                    }
+                    if let Some(value) = parse_hex_le(&values[..size * 2]) {
+                        set_register(frame, n, value);
+                    }
+                    values = &values[size * 2..];
+                }
+                self.response.push_bytes(b"OK");
+            }
+            b'p' => match parse_hex(args).map(|n| n as usize) {
+                Some(n) if n < REGISTERS => {
+                    let (value, size) = register(frame, n);
+                    self.response.push_hex_le(value, size);
+                }
+                _                        => self.response.push_bytes(b"E00"),
+            },
+            b'P' => {
+                let (n, value) = split_at(args, b'=');
+                match (parse_hex(n).map(|n| n as usize), parse_hex_le(value)) {
+                    (Some(n), Some(value)) if n < REGISTERS => {
+                        set_register(frame, n, value);
+                        self.response.push_bytes(b"OK");
+                    }
+                    _                                       => self.response.push_bytes(b"E00"),
+                }
+            }
+            b'm' => {
+                let (addr, len) = split_at(args, b',');
+                match (parse_hex(addr), parse_hex(len)) {
+                    (Some(addr), Some(len)) => {
+                        // Every byte takes two hex digits in response
+                        let len = (len as usize).min(PACKET_SIZE / 2);
+                        for i in 0..len as u64 {
+                            match read_byte(addr.wrapping_add(i)) {
+                                Some(byte) => self.response.push_hex_le(u64::from(byte), 1),
+                                None if i == 0 => {
+                                    self.response.push_bytes(b"E14"); // EFAULT
+                                    break;
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                    _                       => self.response.push_bytes(b"E00"),
+                }
+            }
+            b'M' => {
+                let (addr, rest) = split_at(args, b',');
+                let (_, data)    = split_at(rest, b':');
+
+                let written = parse_hex(addr).map(|addr| {
+                    data.chunks(2)
+                        .enumerate()
+                        .all(|(i, byte)| match parse_hex(byte) {
+                            Some(byte) => write_byte(addr.wrapping_add(i as u64), byte as u8),
+                            None       => false,
+                        })
+                });
+                match written {
+                    Some(true) => self.response.push_bytes(b"OK"),
+                    _          => self.response.push_bytes(b"E14"),
+                }
+            }
+            b'c' | b's' => {
+                // Optional argument is an address to resume at
+                if let Some(addr) = parse_hex(args) {
+                    frame.rip = addr;
+                }
+                if command == b's' {
+                    frame.rflags |= TRAP_FLAG;
+                }
+                return Action::Resume;
+            }
+            b'Z' | b'z' => {
+                let (kind, rest) = split_at(args, b',');
+                let (addr, _)    = split_at(rest, b',');
+
+                // Only software breakpoints (type 0) are supported
+                let result = match (kind, parse_hex(addr)) {
+                    (b"0", Some(addr)) if command == b'Z' => self.insert_breakpoint(addr),
+                    (b"0", Some(addr))                    => self.remove_breakpoint(addr),
+                    _                                     => return Action::Reply, // Empty reply - not supported
+                };
+                match result {
+                    true  => self.response.push_bytes(b"OK"),
+                    false => self.response.push_bytes(b"E0e"),
+                }
+            }
+            b'D' | b'k' => {
+                // Detach or kill - either way kernel continues on its own
+                self.remove_all_breakpoints();
+                self.connected = false;
+
+                if command == b'D' {
+                    self.response.push_bytes(b"OK");
+                    send_packet(self.response.as_bytes());
+                }
+                return Action::Resume;
+            }
+            b'H' => self.response.push_bytes(b"OK"), // Kernel is a single thread for gdb
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    self.response.push_bytes(b"PacketSize=");
+                    self.response.push_hex_be(PACKET_SIZE as u64);
+                } else if args.starts_with(b"Attached") {
+                    self.response.push_bytes(b"1");
+                }
+            }
+            _    => {} // Empty reply - command is not supported
+        }
+
+        Action::Reply
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> bool {
+        if self.breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+            return true;
+        }
+
+        let slot = match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => slot,
+            None       => return false,
+        };
+        let original_byte = match read_byte(addr) {
+            Some(byte) => byte,
+            None       => return false,
+        };
+
+        if write_byte(addr, INT3) {
+            *slot = Some(Breakpoint { addr, original_byte });
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| matches!(bp, Some(bp) if bp.addr == addr)) {
+            Some(slot) => {
+                let breakpoint = slot.take().expect("breakpoint slot is empty");
+                write_byte(breakpoint.addr, breakpoint.original_byte)
+            }
+            None       => false,
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                write_byte(breakpoint.addr, breakpoint.original_byte);
+            }
+        }
+    }
+}
+
+/// Fixed-size response buffer, so trap handler never allocates
+struct Response {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    const fn new() -> Response {
+        Response {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    /// Pushes `size` bytes of value in target (little endian) byte order, as gdb expects for registers & memory
+    fn push_hex_le(&mut self, value: u64, size: usize) {
+        for i in 0..size {
+            let byte = (value >> (i * 8)) as u8;
+            self.push(HEX_DIGITS[usize::from(byte >> 4)]);
+            self.push(HEX_DIGITS[usize::from(byte & 0xf)]);
+        }
+    }
+
+    /// Pushes value as a plain hex number, as gdb expects for numbers in packets
+    fn push_hex_be(&mut self, value: u64) {
+        // Leading zeros are skipped, but at least one digit is always pushed
+        let mut shift = 60;
+        while shift > 0 && value >> shift == 0 {
+            shift -= 4;
+        }
+        for shift in (0..=shift).rev().step_by(4) {
+            self.push(HEX_DIGITS[((value >> shift) & 0xf) as usize]);
+        }
+    }
+}
+
+const HEX_DIGITS: [u8; 16] = *b"0123456789abcdef";
+
+/// Returns register value & its size in bytes, `n` is gdb's register number
+fn register(frame: &TrapFrame, n: usize) -> (u64, usize) {
+    let value = match n {
+        0  => frame.rax,
+        1  => frame.rbx,
+        2  => frame.rcx,
+        3  => frame.rdx,
+        4  => frame.rsi,
+        5  => frame.rdi,
+        6  => frame.rbp,
+        7  => frame.rsp,
+        8  => frame.r8,
+        9  => frame.r9,
+        10 => frame.r10,
+        11 => frame.r11,
+        12 => frame.r12,
+        13 => frame.r13,
+        14 => frame.r14,
+        15 => frame.r15,
+        16 => frame.rip,
+        17 => frame.rflags,
+        18 => frame.cs,
+        19 => frame.ss,
+        _  => 0, // ds, es, fs & gs are not used in long mode
+    };
+
+    (value, if n < GENERAL_REGISTERS { 8 } else { 4 })
+}
+
+fn set_register(frame: &mut TrapFrame, n: usize, value: u64) {
+    let register = match n {
+        0  => &mut frame.rax,
+        1  => &mut frame.rbx,
+        2  => &mut frame.rcx,
+        3  => &mut frame.rdx,
+        4  => &mut frame.rsi,
+        5  => &mut frame.rdi,
+        6  => &mut frame.rbp,
+        7  => &mut frame.rsp,
+        8  => &mut frame.r8,
+        9  => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        // Changing segments under running kernel would only crash it
+        _  => return,
+    };
+    *register = value;
+}
+
+fn read_byte(addr: u64) -> Option<u8> {
+    let addr = VirtAddr::try_new(addr).ok()?;
+    memory::translate_addr(addr)?;
+
+    // Safe because translate_addr() checked that address is mapped
+    Some(unsafe { ptr::read_volatile(addr.as_ptr::<u8>()) })
+}
+
+/// Writes byte even into read-only pages (e.g. kernel code, where breakpoints go)
+fn write_byte(addr: u64, byte: u8) -> bool {
+    let addr = match VirtAddr::try_new(addr) {
+        Ok(addr) if memory::translate_addr(addr).is_some() => addr,
+        _                                                  => return false,
+    };
+
+    // With CR0.WP cleared supervisor code may write into read-only pages
+    let cr0 = Cr0::read();
+    unsafe {
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        ptr::write_volatile(addr.as_mut_ptr::<u8>(), byte);
+        Cr0::write(cr0);
+    }
+    true
+}
+
+/// Byte stream packets go over: COM2, or bytes prepared in memory in tests
+trait Link {
+    fn receive(&mut self) -> u8;
+    fn send(&mut self, byte: u8);
+}
+
+impl Link for SerialPort {
+    fn receive(&mut self) -> u8 {
+        SerialPort::receive(self)
+    }
+
+    fn send(&mut self, byte: u8) {
+        SerialPort::send(self, byte)
+    }
+}
+
+/// Receives "$<data>#<checksum>" packet into buffer and acknowledges it, returns length of data
+fn receive_packet(buf: &mut [u8; PACKET_SIZE]) -> usize {
+    read_packet(&mut *SERIAL2.lock(), buf)
+}
+
+/// Reads packets from link until one with valid checksum arrives, which is acknowledged & copied into buffer.
+/// Returns length of its data
+fn read_packet(link: &mut impl Link, buf: &mut [u8]) -> usize {
+    loop {
+        // Anything outside of packet (acks, Ctrl-C) is skipped
+        while link.receive() != b'$' {}
+
+        let mut len      = 0;
+        let mut checksum = 0u8;
+        let mut overflow = false;
+        loop {
+            match link.receive() {
+                b'#' => break,
+                byte => {
+                    checksum = checksum.wrapping_add(byte);
+                    if len < buf.len() {
+                        buf[len] = byte;
+                        len += 1;
+                    } else {
+                        overflow = true;
+                    }
+                }
+            }
+        }
+
+        let expected = [link.receive(), link.receive()];
+        if !overflow && parse_hex(&expected) == Some(u64::from(checksum)) {
+            link.send(b'+');
+            return len;
+        }
+        link.send(b'-'); // Ask gdb to re-send
+    }
+}
+
+/// Sends "$<data>#<checksum>" packet, re-sending it until gdb acknowledges it
+fn send_packet(data: &[u8]) {
+    let mut serial = SERIAL2.lock();
+    let checksum   = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    loop {
+        serial.send(b'$');
+        for &byte in data {
+            serial.send(byte);
+        }
+        serial.send(b'#');
+        serial.send(HEX_DIGITS[usize::from(checksum >> 4)]);
+        serial.send(HEX_DIGITS[usize::from(checksum & 0xf)]);
+
+        if serial.receive() != b'-' {
+            return;
+        }
+    }
+}
+
+/// Splits bytes at the first separator (separator itself is dropped)
+fn split_at(bytes: &[u8], separator: u8) -> (&[u8], &[u8]) {
+    match bytes.iter().position(|&byte| byte == separator) {
+        Some(pos) => (&bytes[..pos], &bytes[pos + 1..]),
+        None      => (bytes, &[]),
+    }
+}
+
+/// Parses plain hex number, e.g. address or length
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+
+    digits.iter().try_fold(0u64, |value, &digit| {
+        let digit = (digit as char).to_digit(16)?;
+        Some((value << 4) | u64::from(digit))
+    })
+}
+
+/// Parses hex encoded bytes in target (little endian) byte order, e.g. register value
+fn parse_hex_le(digits: &[u8]) -> Option<u64> {
+    if digits.len() & 1 != 0 || digits.len() > 16 {
+        return None;
+    }
+
+    digits.chunks(2)
+        .enumerate()
+        .try_fold(0u64, |value, (i, byte)| Some(value | (parse_hex(byte)? << (i * 8))))
+}
+
+
+/// Link that receives prepared bytes & keeps acknowledgements sent back
+#[cfg(test)]
+struct Script {
+    input: &'static [u8],
+    sent:  [u8; 8],
+    len:   usize,
+}
+
+#[cfg(test)]
+impl Script {
+    fn new(input: &'static [u8]) -> Script {
+        Script { input, sent: [0; 8], len: 0 }
+    }
+
+    fn sent(&self) -> &[u8] {
+        &self.sent[..self.len]
+    }
+}
+
+#[cfg(test)]
+impl Link for Script {
+    fn receive(&mut self) -> u8 {
+        let (byte, rest) = self.input.split_first().expect("script has run out of bytes");
+        self.input = rest;
+        *byte
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.sent[self.len] = byte;
+        self.len += 1;
+    }
+}
+
+#[test_case]
+fn test_packet_framing() {
+    // Ack of the previous reply & Ctrl-C in front of the packet are skipped
+    let mut link = Script::new(b"+\x03$m1000,4#8e");
+    let mut buf  = [0; 16];
+
+    let len = read_packet(&mut link, &mut buf);
+    assert_eq!(&buf[..len], b"m1000,4");
+    assert_eq!(link.sent(), b"+");
+    assert!(link.input.is_empty());
+
+    // Empty packet is still a packet
+    let mut link = Script::new(b"$#00");
+    assert_eq!(read_packet(&mut link, &mut buf), 0);
+    assert_eq!(link.sent(), b"+");
+}
+
+#[test_case]
+fn test_packet_checksum_is_validated() {
+    // Wrong checksum, checksum that is not hex & packet that does not fit are all re-requested
+    let mut link = Script::new(b"$g#66$g#zz$0123456789abcdefX#00$g#67");
+    let mut buf  = [0; 16];
+
+    let len = read_packet(&mut link, &mut buf);
+    assert_eq!(&buf[..len], b"g");
+    assert_eq!(link.sent(), b"---+");
+}
+
+#[test_case]
+fn test_hex_decoding() {
+    assert_eq!(parse_hex(b"0"), Some(0));
+    assert_eq!(parse_hex(b"ffffffff81000000"), Some(0xffff_ffff_8100_0000));
+    assert_eq!(parse_hex(b"1A2b"), Some(0x1a2b));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"12g4"), None);
+    assert_eq!(parse_hex(b"10000000000000000"), None);
+
+    // Register values come in target byte order
+    assert_eq!(parse_hex_le(b"3412"), Some(0x1234));
+    assert_eq!(parse_hex_le(b"efcdab8967452301"), Some(0x0123_4567_89ab_cdef));
+    assert_eq!(parse_hex_le(b"123"), None);
+    assert_eq!(parse_hex_le(b"1x"), None);
+
+    assert_eq!(split_at(b"Z0,1000,1", b','), (&b"Z0"[..], &b"1000,1"[..]));
+    assert_eq!(split_at(b"g", b','), (&b"g"[..], &b""[..]));
+}
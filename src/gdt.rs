@@ -1,54 +1,148 @@
+use core::cell::UnsafeCell;
+use conquer_once::spin::OnceCell;
 use x86_64::{
-    VirtAddr,
+    instructions::interrupts::without_interrupts,
     structures::gdt::{ Descriptor, GlobalDescriptorTable, SegmentSelector },
-    structures::tss::TaskStateSegment
+    structures::paging::{ mapper::MapToError, FrameAllocator, Mapper, Size4KiB },
+    structures::tss::TaskStateSegment,
+    VirtAddr
 };
-use lazy_static::lazy_static;
 
-// Double Fault Interrupt Stack Table index
-pub const DOUBLE_FAULT_IST_IDX: u16 = 0;
-
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector
-}
+use crate::{
+    memory::{ self, StackBounds },
+    percpu,
+};
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
+// Interrupt Stack Table indexes - exceptions that must not run on the interrupted stack
+// (it may have overflown or be in any other broken state) switch to their own stacks
+pub const DOUBLE_FAULT_IST_IDX:  u16 = 0;
+pub const PAGE_FAULT_IST_IDX:    u16 = 1;
+pub const NMI_IST_IDX:           u16 = 2;
+pub const MACHINE_CHECK_IST_IDX: u16 = 3;
 
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_IDX as usize] = {
-            const STACK_SIZE: usize            = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// Size of every IST stack in pages (not counting guard page below it)
+pub const IST_STACK_PAGES: u64 = 5;
 
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+const IST_STACKS: usize = 4;
 
-            // We return top address (end of the stack) because stacks on x86 grow downwards, i.e. from high addresses to low addresses
-            stack_start + STACK_SIZE
-        };
-        tss
-    };
+/// TSS is only read by CPU, however its stack entries are filled in once memory is initialised,
+/// well after TSS is loaded
+struct Tss(UnsafeCell<TaskStateSegment>);
 
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
+// Safe (to share & to hand over to other CPU) because TSS is only written with interrupts disabled, by init_cpu_ist_stacks()
+// & set_kernel_stack() (before its CPU uses it or on its own CPU)
+unsafe impl Sync for Tss {}
+unsafe impl Send for Tss {}
 
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector  = gdt.add_entry(Descriptor::tss_segment(&TSS));
+// Every CPU has TSS of its own, so exceptions of different CPUs never share IST stack
+percpu! {
+    static TSS: Tss = Tss(UnsafeCell::new(TaskStateSegment::new()));
+    static GDT: OnceCell<(GlobalDescriptorTable, Selectors)> = OnceCell::uninit();
+    static STACKS: [OnceCell<StackBounds>; IST_STACKS] = [
+        OnceCell::uninit(),
+        OnceCell::uninit(),
+        OnceCell::uninit(),
+        OnceCell::uninit(),
+    ];
+}
 
-        (gdt, Selectors { code_selector, tss_selector })
-    };
+/// Segment selectors of GDT entries
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub code_selector:      SegmentSelector,
+    pub data_selector:      SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub tss_selector:       SegmentSelector
 }
 
+/// Sets up per-CPU area, GDT & TSS of the boot CPU
 pub fn init() {
+    // Safe because the boot CPU is the first one to run, application processors get further indexes
+    unsafe { init_cpu(0) };
+}
+
+/// Sets up per-CPU area of the calling CPU & loads its GDT & TSS, the first thing every CPU does
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that `index` is not used by any other CPU
+pub unsafe fn init_cpu(index: usize) {
     use x86_64::instructions:: {
         tables::load_tss,
-        segmentation::{ CS, Segment }
+        segmentation::{ CS, SS, Segment }
     };
 
-    GDT.0.load();
-    unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+    percpu::init(index);
+
+    let (gdt, selectors) = GDT.get().get_or_init(|| build_gdt(&*TSS.get().0.get()));
+    gdt.load();
+    CS::set_reg(selectors.code_selector);
+    SS::set_reg(selectors.data_selector);
+    load_tss(selectors.tss_selector);
+}
+
+fn build_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+
+    // Order matters for SYSCALL/SYSRET - CPU derives kernel data selector from kernel code one (+8)
+    // and user code selector from user data one (+8), see syscall::init
+    let code_selector      = gdt.add_entry(Descriptor::kernel_code_segment());
+    let data_selector      = gdt.add_entry(Descriptor::kernel_data_segment());
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector       = gdt.add_entry(Descriptor::tss_segment(tss));
+
+    (gdt, Selectors { code_selector, data_selector, user_data_selector, user_code_selector, tss_selector })
+}
+
+/// Selectors are the same for every CPU
+pub fn selectors() -> &'static Selectors {
+    &GDT.get().get().expect("GDT is not loaded").1
+}
+
+/// Sets stack given CPU switches to when interrupt (or exception) arrives while in user mode
+pub fn set_kernel_stack(cpu: usize, stack_top: VirtAddr) {
+    // Safe because CPU only reads the entry on privilege change, which can't happen while interrupts are disabled here
+    // (or before the CPU is started)
+    let tss = TSS.for_cpu(cpu);
+    without_interrupts(|| unsafe {
+        (*tss.0.get()).privilege_stack_table[0] = stack_top;
+    });
+}
+
+/// Allocates stacks for all IST entries of the calling CPU, each with guard page below it
+///
+/// Must be called right after memory is initialised: until then exceptions that switch stacks (e.g. page fault)
+/// have no stack to switch to & end up in triple fault (i.e. reboot)
+pub fn init_ist_stacks(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), MapToError<Size4KiB>> {
+    init_cpu_ist_stacks(percpu::current_cpu(), mapper, frame_allocator)
+}
+
+/// Allocates IST stacks of given CPU, application processors get them before they are started (see smp)
+pub fn init_cpu_ist_stacks(
+    cpu:             usize,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), MapToError<Size4KiB>> {
+    let tss = TSS.for_cpu(cpu);
+
+    for (index, cell) in STACKS.for_cpu(cpu).iter().enumerate() {
+        let stack = memory::alloc_stack(IST_STACK_PAGES, mapper, frame_allocator)?;
+        cell.try_init_once(|| stack).expect("IST stacks should only be initialised once");
+
+        // We store top address (end of the stack) because stacks on x86 grow downwards, i.e. from high addresses to low addresses
+        without_interrupts(|| unsafe {
+            (*tss.0.get()).interrupt_stack_table[index] = stack.top();
+        });
     }
+
+    Ok(())
 }
 
+/// Returns bounds of the calling CPU's stack at given IST index, None if stacks are not allocated yet
+pub fn ist_stack(index: u16) -> Option<StackBounds> {
+    STACKS.get().get(usize::from(index))?.try_get().ok().copied()
+}
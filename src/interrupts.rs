@@ -1,14 +1,33 @@
+use core::{
+    fmt,
+    sync::atomic::{
+        AtomicU64,
+        AtomicUsize,
+        Ordering,
+    },
+};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
 use x86_64::{
-    instructions::port::Port,
-    structures::idt::{ InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode },
+    instructions::{
+        interrupts::without_interrupts,
+        port::Port,
+    },
+    structures::idt::{ HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode },
     registers::control::Cr2
 };
 
+#[cfg(feature = "gdb-stub")]
+use crate::gdb;
 use crate::{
-    task::keyboard::add_scancode,
+    apic,
+    backtrace::Backtrace,
+    percpu::KernelGs,
+    serial_uart,
+    task::keyboard,
+    thread,
+    time,
     gdt,
     hlt_loop,
     println,
@@ -17,11 +36,79 @@ use crate::{
 pub const PIC1_OFFSET: u8 = 32;
 pub const PIC2_OFFSET: u8 = PIC1_OFFSET + 8;
 
+// I/O ports & commands of 8259 PICs, used directly where ChainedPics falls short
+const PIC1_COMMAND_PORT: u16 = 0x20;
+const PIC2_COMMAND_PORT: u16 = 0xa0;
+const PIC_READ_ISR:      u8  = 0x0b; // OCW3: next read of command port returns In-Service Register
+const PIC_EOI:           u8  = 0x20;
+
+// Exception vectors that have handlers in IDT
+const NMI_VECTOR:           u8 = 2;
+const BREAKPOINT_VECTOR:    u8 = 3;
+const DOUBLE_FAULT_VECTOR:  u8 = 8;
+const PAGE_FAULT_VECTOR:    u8 = 14;
+const MACHINE_CHECK_VECTOR: u8 = 18;
+
+/// Vector of inter-processor interrupt that wakes halted CPU up, e.g. once it has got work (see smp::run_on)
+pub const WAKEUP_VECTOR: u8 = 0xf0;
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error",          "Debug",                 "Non-maskable Interrupt", "Breakpoint",
+    "Overflow",              "Bound Range Exceeded",  "Invalid Opcode",         "Device Not Available",
+    "Double Fault",          "Coprocessor Overrun",   "Invalid TSS",            "Segment Not Present",
+    "Stack-Segment Fault",   "General Protection",    "Page Fault",             "Reserved",
+    "x87 Floating-Point",    "Alignment Check",       "Machine Check",          "SIMD Floating-Point",
+    "Virtualization",        "Reserved",              "Reserved",               "Reserved",
+    "Reserved",              "Reserved",              "Reserved",               "Reserved",
+    "Reserved",              "VMM Communication",     "Security Exception",     "Reserved",
+];
+
+/// Number of IRQ lines served by chained PICs
+///
+/// Once interrupts are routed through APIC, more lines could be added by growing IRQ_HANDLERS & IRQ_STUBS
+pub const IRQ_LINES: usize = 16;
+
 pub static PICS: Mutex<ChainedPics> = Mutex::new(unsafe {
     ChainedPics::new(PIC1_OFFSET, PIC2_OFFSET)
 });
 
-#[derive(Debug, Clone, Copy)]
+/// Function that is called (in interrupt context!) when IRQ line it is registered for fires
+///
+/// Handler receives the line, so same function could serve several devices.
+/// Handler must not block or allocate; end of interrupt is sent to PIC once it returns
+pub type IrqHandler = fn(InterruptIndex);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    AlreadyRegistered(InterruptIndex),
+    NotRegistered(InterruptIndex),
+}
+
+// Registered handlers, stored as function pointers (0 means no handler), so interrupt stubs
+// could read them without taking a lock
+static IRQ_HANDLERS: [AtomicUsize; IRQ_LINES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+// Number of times each of 256 vectors fired; updated without locks, so could be done from any handler
+#[allow(clippy::declare_interior_mutable_const)] // It is only used to initialise array below
+const ZERO_COUNT: AtomicU64 = AtomicU64::new(0);
+static VECTOR_COUNTS: [AtomicU64; 256] = [ZERO_COUNT; 256];
+// Spurious interrupts of primary (IRQ7) & secondary (IRQ15) PICs
+static SPURIOUS_COUNTS: [AtomicU64; 2] = [ZERO_COUNT; 2];
+
+// One generic stub per IRQ line, each of them dispatches to registered handler
+const IRQ_STUBS: [HandlerFunc; IRQ_LINES] = [
+    irq_stub::<0>,  irq_stub::<1>,  irq_stub::<2>,  irq_stub::<3>,
+    irq_stub::<4>,  irq_stub::<5>,  irq_stub::<6>,  irq_stub::<7>,
+    irq_stub::<8>,  irq_stub::<9>,  irq_stub::<10>, irq_stub::<11>,
+    irq_stub::<12>, irq_stub::<13>, irq_stub::<14>, irq_stub::<15>,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC1_OFFSET,
@@ -43,6 +130,24 @@ pub enum InterruptIndex {
 }
 
 impl InterruptIndex {
+    /// Returns interrupt index of given IRQ line (0..IRQ_LINES)
+    pub fn from_line(line: u8) -> Option<InterruptIndex> {
+        use InterruptIndex::*;
+
+        const LINES: [InterruptIndex; IRQ_LINES] = [
+            Timer,         Keyboard,       SecondaryIC, SerialPort2,
+            SerialPort1,   ParallelPort23, FloppyDisk,  ParallelPort1,
+            RealTimeClock, ACPI,           Available1,  Available2,
+            Mouse,         CoProcessor,    PrimaryAta,  SecondaryAta,
+        ];
+        LINES.get(usize::from(line)).copied()
+    }
+
+    /// Returns IRQ line number, i.e. pin of the PIC pair
+    pub fn line(self) -> u8 {
+        self.as_u8() - PIC1_OFFSET
+    }
+
     fn as_u8(self) -> u8 {
         self as u8
     }
@@ -57,20 +162,42 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
 
         // Set CPU Interrupts
+        #[cfg(not(feature = "gdb-stub"))]
         idt.breakpoint.set_handler_fn(breakpoint_hanlder);
+
+        // Debugger has to read & write all registers of the stopped code, which x86-interrupt
+        // handlers do not expose, so breakpoints & single steps go to its own entries
+        #[cfg(feature = "gdb-stub")]
+        unsafe {
+            idt.breakpoint.set_handler_addr(gdb::breakpoint_entry());
+            idt.debug.set_handler_addr(gdb::debug_entry());
+        }
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_IDX);
+            // Kernel stack overflow is a page fault on its guard page, so handler needs a stack of its own
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_IDX);
+            // NMI & machine check could arrive at any instruction, even one switching stacks
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_IDX);
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_IDX);
         }
 
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        // Set PIC interrupts - every line gets generic stub, devices register their handlers at runtime
+        for (line, stub) in (0..).zip(IRQ_STUBS) {
+            let index = InterruptIndex::from_line(line).expect("IRQ stub for unknown line");
+            idt[index.as_usize()].set_handler_fn(stub);
+        }
 
-        // Set PIC interrupts
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
+        // Local APIC interrupts, only inter-processor ones for now
+        idt[usize::from(WAKEUP_VECTOR)].set_handler_fn(wakeup_interrupt_handler);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(apic_spurious_interrupt_handler);
 
         idt
     };
@@ -78,47 +205,281 @@ lazy_static! {
 
 pub fn init_idt() {
     IDT.load();
+
+    register_irq(InterruptIndex::Timer, timer_interrupt_handler)
+        .expect("timer interrupt handler is already registered");
+    register_irq(InterruptIndex::Keyboard, keyboard::keyboard_interrupt_handler)
+        .expect("keyboard interrupt handler is already registered");
+}
+
+/// Loads IDT into application processor, handlers are shared by all CPUs
+pub fn init_ap() {
+    IDT.load();
+}
+
+/// Registers handler for given IRQ line and unmasks that line in PIC
+///
+/// Only one handler per line is allowed, so it has to be unregistered before another could be set
+pub fn register_irq(index: InterruptIndex, handler: IrqHandler) -> Result<(), IrqError> {
+    IRQ_HANDLERS[usize::from(index.line())]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| IrqError::AlreadyRegistered(index))?;
+
+    unmask_irq(index);
+    Ok(())
+}
+
+/// Removes handler of given IRQ line and masks that line in PIC
+///
+/// Returns handler that has been registered
+pub fn unregister_irq(index: InterruptIndex) -> Result<IrqHandler, IrqError> {
+    // Mask first, so interrupt would not fire in between
+    if index != InterruptIndex::SecondaryIC {
+        mask_irq(index);
+    }
+
+    match IRQ_HANDLERS[usize::from(index.line())].swap(0, Ordering::AcqRel) {
+        0       => Err(IrqError::NotRegistered(index)),
+        // Safe because only valid IrqHandler pointers are ever stored into IRQ_HANDLERS
+        handler => Ok(unsafe { core::mem::transmute::<usize, IrqHandler>(handler) }),
+    }
+}
+
+/// Allows PIC to deliver interrupts from given line
+///
+/// Lines of secondary PIC also require cascade line (IRQ2) of primary PIC to be unmasked
+pub fn unmask_irq(index: InterruptIndex) {
+    update_masks(|primary_mask, secondary_mask| {
+        let line = index.line();
+
+        if line < 8 {
+            *primary_mask &= !(1 << line);
+        } else {
+            *secondary_mask &= !(1 << (line - 8));
+            *primary_mask   &= !(1 << InterruptIndex::SecondaryIC.line());
+        }
+    });
+}
+
+/// Stops PIC from delivering interrupts from given line
+pub fn mask_irq(index: InterruptIndex) {
+    update_masks(|primary_mask, secondary_mask| {
+        let line = index.line();
+
+        if line < 8 {
+            *primary_mask |= 1 << line;
+        } else {
+            *secondary_mask |= 1 << (line - 8);
+        }
+    });
+}
+
+fn update_masks(f: impl FnOnce(&mut u8, &mut u8)) {
+    without_interrupts(|| {
+        let mut pics = PICS.lock();
+
+        // Unsafe because unmasking a line of misconfigured PIC could cause UB
+        unsafe {
+            let [mut primary_mask, mut secondary_mask] = pics.read_masks();
+
+            f(&mut primary_mask, &mut secondary_mask);
+            pics.write_masks(primary_mask, secondary_mask);
+        }
+    });
+}
+
+/// Generic interrupt handler of a single IRQ line
+extern "x86-interrupt" fn irq_stub<const LINE: u8>(stack_frame: InterruptStackFrame) {
+    // IRQ may arrive in user mode & handlers use per-CPU data (e.g. TSS on thread switch)
+    let _gs = KernelGs::enter(&stack_frame);
+    dispatch_irq(LINE);
 }
 
+fn dispatch_irq(line: u8) {
+    // Stubs exist only for valid lines, thus expect is fine
+    let index = InterruptIndex::from_line(line).expect("IRQ stub for unknown line");
+    count_interrupt(index.as_u8());
+
+    // Spurious interrupt must not be acknowledged (except of cascade, see is_spurious)
+    if is_spurious(index) {
+        return;
+    }
+
+    let handler = IRQ_HANDLERS[usize::from(line)].load(Ordering::Acquire);
+
+    if handler != 0 {
+        // Safe because only valid IrqHandler pointers are ever stored into IRQ_HANDLERS
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(index);
+    }
+
+    // Unsafe because notify_end_of_interrupt() can potentially cancel unsent interrupt or cause system to hang
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(index.as_u8());
+    }
+
+    // Switching threads is the last thing IRQ does, as the other thread may run for a while
+    thread::preempt_if_needed();
+}
+
+/// PIC raises IRQ7 (or IRQ15 for secondary PIC) when an interrupt disappears before CPU
+/// acknowledged it. Such interrupt is spurious if its bit is not set in PIC In-Service Register
+///
+/// Spurious IRQ15 still went through cascade line of primary PIC, thus primary PIC gets its EOI here
+fn is_spurious(index: InterruptIndex) -> bool {
+    let (command_port, spurious_idx) = match index {
+        InterruptIndex::ParallelPort1 => (PIC1_COMMAND_PORT, 0),
+        InterruptIndex::SecondaryAta  => (PIC2_COMMAND_PORT, 1),
+        _                             => return false,
+    };
+
+    // Hold the lock, so nobody else talks to PICs in between
+    let _pics       = PICS.lock();
+    let mut command = Port::<u8>::new(command_port);
+
+    // Unsafe because reading & writing PIC ports could have side effects
+    let in_service = unsafe {
+        command.write(PIC_READ_ISR);
+        command.read()
+    };
+
+    // Both IRQ7 & IRQ15 are the highest bit of corresponding PIC
+    if in_service & (1 << 7) != 0 {
+        return false;
+    }
+
+    SPURIOUS_COUNTS[spurious_idx].fetch_add(1, Ordering::Relaxed);
+    if index == InterruptIndex::SecondaryAta {
+        unsafe { Port::<u8>::new(PIC1_COMMAND_PORT).write(PIC_EOI) };
+    }
+    true
+}
+
+pub(crate) fn count_interrupt(vector: u8) {
+    VECTOR_COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Snapshot of interrupt counters
+///
+/// Formats as `/proc/interrupts`-like report
+#[derive(Debug, Clone)]
+pub struct InterruptStats {
+    counts:             [u64; 256],
+    spurious_primary:   u64,
+    spurious_secondary: u64,
+}
+
+impl InterruptStats {
+    /// Returns how many times given vector fired
+    pub fn count(&self, vector: u8) -> u64 {
+        self.counts[usize::from(vector)]
+    }
+
+    /// Returns how many times given IRQ line fired (spurious interrupts included)
+    pub fn irq_count(&self, index: InterruptIndex) -> u64 {
+        self.count(index.as_u8())
+    }
+
+    /// Returns number of spurious interrupts of primary (IRQ7) and secondary (IRQ15) PICs
+    pub fn spurious(&self) -> (u64, u64) {
+        (self.spurious_primary, self.spurious_secondary)
+    }
+
+    /// Returns total number of interrupts & exceptions
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl fmt::Display for InterruptStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, " VEC        COUNT  SOURCE")?;
+
+        for (vector, count) in (0u8..=255).zip(self.counts.iter()) {
+            let irq = vector.checked_sub(PIC1_OFFSET).and_then(InterruptIndex::from_line);
+
+            match (irq, EXCEPTION_NAMES.get(usize::from(vector))) {
+                // IRQ lines are listed always, so it is visible which devices keep silent
+                (Some(index), _)                 => writeln!(f, "{:>4} {:>12}  IRQ{:<2} {:?}", vector, count, index.line(), index)?,
+                (None, Some(name)) if *count > 0 => writeln!(f, "{:>4} {:>12}  {}", vector, count, name)?,
+                (None, None)       if *count > 0 => writeln!(f, "{:>4} {:>12}  Vector {}", vector, count, vector)?,
+                _                                => {}
+            }
+        }
+
+        writeln!(f, "{:>4} {:>12}  Spurious IRQ7",  "SPU", self.spurious_primary)?;
+        writeln!(f, "{:>4} {:>12}  Spurious IRQ15", "SPU", self.spurious_secondary)
+    }
+}
+
+/// Returns snapshot of interrupt counters
+///
+/// Counters are read one by one without stopping interrupts, so snapshot is not atomic as a whole
+pub fn stats() -> InterruptStats {
+    let mut counts = [0; 256];
+    for (count, counter) in counts.iter_mut().zip(VECTOR_COUNTS.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+
+    InterruptStats {
+        counts,
+        spurious_primary:   SPURIOUS_COUNTS[0].load(Ordering::Relaxed),
+        spurious_secondary: SPURIOUS_COUNTS[1].load(Ordering::Relaxed),
+    }
+}
+
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(NMI_VECTOR);
+    // NMI could arrive while WRITER or SERIAL1 is held (even by the interrupted code), so it does not lock anything
+    serial_uart::print_unlocked(format_args!("EXCEPTION: NON-MASKABLE INTERRUPT\n{:#?}\n", stack_frame));
+    serial_uart::print_unlocked(format_args!("{}", Backtrace::capture_exception(&stack_frame)));
+}
+
+#[cfg_attr(feature = "gdb-stub", allow(dead_code))]
 extern "x86-interrupt" fn breakpoint_hanlder(stack_frame: InterruptStackFrame) {
+    count_interrupt(BREAKPOINT_VECTOR);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    println!("{}", Backtrace::capture_exception(&stack_frame));
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    count_interrupt(DOUBLE_FAULT_VECTOR);
+    println!("{}", Backtrace::capture_exception(&stack_frame));
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    count_interrupt(MACHINE_CHECK_VECTOR);
+    panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+}
+
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    count_interrupt(PAGE_FAULT_VECTOR);
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error code: {:?}",       error_code);
     println!("{:#?}",                  stack_frame);
+    println!("{}",                     Backtrace::capture_exception(&stack_frame));
 
     hlt_loop();
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!(".");
-
-    // Unsafe because notify_end_of_interrupt() can potentially cancel unsent interrupt or cause system to hang
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+extern "x86-interrupt" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Interrupt itself is what wakes CPU up, there is nothing else to do
+    count_interrupt(WAKEUP_VECTOR);
+    apic::eoi();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut ps2_port = Port::new(0x60); // 0x0060-0x0064: The "8042" PS/2 Controller or its predecessors, dealing with keyboards and mice
-    let scancode: u8 = unsafe { ps2_port.read() };
-
-    // Send scancode into background async task - keeping interrupt handler as simple as possible
-    add_scancode(scancode);
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Spurious interrupt must not be acknowledged
+    count_interrupt(apic::SPURIOUS_VECTOR);
+}
 
-    // Unsafe because notify_end_of_interrupt() can potentially cancel unsent interrupt or cause system to hang
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+fn timer_interrupt_handler(_index: InterruptIndex) {
+    // print!(".");
+    time::tick();
+    thread::timer_tick();
 }
 
 
@@ -126,3 +487,11 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_breakpoint_is_counted() {
+    let before = stats().count(BREAKPOINT_VECTOR);
+    x86_64::instructions::interrupts::int3();
+
+    assert_eq!(stats().count(BREAKPOINT_VECTOR), before + 1);
+}
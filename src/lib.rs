@@ -12,14 +12,31 @@
 // Needed to ensure we can use new() fn of LinkedListAllocator
 #![feature(const_mut_refs)]
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod backtrace;
+pub mod elf;
+#[cfg(feature = "gdb-stub")]
+pub mod gdb;
 pub mod gdt;
 pub mod interrupts;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+pub mod log;
 pub mod macros;
 pub mod memory;
+pub mod percpu;
+pub mod process;
 pub mod qemu_codes;
 pub mod serial_uart;
+pub mod smp;
+pub mod spinlock;
+pub mod syscall;
 pub mod task;
+pub mod thread;
+pub mod time;
+pub mod uart;
 pub mod vga;
 
 extern crate alloc;
@@ -62,6 +79,7 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    serial_println!("{}", backtrace::Backtrace::capture());
 
     qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
     hlt_loop();
@@ -94,8 +112,14 @@ pub fn hlt_loop() -> ! {
 entry_point!(test_kernel_main);
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();
+
+    let phys_memory_offset  = x86_64::VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+
     test_main();
     hlt_loop();
 }
@@ -0,0 +1,229 @@
+use core::{
+    cell::RefCell,
+    fmt,
+    panic::Location,
+    sync::atomic::{ AtomicBool, Ordering },
+};
+use spin::Mutex;
+
+use crate::percpu;
+
+/// Most locks a CPU may hold at once that are tracked, further ones are not checked
+const MAX_HELD: usize = 16;
+/// Most lock classes told apart, locks of further classes are not checked
+const MAX_CLASSES: usize = 64;
+
+/// Lock held by a CPU
+#[derive(Clone, Copy)]
+struct HeldLock {
+    id:       usize,
+    class:    Option<usize>,
+    name:     &'static str,
+    location: &'static Location<'static>,
+}
+
+struct Held {
+    locks: [Option<HeldLock>; MAX_HELD],
+    len:   usize,
+}
+
+percpu! {
+    static HELD: RefCell<Held> = RefCell::new(Held { locks: [None; MAX_HELD], len: 0 });
+}
+
+/// Order in which lock classes were ever taken: edge from A to B means B was taken while A was held
+///
+/// Classes are told apart by name (see IrqSpinLock::named), so all locks of a class have to be taken in one order
+struct Graph {
+    classes: [&'static str; MAX_CLASSES],
+    len:     usize,
+    // Bit `to` of edges[from] is set if there is edge from -> to
+    edges:   [u64; MAX_CLASSES],
+    // Where edge was first seen
+    seen_at: [[Option<&'static Location<'static>>; MAX_CLASSES]; MAX_CLASSES],
+}
+
+// Plain spin Mutex since IrqSpinLock is what calls in here, interrupts are already disabled by then
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    classes: [""; MAX_CLASSES],
+    len:     0,
+    edges:   [0; MAX_CLASSES],
+    seen_at: [[None; MAX_CLASSES]; MAX_CLASSES],
+});
+
+// Checks stop after first report, as the state is not trustworthy anymore & panic handler takes locks too
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Records that lock `id` is about to be taken on the calling CPU, panics if that could deadlock
+///
+/// `trylock` acquisitions can not deadlock themselves, so they are only recorded as held
+pub(crate) fn acquire(id: usize, name: &'static str, location: &'static Location<'static>, trylock: bool) {
+    // Locks taken before GS base is set (very early boot or application processor start) are not tracked
+    if REPORTED.load(Ordering::Relaxed) || !percpu::is_initialised() {
+        return;
+    }
+
+    let held = HELD.with(|held| {
+        let held = held.borrow();
+        let mut locks = [None; MAX_HELD];
+        locks[..held.len].copy_from_slice(&held.locks[..held.len]);
+        (locks, held.len)
+    });
+    let held = &held.0[..held.1];
+
+    if !trylock {
+        if let Some(holding) = held.iter().flatten().find(|lock| lock.id == id) {
+            report(Report::Recursive { name, location, holding: *holding });
+        }
+    }
+
+    let class = {
+        let mut graph = GRAPH.lock();
+        let class = graph.class(name);
+
+        if let (Some(class), false) = (class, trylock) {
+            for holding in held.iter().flatten() {
+                let from = match holding.class {
+                    Some(from) if from != class => from,
+                    _                           => continue,
+                };
+
+                if let Some(path) = graph.path(class, from) {
+                    drop(graph);
+                    report(Report::Inversion { name, location, holding: *holding, path });
+                }
+                if graph.seen_at[from][class].is_none() {
+                    graph.edges[from]         |= 1 << class;
+                    graph.seen_at[from][class] = Some(location);
+                }
+            }
+        }
+        class
+    };
+
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        if held.len < MAX_HELD {
+            let len = held.len;
+            held.locks[len] = Some(HeldLock { id, class, name, location });
+            held.len += 1;
+        }
+    });
+}
+
+/// Records that lock `id` was released on the calling CPU
+pub(crate) fn release(id: usize) {
+    if !percpu::is_initialised() {
+        return;
+    }
+
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        let len = held.len;
+        // Locks need not be released in reverse order. It is not found if it did not fit in
+        if let Some(index) = held.locks[..len].iter().rposition(|lock| lock.filter(|lock| lock.id == id).is_some()) {
+            held.locks.copy_within(index + 1..len, index);
+            held.locks[len - 1] = None;
+            held.len -= 1;
+        }
+    });
+}
+
+impl Graph {
+    /// Class of locks with given name, registers new class if there is room
+    fn class(&mut self, name: &'static str) -> Option<usize> {
+        if let Some(class) = self.classes[..self.len].iter().position(|class| *class == name) {
+            return Some(class);
+        }
+        if self.len == MAX_CLASSES {
+            return None;
+        }
+
+        self.classes[self.len] = name;
+        self.len += 1;
+        Some(self.len - 1)
+    }
+
+    /// Shortest chain of edges from one class to another, breadth first
+    fn path(&self, from: usize, to: usize) -> Option<Path> {
+        let mut parent  = [usize::MAX; MAX_CLASSES];
+        let mut queue   = [0; MAX_CLASSES];
+        let mut visited = 1u64 << from;
+        let (mut head, mut tail) = (0, 1);
+        queue[0] = from;
+
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+
+            if class == to {
+                let mut path = Path { steps: [("", None); MAX_CLASSES], len: 0 };
+                let mut at   = to;
+                while at != from {
+                    let previous = parent[at];
+                    path.steps[path.len] = (self.classes[at], self.seen_at[previous][at]);
+                    path.len += 1;
+                    at = previous;
+                }
+                path.steps[path.len] = (self.classes[from], None);
+                path.len += 1;
+                path.steps[..path.len].reverse();
+                return Some(path);
+            }
+
+            let mut successors = self.edges[class] & !visited;
+            while successors != 0 {
+                let next = successors.trailing_zeros() as usize;
+                successors  &= successors - 1;
+                visited     |= 1 << next;
+                parent[next] = class;
+                queue[tail]  = next;
+                tail        += 1;
+            }
+        }
+        None
+    }
+}
+
+/// Chain of classes with where each one was taken while previous one was held
+struct Path {
+    steps: [(&'static str, Option<&'static Location<'static>>); MAX_CLASSES],
+    len:   usize,
+}
+
+// Built once right before panic, so size does not matter
+#[allow(clippy::large_enum_variant)]
+enum Report {
+    Recursive { name: &'static str, location: &'static Location<'static>, holding: HeldLock },
+    Inversion { name: &'static str, location: &'static Location<'static>, holding: HeldLock, path: Path },
+}
+
+fn report(report: Report) -> ! {
+    REPORTED.store(true, Ordering::Relaxed);
+    panic!("{}", report);
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Report::Recursive { name, location, holding } => {
+                writeln!(f, "lockdep: recursive locking detected on CPU {}", percpu::current_cpu())?;
+                writeln!(f, "  acquiring {} at {}", name, location)?;
+                write!(f, "  already held, taken at {}", holding.location)
+            }
+            Report::Inversion { name, location, holding, path } => {
+                writeln!(f, "lockdep: lock order inversion detected on CPU {}", percpu::current_cpu())?;
+                writeln!(f, "  acquiring {} at {}", name, location)?;
+                writeln!(f, "  while holding {} taken at {}", holding.name, holding.location)?;
+                write!(f, "  but earlier it was the other way round:")?;
+                for (class, seen_at) in &path.steps[..path.len] {
+                    match seen_at {
+                        Some(seen_at) => write!(f, "\n    then {} at {}", class, seen_at)?,
+                        None          => write!(f, "\n    {} held", class)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
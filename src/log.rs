@@ -0,0 +1,459 @@
+use core::{
+    fmt::{ self, Write },
+    str,
+    sync::atomic::{ AtomicU8, Ordering },
+};
+
+use crate::{
+    percpu,
+    println,
+    serial_println,
+    spinlock::IrqSpinLock,
+    time,
+};
+
+/// Size of the in-memory log (dmesg), oldest records are dropped once it is full
+pub const DMESG_SIZE: usize = 16 * 1024;
+/// Most sinks that could be registered at once
+pub const MAX_SINKS: usize = 8;
+/// Most module filters that could be set at once
+pub const MAX_FILTERS: usize = 16;
+
+/// How important record is, `Error` is the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Least important level that still gets logged, `Off` logs nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LevelFilter {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    TooManySinks,
+    TooManyFilters,
+    /// Sink with the same name is registered already
+    SinkExists,
+}
+
+/// Single log message together with where & when it has been logged
+pub struct Record<'a> {
+    pub level:        Level,
+    /// Module path of the call site, e.g. `radius_os::smp`
+    pub module:       &'static str,
+    /// Uptime, precision is that of time::uptime_ns
+    pub timestamp_ns: u64,
+    pub cpu:          usize,
+    pub args:         fmt::Arguments<'a>,
+}
+
+/// Destination of log records, e.g. screen or serial port
+///
+/// Sinks are called with no log lock held, but they could be called from interrupt handler,
+/// so they must not block or allocate
+pub trait Sink: Sync {
+    /// Identifies the sink, see remove_sink
+    fn name(&self) -> &'static str;
+
+    fn write(&self, record: &Record);
+}
+
+/// Prints records on the screen
+pub struct VgaSink;
+
+/// Prints records to COM1
+pub struct SerialSink;
+
+pub static VGA:    VgaSink    = VgaSink;
+pub static SERIAL: SerialSink = SerialSink;
+
+#[derive(Clone, Copy)]
+struct Filter {
+    module: &'static str,
+    level:  LevelFilter,
+}
+
+struct Filters {
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+/// Ring of log text, whole lines are dropped from its start to make room
+struct Ring<const N: usize> {
+    bytes: [u8; N],
+    head:  usize,
+    len:   usize,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Info as u8);
+// Most verbose of LEVEL & module filters, so most disabled records are rejected without a lock
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Info as u8);
+
+static FILTERS: IrqSpinLock<Filters>          = IrqSpinLock::named("log::FILTERS", Filters::new());
+static DMESG:   IrqSpinLock<Ring<DMESG_SIZE>> = IrqSpinLock::named("log::DMESG", Ring::new());
+// Screen & COM1 get records from the very start
+static SINKS:   IrqSpinLock<[Option<&dyn Sink>; MAX_SINKS]> = IrqSpinLock::named("log::SINKS", {
+    let mut sinks: [Option<&dyn Sink>; MAX_SINKS] = [None; MAX_SINKS];
+    sinks[0] = Some(&VGA);
+    sinks[1] = Some(&SERIAL);
+    sinks
+});
+
+/// Sets level of modules that have no filter of their own
+pub fn set_level(level: LevelFilter) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    FILTERS.lock().update_max_level();
+}
+
+pub fn level() -> LevelFilter {
+    LevelFilter::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets level of `module` & its submodules (e.g. `radius_os::task` also covers `radius_os::task::executor`),
+/// the longest matching module wins
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> Result<(), LogError> {
+    let mut filters = FILTERS.lock();
+    filters.set(module, level)?;
+    filters.update_max_level();
+    Ok(())
+}
+
+/// Makes `module` use the level of its parent again, returns false if it had no filter
+pub fn clear_module_level(module: &str) -> bool {
+    let mut filters = FILTERS.lock();
+    let cleared     = filters.clear(module);
+    filters.update_max_level();
+    cleared
+}
+
+/// Returns true if record of given level from `module` would be logged
+pub fn enabled(level: Level, module: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+    FILTERS.lock().level(module) >= level
+}
+
+/// Adds a sink, it gets all records logged from now on
+pub fn add_sink(sink: &'static dyn Sink) -> Result<(), LogError> {
+    let mut sinks = SINKS.lock();
+
+    if sinks.iter().flatten().any(|registered| registered.name() == sink.name()) {
+        return Err(LogError::SinkExists);
+    }
+    let slot = sinks.iter_mut().find(|slot| slot.is_none()).ok_or(LogError::TooManySinks)?;
+    *slot = Some(sink);
+    Ok(())
+}
+
+/// Removes sink with given name (e.g. "vga" to keep the screen to user programs), returns false if there is none
+pub fn remove_sink(name: &str) -> bool {
+    let mut sinks = SINKS.lock();
+
+    match sinks.iter_mut().find(|slot| slot.filter(|sink| sink.name() == name).is_some()) {
+        Some(slot) => {
+            *slot = None;
+            true
+        }
+        None       => false,
+    }
+}
+
+/// Copies the newest dmesg lines that fit into `buf`, returns them as text
+pub fn read_dmesg(buf: &mut [u8]) -> &str {
+    let len = DMESG.lock().copy_newest(buf);
+    // Text has been truncated at line start, unless line is larger than the whole buffer
+    match str::from_utf8(&buf[..len]) {
+        Ok(text) => text,
+        Err(err) => str::from_utf8(&buf[err.valid_up_to()..len]).unwrap_or(""),
+    }
+}
+
+/// Drops everything logged so far from dmesg
+pub fn clear_dmesg() {
+    DMESG.lock().clear();
+}
+
+/// Only to be used by macros!
+#[doc(hidden)]
+pub fn _log(level: Level, module: &'static str, args: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+
+    let record = Record {
+        level,
+        module,
+        timestamp_ns: time::uptime_ns(),
+        // Early boot code runs before GS base is set, it is the boot CPU anyway
+        cpu:          if percpu::is_initialised() { percpu::current_cpu() } else { 0 },
+        args,
+    };
+
+    // Ring never fails to make room, so result does not matter
+    let _ = writeln!(DMESG.lock(), "{}", record);
+
+    // Sinks are copied out, so sink that logs itself does not dead-lock
+    let sinks = *SINKS.lock();
+    for sink in sinks.iter().flatten() {
+        sink.write(&record);
+    }
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn  => "WARN",
+            Level::Info  => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl LevelFilter {
+    fn from_u8(level: u8) -> LevelFilter {
+        match level {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+impl PartialEq<Level> for LevelFilter {
+    fn eq(&self, level: &Level) -> bool {
+        *self as u8 == *level as u8
+    }
+}
+
+impl PartialOrd<Level> for LevelFilter {
+    fn partial_cmp(&self, level: &Level) -> Option<core::cmp::Ordering> {
+        Some((*self as u8).cmp(&(*level as u8)))
+    }
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:06}] cpu{} {:<5} {}: {}",
+            self.timestamp_ns / 1_000_000_000,
+            self.timestamp_ns % 1_000_000_000 / 1_000,
+            self.cpu,
+            self.level,
+            self.module,
+            self.args,
+        )
+    }
+}
+
+impl Sink for VgaSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write(&self, record: &Record) {
+        // Screen is small, so only the level & message are printed
+        println!("{:<5} {}", record.level, record.args);
+    }
+}
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, record: &Record) {
+        serial_println!("{}", record);
+    }
+}
+
+impl Filters {
+    const fn new() -> Filters {
+        Filters { filters: [None; MAX_FILTERS] }
+    }
+
+    fn set(&mut self, module: &'static str, level: LevelFilter) -> Result<(), LogError> {
+        let slot = match self.filters.iter().position(|filter| filter.filter(|filter| filter.module == module).is_some()) {
+            Some(index) => &mut self.filters[index],
+            None        => self.filters.iter_mut().find(|filter| filter.is_none()).ok_or(LogError::TooManyFilters)?,
+        };
+        *slot = Some(Filter { module, level });
+        Ok(())
+    }
+
+    fn clear(&mut self, module: &str) -> bool {
+        match self.filters.iter_mut().find(|filter| filter.filter(|filter| filter.module == module).is_some()) {
+            Some(filter) => {
+                *filter = None;
+                true
+            }
+            None         => false,
+        }
+    }
+
+    /// Level of the longest filter `module` is within, global level if there is none
+    fn level(&self, module: &str) -> LevelFilter {
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|filter| within(module, filter.module))
+            .max_by_key(|filter| filter.module.len())
+            .map(|filter| filter.level)
+            .unwrap_or_else(level)
+    }
+
+    fn update_max_level(&self) {
+        let max_level = self.filters
+            .iter()
+            .flatten()
+            .map(|filter| filter.level)
+            .fold(level(), LevelFilter::max);
+
+        MAX_LEVEL.store(max_level as u8, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if `module` is `parent` or one of its submodules
+fn within(module: &str, parent: &str) -> bool {
+    match module.strip_prefix(parent) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None       => false,
+    }
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Ring<N> {
+        Ring {
+            bytes: [0; N],
+            head:  0,
+            len:   0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.drop_line();
+        }
+        self.bytes[(self.head + self.len) % N] = byte;
+        self.len += 1;
+    }
+
+    /// Drops the oldest line (or whatever is left of it)
+    fn drop_line(&mut self) {
+        while self.len > 0 {
+            let byte = self.bytes[self.head];
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+
+    /// Copies the newest whole lines that fit into `buf`, returns how many bytes have been copied
+    fn copy_newest(&self, buf: &mut [u8]) -> usize {
+        let mut skip = self.len.saturating_sub(buf.len());
+        if skip > 0 {
+            // Copy starts right after a line end, so it has no partial line
+            while skip < self.len && self.bytes[(self.head + skip - 1) % N] != b'\n' {
+                skip += 1;
+            }
+        }
+
+        let len = self.len - skip;
+        for (index, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.bytes[(self.head + skip + index) % N];
+        }
+        len
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len  = 0;
+    }
+}
+
+impl<const N: usize> Write for Ring<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+
+#[test_case]
+fn test_ring_drops_whole_lines() {
+    let mut ring = Ring::<16>::new();
+    let mut buf  = [0; 16];
+
+    ring.write_str("first\nsecond\n").unwrap();
+    let len = ring.copy_newest(&mut buf);
+    assert_eq!(&buf[..len], b"first\nsecond\n");
+
+    ring.write_str("third\n").unwrap();
+    let len = ring.copy_newest(&mut buf);
+    assert_eq!(&buf[..len], b"second\nthird\n");
+
+    let mut small = [0; 8];
+    let len = ring.copy_newest(&mut small);
+    assert_eq!(&small[..len], b"third\n");
+}
+
+#[test_case]
+fn test_longest_module_filter_wins() {
+    let mut filters = Filters::new();
+    filters.set("radius_os::task", LevelFilter::Debug).unwrap();
+    filters.set("radius_os::task::executor", LevelFilter::Error).unwrap();
+
+    assert_eq!(filters.level("radius_os::task::executor"), LevelFilter::Error);
+    assert_eq!(filters.level("radius_os::task::executor::inner"), LevelFilter::Error);
+    assert_eq!(filters.level("radius_os::task::keyboard"), LevelFilter::Debug);
+    // Prefix that is not at a module boundary does not count
+    assert_eq!(filters.level("radius_os::tasks"), level());
+
+    assert!(filters.clear("radius_os::task::executor"));
+    assert_eq!(filters.level("radius_os::task::executor"), LevelFilter::Debug);
+}
+
+#[test_case]
+fn test_records_reach_dmesg() {
+    let mut buf = [0; 256];
+
+    set_module_level(module_path!(), LevelFilter::Debug).unwrap();
+    crate::debug!("dmesg test record {}", 42);
+    crate::trace!("dmesg test record that is filtered out");
+    clear_module_level(module_path!());
+
+    let text = read_dmesg(&mut buf);
+    let line = text.lines().last().unwrap();
+    assert!(line.ends_with("DEBUG radius_os::log: dmesg test record 42"));
+    assert!(line.contains(" cpu0 "));
+}
@@ -21,3 +21,36 @@ macro_rules! serial_println {
     ($fmt:expr,$($arg:tt)* ) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
 
+
+/// Logs a record of given log::Level, e.g. `log!(Level::Info, "CPUs online: {}", cpus)`
+///
+/// Record is dropped if level is disabled for the calling module (see log::set_level & log::set_module_level)
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => ($crate::log::_log($level, module_path!(), format_args!($($arg)+)));
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Trace, $($arg)+));
+}
@@ -15,14 +15,24 @@ use radius_os::{
     task::{
         executor::Executor,
         keyboard::print_keypress,
+        multicore::MultiCoreExecutor,
+        policy::PolicyKind,
         Task,
     },
+    time::{ self, TickSource },
+    uart::{ self, ComPort },
     allocator,
+    gdt,
+    info,
     println,
+    smp,
+    syscall,
+    thread,
     vga,
+    warn,
 };
 #[cfg(not(test))]
-use radius_os::hlt_loop;
+use radius_os::{ backtrace::Backtrace, hlt_loop };
 #[cfg(test)]
 use radius_os::test_panic_handler;
 use x86_64::VirtAddr;
@@ -33,6 +43,7 @@ use x86_64::VirtAddr;
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("Panic at the disco *dance*: {}", info);
+    println!("{}", Backtrace::capture());
     hlt_loop();
 }
 
@@ -59,14 +70,57 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut mapper          = unsafe { memory::init(phys_memory_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    syscall::init(&mut mapper, &mut frame_allocator).expect("syscall initialisation failed");
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
     // <<<<<<<<
+
+    let tick_source = if cfg!(feature = "hpet-timer") { TickSource::Hpet } else { TickSource::Pit };
+    time::init(tick_source, &mut mapper, &mut frame_allocator);
+    match smp::init(&boot_info.memory_map, &mut mapper, &mut frame_allocator) {
+        Ok(cpus) => info!("CPUs online: {}", cpus),
+        Err(err) => warn!("Application processors not started, running on boot CPU only: {:?}", err),
+    }
+    // Serial console takes input too, so kernel could be used under `-display none`
+    let com1 = uart::open(ComPort::Com1, uart::Config::default())
+        .map_err(|err| warn!("COM1 driver is not available, serial console is output only: {:?}", err))
+        .ok();
+    // From now on code below runs as the boot thread, so async executor is just one of the threads
+    thread::init(thread::DEFAULT_TIME_SLICE);
+
+    // Stop right away, so gdb could attach & set breakpoints before anything else runs
+    #[cfg(feature = "gdb-stub")]
+    {
+        radius_os::gdb::init();
+        radius_os::gdb::breakpoint();
+    }
     
     #[cfg(test)]
     test_main();
 
-    let mut executor = Executor::new();
-    executor.spawn(Task::new(print_keypress()));
+    if cfg!(feature = "smp-executor") {
+        let executor = MultiCoreExecutor::new();
+        info!("Async tasks run on {} CPUs", executor.cpu_count());
+        executor.spawn(print_keypress());
+        if let Some(com1) = com1 {
+            executor.spawn(uart::echo_input(com1));
+        }
+        executor.run();
+    }
+
+    let policy = if cfg!(feature = "sched-priority") {
+        PolicyKind::FixedPriority
+    } else if cfg!(feature = "sched-edf") {
+        PolicyKind::EarliestDeadline
+    } else {
+        PolicyKind::RoundRobin
+    };
+
+    let mut executor = Executor::with_policy(policy.create());
+    executor.spawn(Task::new(print_keypress()).with_name("keypress"));
+    if let Some(com1) = com1 {
+        executor.spawn(Task::new(uart::echo_input(com1)).with_name("serial"));
+    }
     executor.run();
 
     // ----------- Anything below is unreachable
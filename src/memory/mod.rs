@@ -2,12 +2,22 @@ use bootloader::bootinfo::{
     MemoryMap,
     MemoryRegionType
 };
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
 use x86_64::{
-    registers::control::Cr3,
+    registers::control::{ Cr3, Cr3Flags },
     structures::paging::{
+        mapper::MapToError,
         FrameAllocator,
+        FrameDeallocator,
+        Mapper,
         OffsetPageTable,
+        Page,
         PageTable,
+        PageTableFlags,
         PhysFrame,
         Size4KiB,
     },
@@ -15,10 +25,33 @@ use x86_64::{
     VirtAddr
 };
 
+/// Start of the virtual region where device registers (MMIO) are mapped to
+pub const MMIO_START: u64 = 0x5555_5555_0000;
+/// Start of the virtual region where kernel stacks are allocated
+pub const STACKS_START: u64 = 0x6666_6666_0000;
+/// Region of address space that belongs to user programs, private to every address space
+///
+/// It takes the last 32 level 4 entries of the lower half, which kernel never uses
+pub const USER_START: u64 = 0x7000_0000_0000;
+pub const USER_END:   u64 = 0x8000_0000_0000;
+
+// Offset at which bootloader mapped the complete physical memory
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+// Level 4 table kernel booted with
+static KERNEL_LEVEL_4_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+// Next free virtual address in MMIO region
+static MMIO_NEXT: AtomicU64 = AtomicU64::new(MMIO_START);
+// Next free virtual address in stacks region
+static STACKS_NEXT: AtomicU64 = AtomicU64::new(STACKS_START);
+
 /// FrameAllocator that returns usable frames from the bootloader's memory map
+///
+/// Frames that are given back are handed out again before any new one is taken from the map
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
-    next:       usize
+    next:       usize,
+    // Given back frames, every one keeps physical address of the next one in its first 8 bytes
+    free:       Option<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -31,7 +64,8 @@ impl BootInfoFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
         BootInfoFrameAllocator {
             memory_map,
-            next: 0
+            next: 0,
+            free: None,
         }
     }
 
@@ -54,8 +88,21 @@ impl BootInfoFrameAllocator {
     }
 }
 
+// Free list end, frame at the last address is never usable as there is no memory past it
+const FREE_LIST_END: u64 = u64::MAX;
+
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free {
+            // Safe because frame is on the free list, so nothing else uses it
+            let next  = unsafe { *phys_to_virt(frame.start_address()).as_ptr::<u64>() };
+            self.free = match next {
+                FREE_LIST_END => None,
+                next          => Some(PhysFrame::containing_address(PhysAddr::new(next))),
+            };
+            return Some(frame);
+        }
+
         let frame = self.usable_frames().nth(self.next);
 
         self.next += 1;
@@ -63,6 +110,15 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let next = self.free.map_or(FREE_LIST_END, |next| next.start_address().as_u64());
+
+        *phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = next;
+        self.free = Some(frame);
+    }
+}
+
 /// # Safety
 /// Initialises a new OffsetPageTable
 ///
@@ -71,6 +127,11 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is UB)
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+  PHYSICAL_MEMORY_OFFSET.try_init_once(|| physical_memory_offset)
+      .expect("memory::init should only be called once");
+  KERNEL_LEVEL_4_FRAME.try_init_once(|| Cr3::read().0)
+      .expect("memory::init should only be called once");
+
   let level_4_table = active_level_4_table(physical_memory_offset);
 
   OffsetPageTable::new(level_4_table, physical_memory_offset)
@@ -94,3 +155,295 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_prt // unsafe
 }
 
+
+/// Returns virtual address through which given physical address could be accessed
+///
+/// Relies on bootloader mapping the complete physical memory, thus would panic
+/// if called before `memory::init`
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET.try_get().expect("memory is not initialised");
+
+    *offset + addr.as_u64()
+}
+
+/// Translates given virtual address into physical one by walking active page tables
+///
+/// Returns None if address is not mapped or if `memory::init` has not been called yet.
+/// Unlike `OffsetPageTable` it only reads tables, so it is safe to use from anywhere,
+/// e.g. to check that address could be dereferenced without page fault
+pub fn translate_addr(addr: VirtAddr) -> Option<PhysAddr> {
+    walk(addr).map(|(phys_addr, _)| phys_addr)
+}
+
+/// Returns true if given address is mapped & could be accessed from user mode (ring 3)
+///
+/// Used to validate pointers passed by user programs, e.g. into syscalls
+pub fn is_user_accessible(addr: VirtAddr) -> bool {
+    match walk(addr) {
+        Some((_, flags)) => flags.contains(PageTableFlags::USER_ACCESSIBLE),
+        None             => false,
+    }
+}
+
+/// Walks active page tables, returns physical address & flags that are set on every level
+fn walk(addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+    let offset = *PHYSICAL_MEMORY_OFFSET.try_get().ok()?;
+
+    let (level_4_page_table, _) = Cr3::read();
+    let mut table_addr          = level_4_page_table.start_address();
+    let mut flags               = PageTableFlags::all();
+
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    for (level, &index) in indexes.iter().enumerate() {
+        let table: &PageTable = unsafe { &*(offset + table_addr.as_u64()).as_ptr() };
+        let entry             = &table[index];
+
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        // E.g. page is only accessible from user mode if all levels allow it
+        flags &= entry.flags();
+
+        // Level 3 & level 2 entries could map 1GiB & 2MiB pages directly
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let page_size: u64 = match level {
+                1 => 1 << 30,
+                2 => 1 << 21,
+                _ => return None,
+            };
+            return Some((entry.addr() + (addr.as_u64() & (page_size - 1)), flags));
+        }
+
+        table_addr = entry.addr();
+    }
+
+    Some((table_addr + u64::from(addr.page_offset()), flags))
+}
+
+/// Maps `size` bytes of device registers located at physical address `phys_addr`
+/// into MMIO region and returns virtual address of the first register
+///
+/// Pages are mapped as uncacheable, so every read & write goes straight to the device
+pub fn map_mmio(
+    phys_addr:       PhysAddr,
+    size:            u64,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys_addr);
+    let last_frame  = PhysFrame::<Size4KiB>::containing_address(phys_addr + (size - 1));
+    let frames      = PhysFrame::range_inclusive(first_frame, last_frame);
+
+    // Reserve virtual space for the whole range upfront, so concurrent callers never overlap
+    let region_size  = (last_frame.start_address() - first_frame.start_address()) + 4096;
+    let region_start = VirtAddr::new(MMIO_NEXT.fetch_add(region_size, Ordering::Relaxed));
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    for (i, frame) in frames.enumerate() {
+        let page = Page::<Size4KiB>::containing_address(region_start + i * 4096);
+
+        // Unsafe because mapping the same frame twice could create aliased mutable references,
+        // however device registers are not Rust objects, so it is fine here
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(region_start + (phys_addr - first_frame.start_address()))
+}
+
+/// Stack allocated by `alloc_stack`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    bottom: VirtAddr,
+    top:    VirtAddr,
+}
+
+impl StackBounds {
+    /// Lowest address of the stack
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// Address right past the end of the stack, i.e. initial stack pointer (stacks grow downwards)
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// Unmapped page right below the stack, any access to it is a page fault
+    pub fn guard_page(&self) -> Page<Size4KiB> {
+        Page::containing_address(self.bottom - 1u64)
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.bottom <= addr && addr < self.top
+    }
+}
+
+/// Allocates & maps stack of `pages` pages in stacks region
+///
+/// The page below the stack is left unmapped (guard page), so stack overflow is a page fault
+/// rather than silent corruption of whatever happens to lie below
+pub fn alloc_stack(
+    pages:           u64,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<StackBounds, MapToError<Size4KiB>> {
+    // Reserve virtual space for the guard page & the stack upfront, so concurrent callers never overlap
+    let guard_start = VirtAddr::new(STACKS_NEXT.fetch_add((pages + 1) * 4096, Ordering::Relaxed));
+    let bottom      = guard_start + 4096u64;
+    let top         = bottom + pages * 4096;
+
+    let first_page = Page::<Size4KiB>::containing_address(bottom);
+    let last_page  = Page::<Size4KiB>::containing_address(top - 1u64);
+
+    for page in Page::range_inclusive(first_page, last_page) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(StackBounds { bottom, top })
+}
+
+/// Set of page tables, i.e. what CR3 points to
+///
+/// Kernel part is shared by all address spaces, while user region (USER_START..USER_END) is private
+#[derive(Debug)]
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Creates address space with kernel mapped & empty user region
+    ///
+    /// Kernel level 4 entries are copied from the active table, so kernel mappings created later
+    /// are only shared if they fall under already existing entries
+    pub fn new(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<AddressSpace, MapToError<Size4KiB>> {
+        let level_4_frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        // Safe because frame has just been allocated & is only accessible through physical memory mapping
+        let table: &mut PageTable  = unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+        let active: &PageTable     = unsafe { &*phys_to_virt(Cr3::read().0.start_address()).as_ptr() };
+        let user_entries           = (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+        table.zero();
+        for (i, entry) in active.iter().enumerate() {
+            if !user_entries.contains(&i) {
+                table[i] = entry.clone();
+            }
+        }
+
+        Ok(AddressSpace { level_4_frame })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns mapper for this address space, it does not have to be active
+    ///
+    /// Pages could be filled through `phys_to_virt` of their frames before address space is activated
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let offset = *PHYSICAL_MEMORY_OFFSET.try_get().expect("memory is not initialised");
+
+        // Safe because tables of this address space are only reachable through &mut self
+        unsafe { OffsetPageTable::new(&mut *(offset + self.level_4_frame.start_address().as_u64()).as_mut_ptr(), offset) }
+    }
+
+    /// Frees the user region: every frame mapped there & page tables that map it, then level 4 table itself
+    ///
+    /// Frames mapped into user region are owned by the address space (whoever maps them through `mapper` vouches
+    /// for that, as mapping is unsafe), kernel part is shared and is left alone. Panics if address space is active
+    pub fn destroy(self, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+        assert_ne!(Cr3::read().0, self.level_4_frame, "active address space could not be destroyed");
+
+        let user_entries = (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+        let level_4      = table(self.level_4_frame);
+
+        // Safe because user region (tables included) is only reachable through this address space,
+        // which is not active & is consumed here
+        unsafe {
+            for level_4_entry in level_4.iter().take(user_entries.end).skip(user_entries.start) {
+                let level_3_frame = match present_table(level_4_entry.flags(), level_4_entry.addr()) {
+                    Some(frame) => frame,
+                    None        => continue,
+                };
+
+                for level_3_entry in table(level_3_frame).iter() {
+                    let level_2_frame = match present_table(level_3_entry.flags(), level_3_entry.addr()) {
+                        Some(frame) => frame,
+                        None        => continue,
+                    };
+
+                    for level_2_entry in table(level_2_frame).iter() {
+                        let level_1_frame = match present_table(level_2_entry.flags(), level_2_entry.addr()) {
+                            Some(frame) => frame,
+                            None        => continue,
+                        };
+
+                        for level_1_entry in table(level_1_frame).iter() {
+                            if level_1_entry.flags().contains(PageTableFlags::PRESENT) {
+                                frame_deallocator.deallocate_frame(PhysFrame::containing_address(level_1_entry.addr()));
+                            }
+                        }
+                        frame_deallocator.deallocate_frame(level_1_frame);
+                    }
+                    frame_deallocator.deallocate_frame(level_2_frame);
+                }
+                frame_deallocator.deallocate_frame(level_3_frame);
+            }
+            frame_deallocator.deallocate_frame(self.level_4_frame);
+        }
+    }
+
+    /// # Safety
+    /// Switches CPU to this address space
+    ///
+    /// Caller must guarantee that nothing in the user region of the previous address space
+    /// is referenced afterwards
+    pub unsafe fn activate(&self) {
+        activate_level_4_frame(self.level_4_frame);
+    }
+}
+
+/// Returns page table stored in given frame, through physical memory mapping
+fn table(frame: PhysFrame) -> &'static PageTable {
+    unsafe { &*phys_to_virt(frame.start_address()).as_ptr() }
+}
+
+/// Returns frame of the next level table entry points to, None if entry is not present
+///
+/// User region is only mapped with 4KiB pages (see elf::load), so huge pages are not expected there
+fn present_table(flags: PageTableFlags, addr: PhysAddr) -> Option<PhysFrame> {
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return None;
+    }
+    assert!(!flags.contains(PageTableFlags::HUGE_PAGE), "huge page in user region");
+
+    Some(PhysFrame::containing_address(addr))
+}
+
+/// Returns level 4 table kernel booted with, i.e. what CR3 of a CPU that only runs kernel points to
+pub fn kernel_level_4_frame() -> PhysFrame {
+    *KERNEL_LEVEL_4_FRAME.try_get().expect("memory is not initialised")
+}
+
+/// # Safety
+/// Switches CPU back to the address space kernel booted with
+///
+/// Same as `AddressSpace::activate`
+pub unsafe fn activate_kernel_address_space() {
+    activate_level_4_frame(kernel_level_4_frame());
+}
+
+/// # Safety
+/// Switches CPU to address space with given level 4 table, e.g. once its AddressSpace is not borrowed anymore
+///
+/// Same as `AddressSpace::activate`, on top of that address space must not be destroyed while it is active
+pub unsafe fn activate_level_4_frame(frame: PhysFrame) {
+    Cr3::write(frame, Cr3Flags::empty());
+}
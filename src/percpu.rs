@@ -0,0 +1,161 @@
+use core::{ arch::asm, mem::offset_of };
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::model_specific::{ GsBase, KernelGsBase },
+    structures::idt::InterruptStackFrame,
+    VirtAddr,
+};
+
+use crate::syscall::CpuSlots;
+
+/// Most CPUs the kernel runs on, further ones are left halted (see smp::init)
+pub const MAX_CPUS: usize = 16;
+
+/// Area GS base of every CPU points at while it runs kernel code
+///
+/// It identifies the CPU, per-CPU variables (see percpu!) are arrays indexed by it. Besides that it only holds
+/// what is addressed relative to GS base directly, without the index
+#[repr(C)]
+struct CpuArea {
+    // Must stay the first field, current_cpu() reads it at gs:0
+    index:   usize,
+    syscall: CpuSlots,
+}
+
+/// Offset of syscall slots from GS base, see syscall
+pub(crate) const SYSCALL_SLOTS_OFFSET: usize = offset_of!(CpuArea, syscall);
+
+static AREAS: [CpuArea; MAX_CPUS] = {
+    let mut areas = [const { CpuArea { index: 0, syscall: CpuSlots::new() } }; MAX_CPUS];
+
+    let mut index = 0;
+    while index < MAX_CPUS {
+        areas[index].index = index;
+        index += 1;
+    }
+    areas
+};
+
+/// Declares per-CPU statics, every CPU gets its own copy of the value
+///
+/// ```ignore
+/// percpu! {
+///     static TICKS: Cell<u64> = Cell::new(0);
+/// }
+///
+/// TICKS.with(|ticks| ticks.set(ticks.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::PerCpu<$ty> = {
+                // Initialiser is repeated for every CPU, so it has to be constant (& it is only ever copied)
+                #[allow(clippy::declare_interior_mutable_const)]
+                const INIT: $ty = $init;
+                $crate::percpu::PerCpu::new([INIT; $crate::percpu::MAX_CPUS])
+            };
+        )+
+    };
+}
+
+/// Value with a separate copy for every CPU, declared by percpu!
+pub struct PerCpu<T> {
+    slots: [T; MAX_CPUS],
+}
+
+// Safe because slot of a CPU is only handed out to code running on that CPU (see with), unless T is Sync
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(slots: [T; MAX_CPUS]) -> PerCpu<T> {
+        PerCpu { slots }
+    }
+
+    /// Runs `f` with the calling CPU's copy
+    ///
+    /// Interrupts are disabled meanwhile, so neither an interrupt handler nor another thread (scheduled on
+    /// timer interrupt) could get to the same copy halfway through
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        without_interrupts(|| f(&self.slots[current_cpu()]))
+    }
+}
+
+impl<T: Sync> PerCpu<T> {
+    /// Returns the calling CPU's copy
+    ///
+    /// It is fine to keep the reference across preemption, but once the thread moves to another CPU
+    /// it is no longer that CPU's copy
+    pub fn get(&self) -> &T {
+        &self.slots[current_cpu()]
+    }
+
+    /// Returns copy of given CPU
+    pub fn for_cpu(&self, cpu: usize) -> &T {
+        &self.slots[cpu]
+    }
+}
+
+/// Points GS base of the calling CPU at its per-CPU area, has to be done before anything per-CPU is used
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that `index` is not used by any other CPU
+pub unsafe fn init(index: usize) {
+    assert!(index < MAX_CPUS, "CPU index {} is beyond MAX_CPUS", index);
+
+    GsBase::write(VirtAddr::from_ptr(&AREAS[index]));
+    // User mode gets it by swapgs, see KernelGs
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// Syscall slots of given CPU
+pub(crate) fn syscall_slots(cpu: usize) -> &'static CpuSlots {
+    &AREAS[cpu].syscall
+}
+
+/// Whether GS base of the calling CPU already points at its per-CPU area (see init)
+pub fn is_initialised() -> bool {
+    GsBase::read() != VirtAddr::zero()
+}
+
+/// Index of the calling CPU, boot CPU is 0
+pub fn current_cpu() -> usize {
+    let index: usize;
+    // Safe because GS base points at CpuArea of the calling CPU (see init)
+    unsafe { asm!("mov {}, gs:[0]", out(reg) index, options(nostack, preserves_flags, readonly)) };
+    index
+}
+
+/// Makes GS base point at the per-CPU area in interrupt handler, switches it back when dropped
+///
+/// User mode has GS base of its own: kernel swaps it out on SYSCALL and back before returning to user mode
+/// (see syscall). Interrupts arriving in user mode have to do the same before they touch anything per-CPU.
+/// NMI & machine check could also arrive in kernel right before it returns to user mode, so they must not
+/// touch per-CPU data at all
+pub struct KernelGs {
+    swapped: bool,
+}
+
+impl KernelGs {
+    pub fn enter(stack_frame: &InterruptStackFrame) -> KernelGs {
+        // Requested privilege level of the interrupted code segment
+        let swapped = stack_frame.code_segment & 0b11 == 3;
+        if swapped {
+            // Safe because interrupted code ran in user mode, thus GS base is the user one
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+
+        KernelGs { swapped }
+    }
+}
+
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.swapped {
+            // Safe because handler returns to user mode right after
+            unsafe { asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use core::mem;
+
+use crate::{
+    serial_uart::SERIAL1,
+    spinlock::IrqSpinLock,
+    uart::{ self, ComPort },
+};
+
+/// Maximum number of files process could have open at once
+pub const MAX_FDS: usize = 16;
+
+pub const STDIN:  usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// Receives console output in place of the serial port, see set_console_sink
+///
+/// Sink is called from system call handlers with no lock held, it must not block
+pub trait ConsoleSink: Sync {
+    fn write(&self, bytes: &[u8]);
+}
+
+static CONSOLE_SINK: IrqSpinLock<Option<&'static dyn ConsoleSink>> = IrqSpinLock::named("fd::CONSOLE_SINK", None);
+
+/// Sends everything written to console into `sink` (e.g. to capture output of user programs),
+/// None sends it to the serial port again. Returns sink that has been set before
+pub fn set_console_sink(sink: Option<&'static dyn ConsoleSink>) -> Option<&'static dyn ConsoleSink> {
+    mem::replace(&mut *CONSOLE_SINK.lock(), sink)
+}
+
+/// Something file descriptor refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum File {
+    /// Serial port (COM1) or console sink if one is set, where output of user programs goes
+    Console,
+}
+
+impl File {
+    /// Writes bytes into the file, returns how many of them have been written
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        match self {
+            File::Console => {
+                let sink = *CONSOLE_SINK.lock();
+                if let Some(sink) = sink {
+                    sink.write(bytes);
+                    return bytes.len();
+                }
+
+                // Same port serial_print! goes to, see serial_uart::_print
+                match uart::get(ComPort::Com1) {
+                    Some(com1) => com1.write_polled(bytes),
+                    None       => {
+                        let mut serial = SERIAL1.lock();
+                        for &byte in bytes {
+                            serial.send(byte);
+                        }
+                    }
+                }
+                bytes.len()
+            }
+        }
+    }
+}
+
+/// Files open by process, indexed by file descriptor
+#[derive(Debug, Clone)]
+pub struct FdTable {
+    files: [Option<File>; MAX_FDS],
+}
+
+impl FdTable {
+    /// Creates table with stdin, stdout & stderr open on console
+    pub const fn new() -> FdTable {
+        let mut files = [None; MAX_FDS];
+        files[STDIN]  = Some(File::Console);
+        files[STDOUT] = Some(File::Console);
+        files[STDERR] = Some(File::Console);
+
+        FdTable { files }
+    }
+
+    pub fn get(&self, fd: usize) -> Option<File> {
+        self.files.get(fd).copied().flatten()
+    }
+
+    /// Opens file at the lowest free descriptor, returns None if table is full
+    pub fn open(&mut self, file: File) -> Option<usize> {
+        let fd = self.files.iter().position(|slot| slot.is_none())?;
+
+        self.files[fd] = Some(file);
+        Some(fd)
+    }
+
+    /// Closes descriptor, returns file it referred to
+    pub fn close(&mut self, fd: usize) -> Option<File> {
+        self.files.get_mut(fd)?.take()
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> FdTable {
+        FdTable::new()
+    }
+}
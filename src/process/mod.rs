@@ -0,0 +1,308 @@
+pub mod fd;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{ String, ToString },
+    vec::Vec,
+};
+use core::{
+    fmt,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+use x86_64::{
+    structures::paging::{ mapper::MapToError, FrameAllocator, FrameDeallocator, Size4KiB },
+    VirtAddr,
+};
+
+use crate::{
+    elf::{ self, ElfError },
+    memory::{ self, AddressSpace },
+    spinlock::IrqSpinLock,
+    syscall,
+};
+use self::fd::{ FdTable, File };
+
+// Syscalls take it with interrupts disabled, so it must never be held by code that could be preempted meanwhile
+static PROCESSES: IrqSpinLock<BTreeMap<Pid, Process>> = IrqSpinLock::named("process::PROCESSES", BTreeMap::new());
+// Process that is in user mode right now, Pid::KERNEL if none
+static CURRENT: AtomicU64 = AtomicU64::new(Pid::KERNEL.0);
+// Files of the kernel itself, used by user code that runs outside of any process
+static KERNEL_FDS: FdTable = FdTable::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// Kernel is not a process, but it is the parent of every process it spawns
+    pub const KERNEL: Pid = Pid(0);
+
+    fn new() -> Pid {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// Ready to run or running
+    Running,
+    /// Waiting for a child to exit
+    Blocked,
+    /// Exited, but its exit status has not been collected by parent yet
+    Zombie,
+}
+
+#[derive(Debug)]
+pub enum ProcessError {
+    Load(ElfError),
+    Map(MapToError<Size4KiB>),
+    NoSuchProcess(Pid),
+    /// Only parent could wait for a process
+    NotChild(Pid),
+    /// Process is blocked or has already exited
+    NotRunnable(Pid),
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(err: ElfError) -> ProcessError {
+        ProcessError::Load(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for ProcessError {
+    fn from(err: MapToError<Size4KiB>) -> ProcessError {
+        ProcessError::Map(err)
+    }
+}
+
+/// User program together with memory & resources it owns
+pub struct Process {
+    pid:           Pid,
+    parent:        Pid,
+    name:          String,
+    address_space: AddressSpace,
+    fds:           FdTable,
+    state:         ProcessState,
+    exit_status:   Option<i32>,
+    entry:         VirtAddr,
+    stack_pointer: VirtAddr,
+}
+
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn parent(&self) -> Pid {
+        self.parent
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    pub fn fds(&self) -> &FdTable {
+        &self.fds
+    }
+
+    pub fn fds_mut(&mut self) -> &mut FdTable {
+        &mut self.fds
+    }
+}
+
+/// Snapshot of process table entry
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid:         Pid,
+    pub parent:      Pid,
+    pub name:        String,
+    pub state:       ProcessState,
+    pub exit_status: Option<i32>,
+}
+
+/// Creates process from ELF image, `args[0]` is its name
+///
+/// Process becomes a child of the current process (or of the kernel)
+pub fn spawn(
+    image:           &[u8],
+    args:            &[&str],
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<Pid, ProcessError> {
+    let mut address_space = AddressSpace::new(frame_allocator)?;
+    let program           = elf::load(image, args, &mut address_space, frame_allocator)?;
+
+    let process = Process {
+        pid:           Pid::new(),
+        parent:        current_pid(),
+        name:          args.first().map_or_else(String::new, |name| name.to_string()),
+        address_space,
+        fds:           FdTable::new(),
+        state:         ProcessState::Running,
+        exit_status:   None,
+        entry:         program.entry,
+        stack_pointer: program.stack_pointer,
+    };
+
+    let pid = process.pid;
+    PROCESSES.lock().insert(pid, process);
+    Ok(pid)
+}
+
+/// Runs process until it exits
+///
+/// Until there is a scheduler, processes only run when they are explicitly run (or waited for)
+pub fn run(pid: Pid) -> Result<(), ProcessError> {
+    let parent = current_pid();
+
+    let (level_4_frame, entry, stack_pointer) = {
+        let mut table = PROCESSES.lock();
+
+        let process = table.get(&pid).ok_or(ProcessError::NoSuchProcess(pid))?;
+        if process.state != ProcessState::Running {
+            return Err(ProcessError::NotRunnable(pid));
+        }
+        let start = (process.address_space.level_4_frame(), process.entry, process.stack_pointer);
+
+        if let Some(parent) = table.get_mut(&parent) {
+            parent.state = ProcessState::Blocked;
+        }
+        start
+    };
+
+    // Safe because kernel is mapped in every address space & nothing of the parent's user region is used below.
+    // Running process is not reaped, so its address space outlives this call
+    unsafe { memory::activate_level_4_frame(level_4_frame) };
+
+    // Lock must not be held while in user mode, as syscalls need it
+    CURRENT.store(pid.0, Ordering::Relaxed);
+    let exit_code = unsafe { syscall::enter_user_mode(entry, stack_pointer) };
+    CURRENT.store(parent.0, Ordering::Relaxed);
+
+    let parent_level_4_frame = {
+        let mut table = PROCESSES.lock();
+
+        // Program may leave user mode without going through exit(), e.g. if it was started with raw SYS_EXIT
+        if let Some(process) = table.get_mut(&pid) {
+            if process.state != ProcessState::Zombie {
+                process.state       = ProcessState::Zombie;
+                process.exit_status = Some(exit_code as i32);
+            }
+        }
+
+        table.get_mut(&parent).map(|parent| {
+            parent.state = ProcessState::Running;
+            parent.address_space.level_4_frame()
+        })
+    };
+
+    // Safe because parent is blocked in this call, so it is not reaped meanwhile
+    match parent_level_4_frame {
+        Some(frame) => unsafe { memory::activate_level_4_frame(frame) },
+        None        => unsafe { memory::activate_kernel_address_space() },
+    }
+    Ok(())
+}
+
+/// Terminates current process with given status, it stays a zombie until its parent waits for it
+///
+/// Called by SYS_EXIT, must only be called from syscall handler
+pub fn exit(status: i32) -> ! {
+    let pid = current_pid();
+
+    if let Some(process) = PROCESSES.lock().get_mut(&pid) {
+        process.state       = ProcessState::Zombie;
+        process.exit_status = Some(status);
+    }
+
+    // Safe because exit could only be called by user code, which runs within enter_user_mode()
+    unsafe { syscall::exit_user_mode(status as u64) }
+}
+
+/// Waits for child process to exit, reaps it and returns its exit status
+///
+/// Frames of the reaped process (its address space) are given back to `frame_deallocator`.
+/// Until there is a scheduler, waiting for running child runs it to completion
+pub fn wait(pid: Pid, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) -> Result<i32, ProcessError> {
+    loop {
+        let zombie = {
+            let mut table = PROCESSES.lock();
+
+            let process = table.get(&pid).ok_or(ProcessError::NoSuchProcess(pid))?;
+            if process.parent != current_pid() {
+                return Err(ProcessError::NotChild(pid));
+            }
+
+            match process.state {
+                ProcessState::Zombie => table.remove(&pid),
+                _                    => None,
+            }
+        };
+
+        if let Some(process) = zombie {
+            // Kernel (or parent's) address space is active again by now, see run()
+            process.address_space.destroy(frame_deallocator);
+            return Ok(process.exit_status.expect("zombie process without exit status"));
+        }
+
+        run(pid)?;
+    }
+}
+
+/// Returns PID of the process that is in user mode right now, Pid::KERNEL if none
+pub fn current_pid() -> Pid {
+    Pid(CURRENT.load(Ordering::Relaxed))
+}
+
+/// Returns file open at `fd` by the current process
+pub fn file(fd: usize) -> Option<File> {
+    let pid = current_pid();
+    if pid == Pid::KERNEL {
+        return KERNEL_FDS.get(fd);
+    }
+
+    PROCESSES.lock().get(&pid)?.fds.get(fd)
+}
+
+/// Runs `f` with process, None if there is no such process
+pub fn with_process<R>(pid: Pid, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+pub fn state(pid: Pid) -> Option<ProcessState> {
+    with_process(pid, |process| process.state)
+}
+
+/// Returns snapshot of the process table, ordered by PID
+pub fn list() -> Vec<ProcessInfo> {
+    PROCESSES.lock()
+        .values()
+        .map(|process| ProcessInfo {
+            pid:         process.pid,
+            parent:      process.parent,
+            name:        process.name.clone(),
+            state:       process.state,
+            exit_status: process.exit_status,
+        })
+        .collect()
+}
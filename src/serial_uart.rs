@@ -1,15 +1,30 @@
 use core::fmt;
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::SerialPort;
 
+use crate::{
+    spinlock::IrqSpinLock,
+    uart::{ self, ComPort },
+};
+
+// I/O port of the first UART device (COM1)
+const COM1_PORT: u16 = 0x3F8;
+
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) }; // I/O mapped port of UART device - 0x3f8; looks like it is a standard port for serial interface (although UART utilises multiple ports, here it is enough to only specify one, then SerialPort would figure out the rest)
+    pub static ref SERIAL1: IrqSpinLock<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1_PORT) }; // I/O mapped port of UART device - 0x3f8; looks like it is a standard port for serial interface (although UART utilises multiple ports, here it is enough to only specify one, then SerialPort would figure out the rest)
+
+        serial_port.init();
+        IrqSpinLock::named("serial_uart::SERIAL1", serial_port)
+    };
+
+    /// COM2 - reserved for debugger (see gdb module), so debugging session does not mix with kernel output
+    pub static ref SERIAL2: IrqSpinLock<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(0x2F8) }; // I/O mapped port of the second UART device
 
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinLock::named("serial_uart::SERIAL2", serial_port)
     };
 }
 
@@ -17,16 +32,25 @@ lazy_static! {
 pub fn _print(args: fmt::Arguments) {
     // Only to be used by macros!
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-
-    // This is required to ensure that we lock WRITER only in interrupt-free cases,
-    // thus avoiding dead-locks
-    interrupts::without_interrupts(|| {
-        // SerialPort already implements fmt::Write, so we don't need to do it here like wee did for
-        // custom VGA buffer
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
+
+    // Both keep interrupts disabled while locked, so printing from interrupt handler could not dead-lock.
+    // COM1 is printed to through its driver once it is open (see uart), so output does not mix with what driver sends.
+    // SerialPort already implements fmt::Write, so we don't need to do it here like wee did for
+    // custom VGA buffer
+    match uart::get(ComPort::Com1) {
+        Some(com1) => com1.polled().write_fmt(args),
+        None       => SERIAL1.lock().write_fmt(args),
+    }
+    .expect("Printing to serial failed");
+}
+
+/// Prints to COM1 without taking any lock, for handlers that may interrupt a lock holder (e.g. NMI)
+///
+/// Output may interleave with whatever else is printed meanwhile
+pub fn print_unlocked(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    // Safe because port is only written to, polling its line status before every byte
+    let mut port = unsafe { SerialPort::new(COM1_PORT) };
+    let _ = port.write_fmt(args);
 }
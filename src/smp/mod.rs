@@ -0,0 +1,277 @@
+mod trampoline;
+
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    vec::Vec,
+};
+use bootloader::bootinfo::{ MemoryMap, MemoryRegionType };
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{
+        mapper::MapToError,
+        FrameAllocator,
+        Mapper,
+        PhysFrame,
+        Size4KiB,
+    },
+    PhysAddr,
+};
+
+use crate::{
+    acpi::{ self, AcpiError },
+    apic,
+    gdt,
+    interrupts::{ self as idt, WAKEUP_VECTOR },
+    memory,
+    percpu::{ self, MAX_CPUS },
+    spinlock::IrqSpinLock,
+    syscall,
+    time,
+    warn,
+};
+use self::trampoline::Trampoline;
+
+/// Size of the stack every application processor gets (in pages)
+pub const AP_STACK_PAGES: u64 = 8;
+
+// INIT-SIPI-SIPI sequence timings (Intel SDM, MP initialisation): 10 ms after INIT, 200 us after each Startup IPI
+const INIT_DELAY_NS:    u64 = 10_000_000;
+const STARTUP_DELAY_NS: u64 = 200_000;
+// How long CPU has to report in before it is given up on
+const START_TIMEOUT_NS: u64 = 100_000_000;
+
+/// Work handed to application processor, see run_on()
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug)]
+pub enum SmpError {
+    Acpi(AcpiError),
+    Mapping(MapToError<Size4KiB>),
+    AlreadyInitialised,
+    NotInitialised,
+    /// No free page below 1 MiB to start application processors from
+    NoTrampolineFrame,
+    /// Page trampoline has to be identity mapped at is mapped to something else
+    TrampolinePageInUse,
+    /// Application processors load CR3 in 32-bit code
+    PageTablesAbove4GiB,
+    NoSuchCpu(usize),
+    /// CPU has not come online (see init)
+    CpuOffline(usize),
+    /// Boot CPU does not run jobs, it runs the rest of the kernel
+    BootCpu,
+}
+
+/// CPU known to the kernel, CPU index is its position in CPUS
+struct Cpu {
+    apic_id: u8,
+    online:  AtomicBool,
+    // Run queue of the CPU
+    jobs:    IrqSpinLock<VecDeque<Job>>,
+}
+
+impl Cpu {
+    fn new(apic_id: u8) -> Cpu {
+        Cpu {
+            apic_id,
+            online: AtomicBool::new(false),
+            jobs:   IrqSpinLock::named("smp::Cpu::jobs", VecDeque::new()),
+        }
+    }
+}
+
+/// Snapshot of CPU state, see cpus()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuInfo {
+    pub index:   usize,
+    pub apic_id: u8,
+    pub online:  bool,
+}
+
+// Boot CPU is always the first one
+static CPUS: OnceCell<Vec<Cpu>> = OnceCell::uninit();
+
+/// Starts application processors listed in ACPI MADT, returns number of CPUs online (boot CPU included)
+///
+/// Every application processor gets its own stack, per-CPU area, GDT, TSS (with IST stacks), kernel stack for user mode,
+/// loads shared IDT & enables SYSCALL. Then it
+/// enters its scheduler loop: runs jobs given to it by run_on() & halts until the next one arrives.
+/// Interrupts have to be enabled, as delays of the startup sequence are measured with the timer
+pub fn init(
+    memory_map:      &'static MemoryMap,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<usize, SmpError> {
+    if CPUS.is_initialized() {
+        return Err(SmpError::AlreadyInitialised);
+    }
+
+    acpi::init().map_err(SmpError::Acpi)?;
+    let madt = acpi::madt().map_err(SmpError::Acpi)?;
+    apic::init(madt.local_apic_address, mapper, frame_allocator).map_err(SmpError::Mapping)?;
+
+    let boot_apic_id = apic::id();
+    let boot_cpu     = Cpu::new(boot_apic_id);
+    boot_cpu.online.store(true, Ordering::Release);
+
+    let application_cpus = madt.local_apics
+        .iter()
+        .filter(|local_apic| local_apic.enabled && local_apic.apic_id != boot_apic_id)
+        .map(|local_apic| Cpu::new(local_apic.apic_id));
+    // CPUs beyond MAX_CPUS have no per-CPU area, so they are never started
+    let cpus = CPUS.get_or_init(|| core::iter::once(boot_cpu).chain(application_cpus).take(MAX_CPUS).collect());
+
+    if cpus.len() == 1 {
+        return Ok(1);
+    }
+
+    let frame      = find_trampoline_frame(memory_map).ok_or(SmpError::NoTrampolineFrame)?;
+    let trampoline = Trampoline::install(frame, ap_entry, mapper, frame_allocator)?;
+
+    // CPUs are started one by one, as they share trampoline fields
+    for (index, cpu) in cpus.iter().enumerate().skip(1) {
+        let stack = memory::alloc_stack(AP_STACK_PAGES, mapper, frame_allocator).map_err(SmpError::Mapping)?;
+        gdt::init_cpu_ist_stacks(index, mapper, frame_allocator).map_err(SmpError::Mapping)?;
+        syscall::init_cpu_stack(index, mapper, frame_allocator).map_err(SmpError::Mapping)?;
+        trampoline.prepare(index, stack.top());
+
+        if !start_cpu(cpu, trampoline.vector()) {
+            // CPU may still wake up later & read trampoline, so it is kept as is & nothing else is started
+            warn!("CPU {} (APIC ID {}) has not started, further CPUs are not started", index, cpu.apic_id);
+            return Ok(cpu_count());
+        }
+    }
+
+    trampoline.remove(mapper);
+    Ok(cpu_count())
+}
+
+/// Number of CPUs online, 1 before init()
+pub fn cpu_count() -> usize {
+    match CPUS.try_get() {
+        Ok(cpus) => cpus.iter().filter(|cpu| cpu.online.load(Ordering::Acquire)).count(),
+        Err(_)   => 1,
+    }
+}
+
+/// Index of the calling CPU, boot CPU is 0
+pub fn current_cpu() -> usize {
+    percpu::current_cpu()
+}
+
+/// Returns snapshot of CPUs known to the kernel, ordered by index
+pub fn cpus() -> Vec<CpuInfo> {
+    CPUS.try_get()
+        .map(|cpus| {
+            cpus.iter()
+                .enumerate()
+                .map(|(index, cpu)| CpuInfo {
+                    index,
+                    apic_id: cpu.apic_id,
+                    online:  cpu.online.load(Ordering::Acquire),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Queues `job` to be run by given application processor, jobs of a CPU run one by one in the order they were queued
+pub fn run_on(cpu: usize, job: impl FnOnce() + Send + 'static) -> Result<(), SmpError> {
+    let cpus   = CPUS.try_get().map_err(|_| SmpError::NotInitialised)?;
+    let target = cpus.get(cpu).ok_or(SmpError::NoSuchCpu(cpu))?;
+
+    if cpu == 0 {
+        return Err(SmpError::BootCpu);
+    }
+    if !target.online.load(Ordering::Acquire) {
+        return Err(SmpError::CpuOffline(cpu));
+    }
+
+    let job: Job = Box::new(job);
+    target.jobs.lock().push_back(job);
+
+    apic::send_ipi(target.apic_id, WAKEUP_VECTOR);
+    Ok(())
+}
+
+/// Wakes given CPU up with an inter-processor interrupt, e.g. so it notices work queued for it while it halts
+///
+/// Does nothing for CPUs that are not online
+pub fn wake_cpu(cpu: usize) {
+    let target = CPUS.try_get().ok().and_then(|cpus| cpus.get(cpu));
+
+    if let Some(target) = target.filter(|target| target.online.load(Ordering::Acquire)) {
+        apic::send_ipi(target.apic_id, WAKEUP_VECTOR);
+    }
+}
+
+/// Sends INIT-SIPI-SIPI sequence, returns whether CPU has reported in
+fn start_cpu(cpu: &Cpu, vector: u8) -> bool {
+    apic::send_init(cpu.apic_id);
+    time::busy_wait_ns(INIT_DELAY_NS);
+
+    // Second Startup IPI is only needed if CPU has missed the first one
+    for _ in 0..2 {
+        apic::send_startup(cpu.apic_id, vector);
+        time::busy_wait_ns(STARTUP_DELAY_NS);
+
+        if cpu.online.load(Ordering::Acquire) {
+            return true;
+        }
+    }
+
+    let deadline = time::uptime_ns() + START_TIMEOUT_NS;
+    while time::uptime_ns() < deadline {
+        if cpu.online.load(Ordering::Acquire) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// Startup IPI could only point at a page of real mode memory. Bootloader is done once kernel runs,
+/// so a page it has occupied is free to use
+fn find_trampoline_frame(memory_map: &MemoryMap) -> Option<PhysFrame> {
+    memory_map
+        .iter()
+        .filter(|region| region.region_type == MemoryRegionType::Bootloader)
+        .flat_map(|region| (region.range.start_addr()..region.range.end_addr()).step_by(4096))
+        // Page 0 holds real mode interrupt table (& Startup IPI vector 0 is reserved)
+        .find(|&addr| addr >= 0x1000 && addr + 4096 <= 0xa_0000)
+        .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+}
+
+/// Where application processor lands once trampoline has switched it to long mode
+extern "C" fn ap_entry(index: usize) -> ! {
+    // Safe because every application processor is started with an index of its own
+    unsafe { gdt::init_cpu(index) };
+    syscall::init_cpu();
+    idt::init_ap();
+    apic::enable();
+
+    let cpu = &CPUS.try_get().expect("application processor started before CPUs are known")[index];
+    cpu.online.store(true, Ordering::Release);
+
+    run_jobs(cpu)
+}
+
+/// Scheduler loop of application processor
+fn run_jobs(cpu: &Cpu) -> ! {
+    loop {
+        // Queue is checked with interrupts disabled, so wakeup IPI could not slip in between check & halt
+        interrupts::disable();
+        let job = cpu.jobs.lock().pop_front();
+
+        match job {
+            Some(job) => {
+                interrupts::enable();
+                job();
+            }
+            None      => interrupts::enable_and_hlt(),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use core::{
+    arch::global_asm,
+    ptr::{ self, addr_of },
+};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator,
+        Mapper,
+        Page,
+        PageTableFlags,
+        PhysFrame,
+        Size4KiB,
+    },
+    PhysAddr,
+    VirtAddr,
+};
+
+use crate::memory;
+use super::SmpError;
+
+// Startup IPI starts CPU in real mode at CS = page << 8, IP = 0. Code below switches it straight into
+// long mode (no protected mode in between) with kernel page tables & calls smp::ap_entry on its own stack.
+//
+// Code is copied to a page below 1 MiB, so it only addresses its data relative to itself: through DS = CS
+// in real mode & RIP in long mode. Fields marked below are filled in by Trampoline before CPU is started
+global_asm!(r#"
+.pushsection .text.ap_trampoline, "ax"
+.balign 16
+.code16
+.global ap_trampoline_start
+ap_trampoline_start:
+    cli
+    cld
+    mov ax, cs
+    mov ds, ax
+
+    // Physical Address Extension is required by long mode
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    mov eax, dword ptr [trampoline_cr3_offset]
+    mov cr3, eax
+
+    // EFER: long mode & no-execute bit (kernel page tables use it)
+    mov ecx, 0xc0000080
+    rdmsr
+    or eax, (1 << 8) | (1 << 11)
+    wrmsr
+
+    lgdt [trampoline_gdtr_offset]
+
+    // Protected mode, write protection (as boot CPU has it) & paging at once
+    mov eax, cr0
+    or eax, 0x80010001
+    mov cr0, eax
+
+    // jmp 0x08:<linear address of ap_trampoline_long_mode>, written as bytes to get 32-bit offset in 16-bit code
+    .byte 0x66, 0xea
+.global ap_trampoline_target
+ap_trampoline_target:
+    .long 0
+    .word 0x08
+
+.code64
+.global ap_trampoline_long_mode
+ap_trampoline_long_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    xor eax, eax
+    mov fs, ax
+    mov gs, ax
+
+    mov rsp, [rip + ap_trampoline_stack]
+    mov rdi, [rip + ap_trampoline_cpu]
+    mov rax, [rip + ap_trampoline_entry]
+    call rax
+    ud2
+
+// Temporary GDT: null, 64-bit code & data segments, kernel GDT is loaded by ap_entry
+.balign 8
+.global ap_trampoline_gdt
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_trampoline_gdt_end:
+
+// Limit & base (linear address of ap_trampoline_gdt)
+.balign 8
+.global ap_trampoline_gdtr
+ap_trampoline_gdtr:
+    .word ap_trampoline_gdt_end - ap_trampoline_gdt - 1
+    .long 0
+
+.balign 8
+.global ap_trampoline_cr3
+ap_trampoline_cr3:
+    .quad 0
+.global ap_trampoline_stack
+ap_trampoline_stack:
+    .quad 0
+.global ap_trampoline_cpu
+ap_trampoline_cpu:
+    .quad 0
+.global ap_trampoline_entry
+ap_trampoline_entry:
+    .quad 0
+
+.global ap_trampoline_end
+ap_trampoline_end:
+
+// Real mode addresses of fields (DS points at ap_trampoline_start)
+.set trampoline_cr3_offset,  ap_trampoline_cr3 - ap_trampoline_start
+.set trampoline_gdtr_offset, ap_trampoline_gdtr - ap_trampoline_start
+.popsection
+"#);
+
+extern "C" {
+    static ap_trampoline_start:     u8;
+    static ap_trampoline_end:       u8;
+    static ap_trampoline_target:    u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt:       u8;
+    static ap_trampoline_gdtr:      u8;
+    static ap_trampoline_cr3:       u8;
+    static ap_trampoline_stack:     u8;
+    static ap_trampoline_cpu:       u8;
+    static ap_trampoline_entry:     u8;
+}
+
+/// Real mode memory ends at 640 KiB, the rest below 1 MiB belongs to devices & BIOS
+const REAL_MODE_END: u64 = 0xa_0000;
+
+/// Copy of the startup code in a page Startup IPI could point at
+pub(super) struct Trampoline {
+    frame:       PhysFrame,
+    // Whether page has been identity mapped by install(), so it has to be unmapped by remove()
+    mapped_here: bool,
+}
+
+impl Trampoline {
+    /// Copies code into `frame` & identity maps it, so CPU keeps running from it once it turns paging on
+    pub(super) fn install(
+        frame:           PhysFrame,
+        entry:           extern "C" fn(usize) -> !,
+        mapper:          &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>
+    ) -> Result<Trampoline, SmpError> {
+        let start = frame.start_address().as_u64();
+        if start == 0 || start + 4096 > REAL_MODE_END {
+            return Err(SmpError::NoTrampolineFrame);
+        }
+
+        // CR3 is loaded while CPU still runs 32-bit code
+        let cr3 = memory::kernel_level_4_frame().start_address().as_u64();
+        if cr3 > u64::from(u32::MAX) {
+            return Err(SmpError::PageTablesAbove4GiB);
+        }
+
+        let page        = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let mapped_here = match memory::translate_addr(page.start_address()) {
+            // E.g. bootloader has identity mapped low memory already
+            Some(addr) if addr == frame.start_address() => false,
+            Some(_)                                     => return Err(SmpError::TrampolinePageInUse),
+            None                                        => {
+                let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+                // Safe because frame is not used by anything else & page is not mapped
+                unsafe { mapper.map_to(page, frame, flags, frame_allocator).map_err(SmpError::Mapping)?.flush() };
+                true
+            }
+        };
+
+        let trampoline = Trampoline { frame, mapped_here };
+        let size       = trampoline.offset(addr_of!(ap_trampoline_end));
+        assert!(size <= 4096, "AP trampoline does not fit into a page");
+
+        // Safe because code is copied into a frame it fits into & nothing else uses
+        unsafe {
+            ptr::copy_nonoverlapping(
+                addr_of!(ap_trampoline_start),
+                memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                size as usize,
+            );
+
+            trampoline.write(addr_of!(ap_trampoline_target), (start + trampoline.offset(addr_of!(ap_trampoline_long_mode))) as u32);
+            trampoline.write(addr_of!(ap_trampoline_gdtr).add(2), (start + trampoline.offset(addr_of!(ap_trampoline_gdt))) as u32);
+            trampoline.write(addr_of!(ap_trampoline_cr3), cr3);
+            trampoline.write(addr_of!(ap_trampoline_entry), entry as usize as u64);
+        }
+
+        Ok(trampoline)
+    }
+
+    /// Page number Startup IPI has to carry
+    pub(super) fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() >> 12) as u8
+    }
+
+    /// Sets what the next CPU started gets, must not be called before previous CPU has reported in
+    pub(super) fn prepare(&self, cpu: usize, stack_top: VirtAddr) {
+        // Safe because fields are within the copied code
+        unsafe {
+            self.write(addr_of!(ap_trampoline_stack), stack_top.as_u64());
+            self.write(addr_of!(ap_trampoline_cpu), cpu as u64);
+        }
+    }
+
+    /// Unmaps identity mapped page, once no CPU runs from it anymore
+    pub(super) fn remove(self, mapper: &mut impl Mapper<Size4KiB>) {
+        if !self.mapped_here {
+            return;
+        }
+
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(self.frame.start_address().as_u64()));
+        match mapper.unmap(page) {
+            Ok((_, flush)) => flush.flush(),
+            Err(err)       => panic!("AP trampoline page has been unmapped already: {:?}", err),
+        }
+    }
+
+    /// Offset of given trampoline symbol from its start
+    fn offset(&self, symbol: *const u8) -> u64 {
+        symbol as u64 - addr_of!(ap_trampoline_start) as u64
+    }
+
+    /// # Safety
+    /// Writes `value` into the copy of the trampoline, at given field
+    ///
+    /// This function is unsafe because the caller must guarantee that `field` points into trampoline code
+    unsafe fn write<T>(&self, field: *const u8, value: T) {
+        let addr = PhysAddr::new(self.frame.start_address().as_u64() + self.offset(field));
+
+        ptr::write_unaligned(memory::phys_to_virt(addr).as_mut_ptr::<T>(), value);
+    }
+}
@@ -0,0 +1,190 @@
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{ Deref, DerefMut },
+};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use spin::{ Mutex, MutexGuard };
+use x86_64::instructions::interrupts;
+
+#[cfg(feature = "lockdep")]
+use crate::lockdep;
+
+/// Spin lock that keeps interrupts disabled while held
+///
+/// Lock that is also taken by an interrupt handler must be one: otherwise handler that interrupted the holder
+/// on the same CPU spins forever. Holder is never preempted either, so others do not spin for a whole time slice.
+/// Interrupts are restored to what they were before lock() once the guard is dropped
+///
+/// With `lockdep` feature, recursive locking & locks taken in inconsistent order panic with a report (see lockdep)
+pub struct IrqSpinLock<T> {
+    inner: Mutex<T>,
+    // Lock class in lockdep reports, type name of the value if not given
+    name:  Option<&'static str>,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(value: T) -> IrqSpinLock<T> {
+        IrqSpinLock {
+            inner: Mutex::new(value),
+            name:  None,
+        }
+    }
+
+    /// Lock whose `name` shows up in lockdep reports. Locks sharing a name are one class for lock ordering
+    pub const fn named(name: &'static str, value: T) -> IrqSpinLock<T> {
+        IrqSpinLock {
+            inner: Mutex::new(value),
+            name:  Some(name),
+        }
+    }
+
+    /// Disables interrupts & spins until lock is free
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.id(), self.name(), Location::caller(), false);
+
+        IrqSpinLockGuard {
+            lock:  self,
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+
+    /// Locks if lock is free, interrupts are left as they are otherwise
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        match self.inner.try_lock() {
+            Some(guard) => {
+                #[cfg(feature = "lockdep")]
+                lockdep::acquire(self.id(), self.name(), Location::caller(), true);
+
+                Some(IrqSpinLockGuard {
+                    lock:  self,
+                    guard: ManuallyDrop::new(guard),
+                    interrupts_enabled,
+                })
+            }
+            None        => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn id(&self) -> usize {
+        self as *const IrqSpinLock<T> as usize
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn name(&self) -> &'static str {
+        self.name.unwrap_or_else(core::any::type_name::<T>)
+    }
+}
+
+impl<T: Default> Default for IrqSpinLock<T> {
+    fn default() -> IrqSpinLock<T> {
+        IrqSpinLock::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IrqSpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IrqSpinLock")
+            .field("name",  &self.name)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// Lock on IrqSpinLock, re-enables interrupts (if they were enabled) once dropped
+pub struct IrqSpinLockGuard<'a, T> {
+    lock:               &'a IrqSpinLock<T>,
+    guard:              ManuallyDrop<MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.id());
+        #[cfg(not(feature = "lockdep"))]
+        let _ = self.lock;
+
+        // Lock has to be released before interrupts are enabled, so it is dropped by hand
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+
+#[test_case]
+fn test_lock_disables_interrupts() {
+    let lock = IrqSpinLock::new(0);
+
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn test_lock_keeps_interrupts_disabled() {
+    let lock = IrqSpinLock::new(());
+
+    interrupts::without_interrupts(|| {
+        drop(lock.lock());
+        // Guard restores state lock() has found, i.e. disabled
+        assert!(!interrupts::are_enabled());
+    });
+}
+
+#[test_case]
+fn test_try_lock_fails_while_locked() {
+    let lock  = IrqSpinLock::new(());
+    let guard = lock.lock();
+
+    interrupts::enable();
+    assert!(lock.try_lock().is_none());
+    assert!(interrupts::are_enabled());
+
+    interrupts::disable();
+    drop(guard);
+    interrupts::enable();
+    assert!(lock.try_lock().is_some());
+}
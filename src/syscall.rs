@@ -0,0 +1,340 @@
+use core::{
+    arch::global_asm,
+    mem::offset_of,
+    sync::atomic::{
+        AtomicBool,
+        AtomicU64,
+        Ordering,
+    },
+};
+use x86_64::{
+    registers::{
+        model_specific::{ Efer, EferFlags, LStar, SFMask, Star },
+        rflags::RFlags,
+    },
+    structures::paging::{ mapper::MapToError, FrameAllocator, Mapper, Page, Size4KiB },
+    VirtAddr,
+};
+
+use crate::{
+    gdt,
+    memory,
+    percpu,
+    process,
+};
+
+// Syscall numbers, passed in rax. Arguments go in rdi, rsi, rdx, r10, r8 & r9, result is returned in rax
+pub const SYS_WRITE:  u64 = 0;
+pub const SYS_EXIT:   u64 = 1;
+pub const SYS_GETPID: u64 = 2;
+
+// Errors are returned as negative numbers (same values as Linux uses)
+pub const EBADF:  i64 = 9;
+pub const EFAULT: i64 = 14;
+pub const ENOSYS: i64 = 38;
+
+/// Size of the kernel stack used by syscalls & interrupts that arrive in user mode (in pages)
+pub const KERNEL_STACK_PAGES: u64 = 5;
+
+pub type SyscallHandler = fn(args: [u64; 6]) -> i64;
+
+/// Syscall handlers, indexed by syscall number
+static SYSCALL_TABLE: [SyscallHandler; 3] = [
+    sys_write,  // SYS_WRITE
+    sys_exit,   // SYS_EXIT
+    sys_getpid, // SYS_GETPID
+];
+
+/// Slots of syscall entry & user mode entry/exit stubs. They have no free register to find per-CPU statics with,
+/// so the slots live in the per-CPU area & stubs address them relative to GS base (see percpu)
+#[repr(C)]
+pub(crate) struct CpuSlots {
+    // SYSCALL does not switch stacks, so entry stub does it itself: user stack pointer is saved here...
+    user_rsp:   AtomicU64,
+    // ...and kernel one is loaded from here
+    kernel_rsp: AtomicU64,
+    // Kernel stack pointer of enter_user_mode() caller, to return to once user program exits
+    return_rsp: AtomicU64,
+}
+
+impl CpuSlots {
+    pub(crate) const fn new() -> CpuSlots {
+        CpuSlots {
+            user_rsp:   AtomicU64::new(0),
+            kernel_rsp: AtomicU64::new(0),
+            return_rsp: AtomicU64::new(0),
+        }
+    }
+}
+
+// Offsets of the slots from GS base
+const USER_RSP:   usize = percpu::SYSCALL_SLOTS_OFFSET + offset_of!(CpuSlots, user_rsp);
+const KERNEL_RSP: usize = percpu::SYSCALL_SLOTS_OFFSET + offset_of!(CpuSlots, kernel_rsp);
+const RETURN_RSP: usize = percpu::SYSCALL_SLOTS_OFFSET + offset_of!(CpuSlots, return_rsp);
+
+percpu! {
+    // Set while enter_user_mode() runs, i.e. while the kernel stack for user mode of the CPU is in use
+    static IN_USER_MODE: AtomicBool = AtomicBool::new(false);
+}
+
+/// User registers saved by syscall entry, as they are laid out on the kernel stack
+#[derive(Debug)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub r9:     u64,
+    pub r8:     u64,
+    pub r10:    u64,
+    pub rdx:    u64,
+    pub rsi:    u64,
+    pub rdi:    u64,
+    pub rax:    u64,
+    pub rflags: u64, // Saved by CPU in r11
+    pub rip:    u64, // Saved by CPU in rcx
+    pub rsp:    u64,
+}
+
+// Interrupts are masked by SFMASK on entry, so nothing could run on the kernel stack in between.
+// Kernel stack top is 16 bytes aligned and 10 registers are pushed, so stack stays aligned for the call.
+// User mode runs with GS base of its own, swapgs switches between it & the per-CPU one (see percpu).
+// user_mode_enter & user_mode_exit run in kernel, i.e. with the per-CPU GS base already
+global_asm!(r#"
+.global syscall_entry
+syscall_entry:
+    swapgs
+    mov gs:[{user_rsp}], rsp
+    mov rsp, gs:[{kernel_rsp}]
+
+    push qword ptr gs:[{user_rsp}]
+    push rcx
+    push r11
+    push rax
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
+
+    mov rdi, rsp
+    call syscall_dispatch
+
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    add rsp, 8
+    pop r11
+    pop rcx
+    pop rsp
+    swapgs
+    sysretq
+
+.global user_mode_enter
+user_mode_enter:
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+    pushfq
+    mov gs:[{return_rsp}], rsp
+
+    push rcx
+    push rsi
+    push 0x202
+    push rdx
+    push rdi
+
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+    // No interrupt may arrive in kernel with the user GS base
+    cli
+    swapgs
+    iretq
+
+.global user_mode_exit
+user_mode_exit:
+    mov rsp, gs:[{return_rsp}]
+    mov rax, rdi
+
+    popfq
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    ret
+"#,
+    user_rsp   = const USER_RSP,
+    kernel_rsp = const KERNEL_RSP,
+    return_rsp = const RETURN_RSP,
+);
+
+extern "C" {
+    fn syscall_entry();
+    // Saves callee-saved registers & flags, then irets into user mode at `entry` (with interrupts enabled)
+    fn user_mode_enter(entry: u64, stack_top: u64, code_selector: u64, data_selector: u64) -> u64;
+    // Restores state saved by user_mode_enter and returns from it with `exit_code`
+    fn user_mode_exit(exit_code: u64) -> !;
+}
+
+/// Allocates kernel stack for user mode of the calling CPU & enables SYSCALL instruction on it
+///
+/// Must be called after GDT is loaded & memory is initialised
+pub fn init(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), MapToError<Size4KiB>> {
+    init_cpu_stack(percpu::current_cpu(), mapper, frame_allocator)?;
+    init_cpu();
+
+    Ok(())
+}
+
+/// Allocates kernel stack for user mode of given CPU, application processors get it before they are started (see smp)
+pub fn init_cpu_stack(
+    cpu:             usize,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<(), MapToError<Size4KiB>> {
+    let stack = memory::alloc_stack(KERNEL_STACK_PAGES, mapper, frame_allocator)?;
+
+    // Both SYSCALL & interrupts in user mode switch to the same stack. They never nest,
+    // as both only happen in user mode & interrupts are masked during syscalls
+    gdt::set_kernel_stack(cpu, stack.top());
+    percpu::syscall_slots(cpu).kernel_rsp.store(stack.top().as_u64(), Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Enables SYSCALL instruction on the calling CPU, application processors do so on their own (see smp)
+///
+/// Must be called after GDT is loaded
+pub fn init_cpu() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    ).expect("GDT segments are not in the order SYSCALL/SYSRET expect");
+
+    LStar::write(VirtAddr::from_ptr(syscall_entry as *const ()));
+    // Syscalls start with interrupts disabled & clean direction flag; TF is cleared, so single step does not leak into kernel
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+/// Runs code at `entry` in user mode (ring 3) on `stack_top`, returns once it calls SYS_EXIT
+///
+/// # Safety
+/// Both `entry` & the stack must be mapped as user accessible. Must not be called
+/// while another user program runs on the same CPU, as every CPU has only one kernel stack for user mode
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack_top: VirtAddr) -> u64 {
+    let selectors = gdt::selectors();
+
+    IN_USER_MODE.get().store(true, Ordering::Relaxed);
+    let exit_code = user_mode_enter(
+        entry.as_u64(),
+        stack_top.as_u64(),
+        u64::from(selectors.user_code_selector.0),
+        u64::from(selectors.user_data_selector.0),
+    );
+    IN_USER_MODE.get().store(false, Ordering::Relaxed);
+
+    exit_code
+}
+
+/// Whether some user program runs on the calling CPU, i.e. enter_user_mode() has not returned there yet
+pub fn in_user_mode() -> bool {
+    IN_USER_MODE.get().load(Ordering::Relaxed)
+}
+
+/// Leaves user mode, making enter_user_mode() return `exit_code`
+///
+/// # Safety
+/// Must only be called from syscall handler, i.e. while enter_user_mode() runs
+pub(crate) unsafe fn exit_user_mode(exit_code: u64) -> ! {
+    user_mode_exit(exit_code)
+}
+
+/// Called by syscall entry with interrupts disabled
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &SyscallFrame) -> u64 {
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+
+    let result = match SYSCALL_TABLE.get(frame.rax as usize) {
+        Some(handler) => handler(args),
+        None          => -ENOSYS,
+    };
+    result as u64
+}
+
+/// write(fd, buf, len) - writes into file open by current process, returns number of bytes written
+fn sys_write(args: [u64; 6]) -> i64 {
+    let [fd, buf, len, ..] = args;
+
+    let file = match process::file(fd as usize) {
+        Some(file) => file,
+        None       => return -EBADF,
+    };
+    let bytes = match user_slice(buf, len) {
+        Some(bytes) => bytes,
+        None        => return -EFAULT,
+    };
+
+    file.write(bytes) as i64
+}
+
+/// exit(status) - terminates current process, enter_user_mode() returns given status
+fn sys_exit(args: [u64; 6]) -> i64 {
+    process::exit(args[0] as i32)
+}
+
+/// getpid() - returns PID of current process (0 if user code runs outside of any process)
+fn sys_getpid(_args: [u64; 6]) -> i64 {
+    process::current_pid().as_u64() as i64
+}
+
+/// Returns buffer passed by user program, None if any of its pages is not accessible from user mode
+fn user_slice(addr: u64, len: u64) -> Option<&'static [u8]> {
+    if len == 0 {
+        return Some(&[]);
+    }
+
+    // Buffer must be within user region, so it can't cross into (or over) kernel or non-canonical hole
+    let end = addr.checked_add(len - 1)?;
+    if addr < memory::USER_START || end >= memory::USER_END {
+        return None;
+    }
+    let start = VirtAddr::new(addr);
+    let end   = VirtAddr::new(end);
+
+    let pages = Page::<Size4KiB>::range_inclusive(Page::containing_address(start), Page::containing_address(end));
+    for page in pages {
+        if !memory::is_user_accessible(page.start_address()) {
+            return None;
+        }
+    }
+
+    // Safe because the whole range is mapped
+    Some(unsafe { core::slice::from_raw_parts(start.as_ptr(), len as usize) })
+}
@@ -0,0 +1,205 @@
+use alloc::{
+    collections::{ BTreeMap, VecDeque },
+    sync::Arc,
+};
+use core::{
+    future,
+    mem,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+
+use crate::spinlock::IrqSpinLock;
+use super::SendError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// All senders are gone & receiver has seen everything
+    Closed,
+    /// Receiver has fallen behind, that many oldest values have been overwritten before it got them.
+    /// Next recv returns the oldest value that is still there
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct State<T> {
+    // Last `capacity` values sent, the oldest one goes away to make room for a new one
+    buffer:           VecDeque<T>,
+    capacity:         usize,
+    // Sequence number the next value sent gets
+    next_seq:         u64,
+    senders:          usize,
+    receivers:        usize,
+    next_receiver_id: u64,
+    // Wakers of receivers that are waiting, by receiver ID
+    wakers:           BTreeMap<u64, Waker>,
+}
+
+impl<T: Clone> State<T> {
+    /// Returns value receiver should get next & moves its cursor past it
+    fn take(&self, next: &mut u64) -> Result<T, TryRecvError> {
+        let oldest = self.next_seq - self.buffer.len() as u64;
+
+        if *next < oldest {
+            let lagged = oldest - *next;
+            *next      = oldest;
+            return Err(TryRecvError::Lagged(lagged));
+        }
+
+        match self.buffer.get((*next - oldest) as usize) {
+            Some(value)              => {
+                *next += 1;
+                Ok(value.clone())
+            }
+            None if self.senders > 0 => Err(TryRecvError::Empty),
+            None                     => Err(TryRecvError::Closed),
+        }
+    }
+}
+
+/// Creates channel where every receiver gets every value sent after it has subscribed
+///
+/// Channel keeps last `capacity` values, receiver that falls further behind gets RecvError::Lagged
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel needs room for at least one value");
+
+    let state = Arc::new(IrqSpinLock::new(State {
+        // Allocated upfront, so sending never allocates (e.g. in interrupt handler)
+        buffer:           VecDeque::with_capacity(capacity),
+        capacity,
+        next_seq:         0,
+        senders:          1,
+        receivers:        1,
+        next_receiver_id: 1,
+        wakers:           BTreeMap::new(),
+    }));
+
+    (Sender { state: state.clone() }, Receiver { state, id: 0, next: 0 })
+}
+
+/// Sending half, could be cloned to have multiple producers. Send never waits, thus it could be used
+/// in interrupt handlers
+pub struct Sender<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends value to every receiver, returns how many of them there are. Fails if there are none
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let (receivers, overwritten, wakers) = {
+            let mut state = self.state.lock();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+
+            let overwritten = if state.buffer.len() == state.capacity {
+                state.buffer.pop_front()
+            } else {
+                None
+            };
+            state.buffer.push_back(value);
+            state.next_seq += 1;
+
+            (state.receivers, overwritten, mem::take(&mut state.wakers))
+        };
+
+        // Wakers are called with lock released, as they may poll receivers right away
+        drop(overwritten);
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+        Ok(receivers)
+    }
+
+    /// Creates receiver that gets values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.state.lock();
+
+        let id = state.next_receiver_id;
+        state.next_receiver_id += 1;
+        state.receivers        += 1;
+
+        Receiver { state: self.state.clone(), id, next: state.next_seq }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.state.lock().senders += 1;
+        Sender { state: self.state.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state  = self.state.lock();
+            state.senders -= 1;
+            if state.senders > 0 {
+                return;
+            }
+            mem::take(&mut state.wakers)
+        };
+
+        // Receivers learn that nothing else is coming
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+    id:    u64,
+    // Sequence number of the value this receiver gets next
+    next:  u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Waits for the next value
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        future::poll_fn(|context| self.poll_recv(context)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.state.lock().take(&mut self.next)
+    }
+
+    pub fn poll_recv(&mut self, context: &mut Context) -> Poll<Result<T, RecvError>> {
+        let mut state = self.state.lock();
+
+        match state.take(&mut self.next) {
+            Ok(value)                       => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Lagged(lost)) => Poll::Ready(Err(RecvError::Lagged(lost))),
+            Err(TryRecvError::Closed)       => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Empty)        => {
+                state.wakers.insert(self.id, context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state    = self.state.lock();
+            state.receivers -= 1;
+            state.wakers.remove(&self.id)
+        };
+        drop(waker);
+    }
+}
@@ -0,0 +1,45 @@
+// Channels for tasks to talk to each other (and for interrupt handlers to talk to tasks)
+//
+// Sending never blocks, so senders could be used from interrupt context: bounded mpsc (try_send),
+// oneshot & broadcast never allocate on send, as their buffers are allocated upfront
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+
+use core::fmt;
+
+/// Receiver is gone, value is handed back
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// Bounded channel has no room left
+    Full(T),
+    /// Receiver is gone
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_)   => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing has been sent yet
+    Empty,
+    /// Nothing is left & nothing could be sent anymore
+    Closed,
+}
@@ -0,0 +1,289 @@
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    future,
+    mem,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+use futures_util::stream::Stream;
+
+use crate::spinlock::IrqSpinLock;
+use super::{ SendError, TryRecvError, TrySendError };
+
+struct State<T> {
+    queue:          VecDeque<T>,
+    // None for unbounded channel
+    capacity:       Option<usize>,
+    senders:        usize,
+    receiver_alive: bool,
+    recv_waker:     Option<Waker>,
+    // Senders waiting for room in bounded channel, all of them are woken once something is received
+    send_wakers:    Vec<Waker>,
+}
+
+impl<T> State<T> {
+    /// Queues value, returns receiver's waker to be woken once lock is released
+    fn push(&mut self, value: T) -> Result<Option<Waker>, TrySendError<T>> {
+        if !self.receiver_alive {
+            return Err(TrySendError::Closed(value));
+        }
+        if self.capacity.filter(|&capacity| self.queue.len() >= capacity).is_some() {
+            return Err(TrySendError::Full(value));
+        }
+
+        self.queue.push_back(value);
+        Ok(self.recv_waker.take())
+    }
+}
+
+struct Chan<T> {
+    state: IrqSpinLock<State<T>>,
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Arc<Chan<T>> {
+        // Bounded queue is allocated upfront, so sending never allocates (e.g. in interrupt handler)
+        let queue = match capacity {
+            Some(capacity) => VecDeque::with_capacity(capacity),
+            None           => VecDeque::new(),
+        };
+
+        Arc::new(Chan {
+            state: IrqSpinLock::new(State {
+                queue,
+                capacity,
+                senders:        1,
+                receiver_alive: true,
+                recv_waker:     None,
+                send_wakers:    Vec::new(),
+            }),
+        })
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let waker = self.state.lock().push(value)?;
+
+        // Wakers are called with lock released, as they may poll the other side right away
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn poll_send(&self, context: &mut Context, slot: &mut Option<T>) -> Poll<Result<(), SendError<T>>> {
+        let value = slot.take().expect("send polled after completion");
+
+        let waker = {
+            let mut state = self.state.lock();
+            match state.push(value) {
+                Ok(waker)                        => waker,
+                Err(TrySendError::Closed(value)) => return Poll::Ready(Err(SendError(value))),
+                Err(TrySendError::Full(value))   => {
+                    // Waker is registered under the same lock as the check, so receiver could not miss it
+                    state.send_wakers.push(context.waker().clone());
+                    *slot = Some(value);
+                    return Poll::Pending;
+                }
+            }
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_recv(&self, context: &mut Context) -> Poll<Option<T>> {
+        let (value, send_wakers) = {
+            let mut state = self.state.lock();
+            match state.queue.pop_front() {
+                Some(value)                => (value, mem::take(&mut state.send_wakers)),
+                None if state.senders == 0 => return Poll::Ready(None),
+                None                       => {
+                    state.recv_waker = Some(context.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+
+        for waker in send_wakers {
+            waker.wake();
+        }
+        Poll::Ready(Some(value))
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let (value, send_wakers) = {
+            let mut state = self.state.lock();
+            match state.queue.pop_front() {
+                Some(value)                => (value, mem::take(&mut state.send_wakers)),
+                None if state.senders == 0 => return Err(TryRecvError::Closed),
+                None                       => return Err(TryRecvError::Empty),
+            }
+        };
+
+        for waker in send_wakers {
+            waker.wake();
+        }
+        Ok(value)
+    }
+
+    fn add_sender(&self) {
+        self.state.lock().senders += 1;
+    }
+
+    fn drop_sender(&self) {
+        let waker = {
+            let mut state  = self.state.lock();
+            state.senders -= 1;
+            if state.senders > 0 {
+                return;
+            }
+            state.recv_waker.take()
+        };
+
+        // Receiver learns that nothing else is coming
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.state.lock().receiver_alive
+    }
+}
+
+/// Creates channel that holds up to `capacity` values, sender waits (or fails with try_send) once it is full
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "bounded channel needs room for at least one value");
+
+    let chan = Chan::new(Some(capacity));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates channel that never runs out of room
+///
+/// Sending may allocate, thus bounded channel (with try_send) suits interrupt handlers better
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = Chan::new(None);
+    (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Sending half of bounded channel, could be cloned to have multiple producers
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Waits for room in the channel & sends value, fails if receiver is gone
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut slot = Some(value);
+        future::poll_fn(|context| self.chan.poll_send(context, &mut slot)).await
+    }
+
+    /// Sends value if there is room, never waits, thus could be used in interrupt handlers
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+
+    /// Whether receiver is gone
+    pub fn is_closed(&self) -> bool {
+        self.chan.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.chan.add_sender();
+        Sender { chan: self.chan.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+/// Sending half of unbounded channel, could be cloned to have multiple producers
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends value right away, fails if receiver is gone
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan.try_send(value).map_err(|err| match err {
+            TrySendError::Closed(value) | TrySendError::Full(value) => SendError(value),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.is_closed()
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> UnboundedSender<T> {
+        self.chan.add_sender();
+        UnboundedSender { chan: self.chan.clone() }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+/// Receiving half of the channel, values come out in the order they were sent
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value, None once all senders are gone & nothing is left
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|context| self.chan.poll_recv(context)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    pub fn poll_recv(&mut self, context: &mut Context) -> Poll<Option<T>> {
+        self.chan.poll_recv(context)
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        self.chan.poll_recv(context)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let (queue, send_wakers) = {
+            let mut state        = self.chan.state.lock();
+            state.receiver_alive = false;
+            (mem::take(&mut state.queue), mem::take(&mut state.send_wakers))
+        };
+
+        // Values nobody is going to receive are dropped with lock released (& interrupts enabled)
+        drop(queue);
+        for waker in send_wakers {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+
+use crate::spinlock::IrqSpinLock;
+use super::TryRecvError;
+
+/// Sender has been dropped without sending anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct State<T> {
+    value:          Option<T>,
+    sender_alive:   bool,
+    receiver_alive: bool,
+    waker:          Option<Waker>,
+}
+
+/// Creates channel that carries exactly one value
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(IrqSpinLock::new(State {
+        value:          None,
+        sender_alive:   true,
+        receiver_alive: true,
+        waker:          None,
+    }));
+
+    (Sender { state: state.clone() }, Receiver { state })
+}
+
+/// Sending half, send never waits nor allocates, thus could be used in interrupt handlers
+pub struct Sender<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Hands value over to receiver, gives it back if receiver is gone
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+        if !state.receiver_alive {
+            return Err(value);
+        }
+
+        // Receiver is woken when sender is dropped right after
+        state.value = Some(value);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.state.lock().receiver_alive
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state      = self.state.lock();
+            state.sender_alive = false;
+            state.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Receiving half, resolves with the value or with RecvError if sender is dropped without sending
+pub struct Receiver<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock();
+        match state.value.take() {
+            Some(value)                => Ok(value),
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None                       => Err(TryRecvError::Closed),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<T, RecvError>> {
+        let mut state = self.state.lock();
+        match state.value.take() {
+            Some(value)                => Poll::Ready(Ok(value)),
+            None if state.sender_alive => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+            None                       => Poll::Ready(Err(RecvError)),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut state        = self.state.lock();
+            state.receiver_alive = false;
+            state.value.take()
+        };
+
+        // Value nobody is going to receive is dropped with lock released
+        drop(value);
+    }
+}
@@ -0,0 +1,116 @@
+use core::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+
+use crate::spinlock::IrqSpinLock;
+
+/// Maximum number of work items that could wait to be run
+pub const CAPACITY: usize = 128;
+
+static QUEUE:   IrqSpinLock<WorkQueue> = IrqSpinLock::named("deferred::QUEUE", WorkQueue::new());
+static DROPPED: AtomicU64              = AtomicU64::new(0);
+
+/// Small piece of work that interrupt handler postpones (also known as bottom half)
+///
+/// Work is run later by the executor with interrupts enabled, so it may take its time,
+/// however it still must not block
+#[derive(Clone, Copy)]
+pub struct Work {
+    func: fn(usize),
+    arg:  usize,
+}
+
+impl Work {
+    /// `arg` is passed to `func` when work is run, e.g. a byte read from device
+    pub const fn new(func: fn(usize), arg: usize) -> Work {
+        Work { func, arg }
+    }
+
+    fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+/// Fixed-capacity ring buffer of work items
+///
+/// It lives in a static, so scheduling work never allocates and could be done before heap is initialised
+struct WorkQueue {
+    items: [Option<Work>; CAPACITY],
+    head:  usize,
+    len:   usize,
+}
+
+impl WorkQueue {
+    const fn new() -> WorkQueue {
+        WorkQueue {
+            items: [None; CAPACITY],
+            head:  0,
+            len:   0,
+        }
+    }
+
+    fn push(&mut self, work: Work) -> Result<(), Work> {
+        if self.len == CAPACITY {
+            return Err(work);
+        }
+
+        self.items[(self.head + self.len) % CAPACITY] = Some(work);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % CAPACITY;
+        self.len -= 1;
+        work
+    }
+}
+
+/// Called by interrupt handlers to postpone work
+///
+/// Must not block or allocate. Returns work back if queue is full (work is dropped then)
+pub fn schedule(work: Work) -> Result<(), Work> {
+    // QUEUE keeps interrupts disabled while locked, so the lock is never held by interrupted code on this CPU
+    let result = QUEUE.lock().push(work);
+
+    if result.is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Runs all work scheduled so far, returns how many items have been run
+///
+/// Called by the executor (or any other idle loop), never from interrupt handler
+pub fn run_pending() -> usize {
+    let mut count = 0;
+
+    // Lock is only held (with interrupts disabled) while item is popped, work itself
+    // runs with interrupts enabled and could schedule more work
+    while let Some(work) = next_work() {
+        work.run();
+        count += 1;
+    }
+    count
+}
+
+// Separate function, so guard is dropped before work runs: temporaries of `while let` live throughout its body
+fn next_work() -> Option<Work> {
+    QUEUE.lock().pop()
+}
+
+/// Returns true if there is work waiting to be run
+pub fn has_pending() -> bool {
+    QUEUE.lock().len > 0
+}
+
+/// Returns number of work items dropped because queue was full
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
@@ -1,100 +1,275 @@
 use alloc::{
+    boxed::Box,
     collections::BTreeMap,
+    rc::Rc,
     sync::Arc,
     task::Wake,
+    vec::Vec,
 };
 use core::{
+    future::Future,
+    mem,
+    pin::pin,
+    sync::atomic::{ AtomicBool, Ordering },
     task::{
         Context,
         Poll,
         Waker,
     },
 };
-use crossbeam_queue::ArrayQueue;
 use x86_64::instructions::interrupts::{
     disable,
     enable,
     enable_and_hlt,
 };
 
-use crate::task::{
-    Task,
-    TaskId,
+use crate::{
+    task::{
+        deferred,
+        join::JoinHandle,
+        policy::{ RoundRobin, SchedulingPolicy },
+        spawner::{ self, Spawner },
+        table::TaskTable,
+        waker::WakeQueue,
+        RawTask,
+        Task,
+        TaskId,
+        TaskInfo,
+    },
+    time,
 };
 
+/// Capacity of the wake queue executors are created with, see Executor::with_wake_capacity
+pub const DEFAULT_WAKE_CAPACITY: usize = 128;
+
 pub struct Executor {
     // We use BTree to store Tasks as it allows for fast search
-    tasks:       BTreeMap<TaskId, Task>,
-    // We use Arc<ArrayQueue> because this queue would be shared between executor and wakers
-    //   wakers: push TaskId, executor consumes that TaskId and runs the Task which is associated
-    //     with this ID
-    task_queue:  Arc<ArrayQueue<TaskId>>,
-    // We use BTree to store Wakers as it allows for fast search:
+    tasks:       BTreeMap<TaskId, RawTask>,
+    // We use Arc<WakeQueue> because this queue would be shared between executor and wakers
+    //   wakers: push themselves (at most once until popped), executor consumes them and runs the Task
+    //     which is associated with their ID
+    wake_queue:  Arc<WakeQueue>,
+    // Woken tasks are moved from wake_queue into the policy, which decides what is polled next
+    policy:      Box<dyn SchedulingPolicy>,
+    // Spawned tasks wait there until run_ready_tasks adds them, so running tasks could spawn too
+    spawner:     Spawner,
+    // Name, stats & Waker of every task, shared with spawner so it could be listed from within tasks:
     //   we need to cache Wakers so they could be re-used to wake task multiple times
     //   also ensures that reference-counted wakers are not deallocated inside interrupt handlers
     //     as it may lead to deadlock
-    waker_cache: BTreeMap<TaskId, Waker>
+    //   also lets executor find woken tasks that have not fit into wake_queue
+    table:       Rc<TaskTable>,
 }
 
 impl Executor {
+    /// Creates executor that polls tasks in the order they were woken
     pub fn new() -> Executor {
+        Executor::with_policy(Box::new(RoundRobin::new()))
+    }
+
+    pub fn with_policy(policy: Box<dyn SchedulingPolicy>) -> Executor {
+        Executor::with_wake_capacity(policy, DEFAULT_WAKE_CAPACITY)
+    }
+
+    /// `wake_capacity` is how many woken tasks are kept in the order they were woken. Waking more tasks
+    /// at once still works, but the ones that do not fit are polled in the order of their IDs
+    pub fn with_wake_capacity(policy: Box<dyn SchedulingPolicy>, wake_capacity: usize) -> Executor {
+        let wake_queue = WakeQueue::new(wake_capacity);
+        let table      = TaskTable::new();
+
         Executor {
             tasks:       BTreeMap::new(),
-            task_queue:  Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
+            spawner:     Spawner::new(wake_queue.clone(), table.clone()),
+            wake_queue,
+            policy,
+            table,
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
+    /// Returns handle that spawns tasks onto this executor, even from within its running tasks
+    pub fn spawner(&self) -> Spawner {
+        self.spawner.clone()
+    }
+
+    pub fn policy_name(&self) -> &'static str {
+        self.policy.name()
+    }
 
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already exists");
-        }
-        self.task_queue.push(task_id).expect("queue is full");
+    /// Returns snapshot of all tasks that have not completed yet, ordered by ID
+    ///
+    /// Tasks could take it through their spawner, see Spawner::tasks
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.table.snapshot()
+    }
+
+    /// Queues task to be polled, returned handle resolves with task's output
+    pub fn spawn<T>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        self.spawner.spawn(task)
+    }
+
+    /// Returns handle that shuts executor down, e.g. from within one of its tasks
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { wake_queue: self.wake_queue.clone() }
+    }
+
+    /// Drops all outstanding tasks right away, see ShutdownHandle::shutdown
+    pub fn shutdown(&mut self) {
+        self.wake_queue.shut_down();
+        self.drop_tasks();
     }
 
+    pub fn is_shut_down(&self) -> bool {
+        self.wake_queue.is_shut_down()
+    }
+
+    /// Runs tasks forever, sleeping whenever none of them is ready
+    ///
+    /// Once executor is shut down, it only sleeps
     pub fn run(&mut self) -> ! {
-        // Executor is not optimal as it would run endlesly thus burning CPU at 100%
         loop {
+            // Work deferred by interrupt handlers goes first, as it is likely to wake some tasks
+            deferred::run_pending();
+            self.run_ready_tasks();
+            self.sleep_if_idle(None);
+        }
+    }
+
+    /// Runs tasks until none of them is ready (nor any deferred work is pending) & returns,
+    /// tasks that wait for something stay in executor
+    pub fn run_until_idle(&mut self) {
+        loop {
+            deferred::run_pending();
+            self.run_ready_tasks();
+
+            if self.wake_queue.is_empty() && !deferred::has_pending() {
+                return;
+            }
+        }
+    }
+
+    /// Runs tasks until `future` completes & returns its output
+    ///
+    /// Future is polled on its own (not as a task), so it could borrow from the caller. Tasks it spawns
+    /// (see task::spawn) run on this executor & stay there once it has completed. Shutdown drops tasks,
+    /// but not the future itself
+    pub fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let main_waker = MainWaker::new();
+        let waker      = Waker::from(main_waker.clone());
+
+        loop {
+            deferred::run_pending();
+
+            if main_waker.woken.swap(false, Ordering::AcqRel) {
+                let _enter      = spawner::enter(&self.spawner);
+                let mut context = Context::from_waker(&waker);
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            }
+
             self.run_ready_tasks();
-            self.sleep_if_idle();
+            self.sleep_if_idle(Some(&main_waker.woken));
         }
     }
 
     fn run_ready_tasks(&mut self) {
+        self.poll_ready_tasks();
+
+        if self.is_shut_down() {
+            self.drop_tasks();
+        }
+    }
+
+    fn poll_ready_tasks(&mut self) {
         // May not be required as RFC 2229 has been implemented (or so it seems atm)
         let Self {
             tasks,
-            task_queue,
-            waker_cache
+            wake_queue,
+            policy,
+            spawner,
+            table,
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
+        // Running tasks could reach this executor through task::spawn()
+        let _enter = spawner::enter(spawner);
+
+        // Shutdown could be requested by a task, the rest are dropped instead of being polled
+        while !wake_queue.is_shut_down() {
+            for task in spawner.take_queued() {
+                if tasks.insert(task.id, task).is_some() {
+                    panic!("task with same ID already exists");
+                }
+            }
+
+            // Tasks woken by the previous poll (or by interrupts) are taken into account before the next pick
+            while let Some(waker) = wake_queue.pop() {
+                // Flag is not set if task has already been taken as overflowed one, or has completed
+                let task_id = waker.task_id();
+                if let Some(task) = tasks.get(&task_id).filter(|_| waker.take_queued()) {
+                    policy.enqueue(task_id, task.params);
+                    table.set_ready(task_id);
+                }
+            }
+            if wake_queue.take_overflowed() {
+                for task_id in table.take_woken() {
+                    if let Some(task) = tasks.get(&task_id) {
+                        policy.enqueue(task_id, task.params);
+                        table.set_ready(task_id);
+                    }
+                }
+            }
+
+            let task_id = match policy.pick_next() {
+                Some(task_id) => task_id,
+                None          => break,
+            };
             let task = match tasks.get_mut(&task_id) {
                 Some(task) => task,
                 None       => continue,
             };
 
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let waker = match table.start_poll(task_id) {
+                Some(waker) => waker,
+                None        => continue,
+            };
+
+            let mut context = Context::from_waker(&waker);
+            let started     = time::uptime_ns();
+            let poll        = task.poll(&mut context);
+            table.finish_poll(task_id, time::uptime_ns().saturating_sub(started));
 
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+            match poll {
                 Poll::Ready(()) => {
                     // Task has been completed - remove it together with cached waker
                     tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+                    table.remove(task_id);
                 }
                 Poll::Pending   => {}
             }
         }
     }
 
-    fn sleep_if_idle(&self) {
+    /// Drops all tasks (their JoinHandles resolve with JoinError::Aborted) & everything queued for them
+    fn drop_tasks(&mut self) {
+        let tasks  = mem::take(&mut self.tasks);
+        let queued = self.spawner.take_queued();
+
+        self.wake_queue.clear();
+        while self.policy.pick_next().is_some() {}
+        self.table.clear();
+
+        // Tasks may wake or spawn others while being dropped, which does nothing by now
+        drop(tasks);
+        drop(queued);
+    }
+
+    /// Sleeps until the next interrupt, unless some task (or future of block_on) has been woken meanwhile
+    fn sleep_if_idle(&self, main_woken: Option<&AtomicBool>) {
         disable();
-        if self.task_queue.is_empty() {
+        let main_ready = main_woken.filter(|woken| woken.load(Ordering::Acquire)).is_some();
+
+        if self.wake_queue.is_empty() && !deferred::has_pending() && !main_ready {
             enable_and_hlt();
         } else {
             enable();
@@ -102,33 +277,55 @@ impl Executor {
     }
 }
 
-struct TaskWaker {
-    task_id:    TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>
+impl Default for Executor {
+    fn default() -> Executor {
+        Executor::new()
+    }
 }
 
-impl TaskWaker {
-    // Waker implements From, so we can create safe version of Waker
-    // from our Arc-based TaskWaker
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker{
-            task_id,
-            task_queue,
-        }))
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // Wakers & spawners could outlive executor, they have to stop queueing tasks nobody is going to run
+        self.shutdown();
     }
+}
+
+/// Shuts executor down: all its outstanding tasks are dropped (their JoinHandles resolve with
+/// JoinError::Aborted) the next time executor gets control, as are tasks spawned afterwards.
+/// Wakers of dropped tasks do nothing from then on
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    wake_queue: Arc<WakeQueue>,
+}
 
-    fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue is full");
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.wake_queue.shut_down();
     }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.wake_queue.is_shut_down()
+    }
+}
+
+/// Waker of the future block_on runs (multi-core executor's one too)
+pub(super) struct MainWaker {
+    pub(super) woken: AtomicBool,
 }
 
-impl Wake for TaskWaker {
+impl MainWaker {
+    /// Future is woken from the start, so it is polled right away
+    pub(super) fn new() -> Arc<MainWaker> {
+        Arc::new(MainWaker { woken: AtomicBool::new(true) })
+    }
+}
+
+impl Wake for MainWaker {
     fn wake(self: Arc<Self>) {
-        self.wake_task();
+        self.woken.store(true, Ordering::Release);
     }
 
     fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
+        self.woken.store(true, Ordering::Release);
     }
 }
-
@@ -0,0 +1,192 @@
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+};
+use core::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+use spin::Mutex;
+
+use crate::task::TaskId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// Task has been aborted before it completed
+    Aborted,
+}
+
+enum Stage<T> {
+    Running,
+    Finished(Result<T, JoinError>),
+    // Result has been handed out by JoinHandle
+    Consumed,
+}
+
+struct Inner<T> {
+    stage:           Stage<T>,
+    abort_requested: bool,
+    // Waker of the task itself, so abort could get it polled (and dropped) right away
+    task_waker:      Option<Waker>,
+    // Waker of whoever awaits JoinHandle
+    join_waker:      Option<Waker>,
+}
+
+/// Shared by task (through Harness) & its JoinHandle
+pub(crate) struct JoinState<T> {
+    inner: Mutex<Inner<T>>,
+}
+
+impl<T> JoinState<T> {
+    pub(crate) fn new() -> JoinState<T> {
+        JoinState {
+            inner: Mutex::new(Inner {
+                stage:           Stage::Running,
+                abort_requested: false,
+                task_waker:      None,
+                join_waker:      None,
+            }),
+        }
+    }
+
+    fn finish(&self, result: Result<T, JoinError>) {
+        let join_waker = {
+            let mut inner = self.inner.lock();
+            inner.stage   = Stage::Finished(result);
+            inner.task_waker.take();
+            inner.join_waker.take()
+        };
+
+        // Waker is called with lock released, as it may poll JoinHandle right away
+        if let Some(waker) = join_waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future executor actually polls: runs task's future & stores its output for JoinHandle
+///
+/// Once abort is requested, task's future is not polled anymore. Harness completes instead,
+/// so executor drops it together with the future
+pub(crate) struct Harness<F: Future> {
+    future: Pin<Box<F>>,
+    state:  Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Harness<F> {
+    pub(crate) fn new(future: F, state: Arc<JoinState<F::Output>>) -> Harness<F> {
+        Harness {
+            future: Box::pin(future),
+            state,
+        }
+    }
+}
+
+impl<F: Future> Future for Harness<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        {
+            let mut inner = self.state.inner.lock();
+            if inner.abort_requested {
+                drop(inner);
+                self.state.finish(Err(JoinError::Aborted));
+                return Poll::Ready(());
+            }
+
+            if inner.task_waker.as_ref().filter(|waker| waker.will_wake(context.waker())).is_none() {
+                inner.task_waker = Some(context.waker().clone());
+            }
+        }
+
+        match self.future.as_mut().poll(context) {
+            Poll::Ready(output) => {
+                self.state.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending       => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> Drop for Harness<F> {
+    fn drop(&mut self) {
+        // Task dropped before completion (e.g. by executor shutdown) counts as aborted
+        let running = matches!(self.state.inner.lock().stage, Stage::Running);
+        if running {
+            self.state.finish(Err(JoinError::Aborted));
+        }
+    }
+}
+
+/// Resolves with the output of spawned task
+///
+/// Dropping the handle detaches the task: it keeps running, but its output is dropped
+pub struct JoinHandle<T> {
+    id:    TaskId,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(id: TaskId, state: Arc<JoinState<T>>) -> JoinHandle<T> {
+        JoinHandle { id, state }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Whether task has completed (or has been aborted)
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.state.inner.lock().stage, Stage::Running)
+    }
+
+    /// Lets task run to completion without anyone waiting for it, same as dropping the handle
+    pub fn detach(self) {}
+
+    /// Stops the task: its future is dropped the next time executor gets to it
+    /// (without being polled again) & the handle resolves with JoinError::Aborted.
+    /// Same happens to all tasks when executor is shut down
+    ///
+    /// Does nothing if task has already completed
+    pub fn abort(&self) {
+        let task_waker = {
+            let mut inner = self.state.inner.lock();
+            if !matches!(inner.stage, Stage::Running) {
+                return;
+            }
+
+            inner.abort_requested = true;
+            inner.task_waker.take()
+        };
+
+        // Task that has not been polled yet has no waker, but it is queued already
+        if let Some(waker) = task_waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let mut inner = self.state.inner.lock();
+
+        match mem::replace(&mut inner.stage, Stage::Consumed) {
+            Stage::Finished(result) => Poll::Ready(result),
+            Stage::Running          => {
+                inner.stage      = Stage::Running;
+                inner.join_waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+            Stage::Consumed         => panic!("JoinHandle polled after it has completed"),
+        }
+    }
+}
@@ -18,8 +18,13 @@ use pc_keyboard::{
     ScancodeSet1,
     layouts,
 };
+use x86_64::instructions::port::Port;
 
-use crate::print;
+use crate::{
+    interrupts::InterruptIndex,
+    print,
+    task::deferred::{ self, Work },
+};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -78,11 +83,22 @@ pub async fn print_keypress() {
     }
 }
 
-/// Called by Keyboard interrupt
+/// Registered for Keyboard IRQ line by `interrupts::init_idt`
+pub(crate) fn keyboard_interrupt_handler(_index: InterruptIndex) {
+    let mut ps2_port = Port::new(0x60); // 0x0060-0x0064: The "8042" PS/2 Controller or its predecessors, dealing with keyboards and mice
+    let scancode: u8 = unsafe { ps2_port.read() };
+
+    // Defer the rest of the work - keeping interrupt handler as simple as possible.
+    // If deferred queue is full, keypress is lost, same as it would be with full scancode queue
+    let _ = deferred::schedule(Work::new(add_scancode, usize::from(scancode)));
+}
+
+/// Deferred work of Keyboard interrupt - sends scancode into background async task
 ///
 /// Must not block or allocate (thus try_get())
-/// pub(crate) is to ensure visibility is only for lib, not main
-pub(crate) fn add_scancode(scancode: u8) {
+fn add_scancode(scancode: usize) {
+    let scancode = scancode as u8;
+
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Ok(()) = queue.push(scancode) {
             WAKER.wake();
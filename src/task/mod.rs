@@ -1,8 +1,22 @@
+pub mod channel;
+pub mod deferred;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod multicore;
+pub mod policy;
+pub mod spawner;
+pub mod sync;
 
-use alloc::boxed::Box;
+mod table;
+mod waker;
+
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+};
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::atomic::{
@@ -15,8 +29,13 @@ use core::{
     },
 };
 
+use self::{
+    join::{ Harness, JoinHandle, JoinState },
+    policy::{ Priority, SchedParams },
+};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     fn new() -> Self {
@@ -24,28 +43,150 @@ impl TaskId {
         // We only require Id to be unique, so having Relaxed ordering is enough (weakest ordering available)
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
 }
 
-pub struct Task {
-    id: TaskId,
-    // () - means that Task doesn't return and we only require tasks side effect
-    // dyn Future - means that we are going to store trait object
-    // Pin<Box> - means that value is on heap and cannot be moved in memory; also &mut reference
-    // cannot be created
-    future: Pin<Box<dyn Future<Output = ()>>>,
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Woken & waiting to be polled
+    Ready,
+    /// Being polled right now, i.e. it is the task that has asked for the snapshot
+    Running,
+    /// Pending until something wakes it
+    Waiting,
 }
 
-impl Task {
+/// Snapshot of executor's task, see Executor::tasks
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id:            TaskId,
+    pub name:          Option<&'static str>,
+    pub state:         TaskState,
+    pub params:        SchedParams,
+    pub polls:         u64,
+    /// Time spent in poll, precision is that of time::uptime_ns
+    pub poll_time_ns:  u64,
+    /// Uptime when task has last been woken (spawning counts too)
+    pub last_woken_ns: u64,
+}
+
+/// Future together with what executor needs to know about it, `T` is what the future resolves to
+pub struct Task<T = ()> {
+    raw:    RawTask,
+    handle: JoinHandle<T>,
+}
+
+impl<T: 'static> Task<T> {
     // 'static is required because we need to ensure that future lives as long as the Task
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+    pub fn new(future: impl Future<Output = T> + 'static) -> Task<T> {
+        let id    = TaskId::new();
+        let state = Arc::new(JoinState::new());
+
         Task {
-            id: TaskId::new(),
-            future: Box::pin(future),
+            raw:    RawTask {
+                id,
+                name:   None,
+                future: Box::pin(Harness::new(future, state.clone())),
+                params: SchedParams::default(),
+            },
+            handle: JoinHandle::new(id, state),
         }
     }
+}
 
+impl<T> Task<T> {
+    /// Name shows up in task listings (see Executor::tasks)
+    pub fn with_name(mut self, name: &'static str) -> Task<T> {
+        self.raw.name = Some(name);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Task<T> {
+        self.raw.params.priority = priority;
+        self
+    }
+
+    /// `deadline` is absolute, in timer ticks (see time::ticks)
+    pub fn with_deadline(mut self, deadline: u64) -> Task<T> {
+        self.raw.params.deadline = Some(deadline);
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.raw.id
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.raw.name
+    }
+
+    pub fn params(&self) -> SchedParams {
+        self.raw.params
+    }
+
+    fn into_parts(self) -> (RawTask, JoinHandle<T>) {
+        (self.raw, self.handle)
+    }
+}
+
+/// Task as executor stores it, output has already been routed to JoinHandle
+struct RawTask {
+    id:   TaskId,
+    name: Option<&'static str>,
+    // () - means that executor only requires task's side effect (Harness stores output for JoinHandle)
+    // dyn Future - means that we are going to store trait object
+    // Pin<Box> - means that value is on heap and cannot be moved in memory; also &mut reference
+    // cannot be created
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    // Passed to scheduling policy every time task is woken
+    params: SchedParams,
+}
+
+impl RawTask {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
 
+/// Spawns task onto the executor that runs the calling task
+///
+/// Panics if called outside of a task, see spawner::current()
+pub fn spawn<T: 'static>(future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    spawner::current()
+        .expect("task::spawn called outside of a running executor")
+        .spawn(Task::new(future))
+}
+
+/// Lets other ready tasks run before the current one continues
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Future returned by yield_now(), pending exactly once
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        // Task is queued again right away, behind whatever is ready already (as policy decides)
+        self.yielded = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,675 @@
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    sync::{ Arc, Weak },
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    cell::{ RefCell, UnsafeCell },
+    future::Future,
+    mem,
+    pin::{ pin, Pin },
+    sync::atomic::{
+        fence,
+        AtomicBool,
+        AtomicU64,
+        AtomicU8,
+        AtomicUsize,
+        Ordering,
+    },
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+use crossbeam_queue::{ ArrayQueue, PushError };
+use x86_64::instructions::interrupts::{
+    disable,
+    enable,
+    enable_and_hlt,
+};
+
+use crate::{
+    hlt_loop,
+    percpu,
+    smp,
+    spinlock::IrqSpinLock,
+    task::{
+        deferred,
+        executor::MainWaker,
+        join::{ Harness, JoinHandle, JoinState },
+        TaskId,
+    },
+    warn,
+};
+
+/// Capacity of every CPU's run queue executors are created with, see MultiCoreExecutor::with_queue_capacity
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/*
+ * Task states, a task moves through them as follows:
+ * | From      | To        | When                                                      |
+ * | IDLE      | SCHEDULED | woken, task is pushed to a run queue                      |
+ * | SCHEDULED | RUNNING   | popped by a worker, which is the only one to poll it      |
+ * | RUNNING   | NOTIFIED  | woken while being polled, worker queues it again after    |
+ * | RUNNING   | IDLE      | poll returned Pending                                     |
+ * | RUNNING   | DONE      | poll returned Ready (or executor has shut down)           |
+ */
+const IDLE:      u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING:   u8 = 2;
+const NOTIFIED:  u8 = 3;
+const DONE:      u8 = 4;
+
+percpu! {
+    // Executor whose worker runs on the CPU, see spawn()
+    static CURRENT: RefCell<Option<ExecutorHandle>> = RefCell::new(None);
+}
+
+/// Executor that polls `Send` tasks on all CPUs online (see smp)
+///
+/// Every CPU has a run queue of its own: spawned tasks go to the queue of the spawning CPU, woken ones
+/// to the queue of the CPU that has polled them last. A CPU that runs out of tasks steals half of
+/// another CPU's queue & halts only if there is nothing to steal. Wakeup IPI gets it going again, once
+/// a task is queued for it (or for a CPU too busy to get to it soon)
+///
+/// Tasks are polled in the order they were queued, scheduling policies are only supported by the
+/// single-core Executor
+pub struct MultiCoreExecutor {
+    shared: Arc<Shared>,
+}
+
+impl MultiCoreExecutor {
+    pub fn new() -> MultiCoreExecutor {
+        MultiCoreExecutor::with_queue_capacity(DEFAULT_QUEUE_CAPACITY)
+    }
+
+    /// `queue_capacity` is how many tasks a CPU's run queue holds. Waking more tasks at once still works,
+    /// but the ones that do not fit are picked up by whichever CPU notices them first
+    pub fn with_queue_capacity(queue_capacity: usize) -> MultiCoreExecutor {
+        // CPUs are started one by one & no further ones once one fails, so those online come first
+        let workers = (0..smp::cpu_count()).map(|cpu| Worker::new(cpu, queue_capacity)).collect();
+
+        MultiCoreExecutor {
+            shared: Arc::new(Shared {
+                workers,
+                tasks:      IrqSpinLock::named("multicore::Shared::tasks", BTreeMap::new()),
+                overflowed: AtomicBool::new(false),
+                shutdown:   AtomicBool::new(false),
+                active:     AtomicUsize::new(0),
+                started:    AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns handle that spawns tasks onto this executor & shuts it down, from any CPU
+    pub fn handle(&self) -> ExecutorHandle {
+        ExecutorHandle { shared: self.shared.clone() }
+    }
+
+    /// Number of CPUs tasks are polled on
+    pub fn cpu_count(&self) -> usize {
+        self.shared.workers.len()
+    }
+
+    /// Queues task to be polled, returned handle resolves with task's output
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F:         Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.shared.spawn(future)
+    }
+
+    /// Returns counters of every CPU, ordered by CPU index
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.shared.workers
+            .iter()
+            .map(|worker| WorkerStats {
+                cpu:    worker.cpu,
+                polls:  worker.polls.load(Ordering::Relaxed),
+                steals: worker.steals.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Drops all outstanding tasks once CPUs have stopped polling them, see ExecutorHandle::shutdown
+    pub fn shutdown(&self) {
+        self.shared.shut_down();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shared.is_shut_down()
+    }
+
+    /// Starts polling tasks on application processors & runs them on the calling CPU forever
+    ///
+    /// Has to be called on the boot CPU. Once executor is shut down, it only sleeps
+    pub fn run(&self) -> ! {
+        let index = self.shared.enter_boot_cpu();
+
+        {
+            let _enter  = enter(self.handle());
+            let _worker = WorkerGuard::new(&self.shared);
+
+            while !self.shared.is_shut_down() {
+                if !self.shared.run_once(index) {
+                    self.shared.sleep_if_idle(index, None);
+                }
+            }
+        }
+
+        hlt_loop();
+    }
+
+    /// Runs tasks (on all CPUs) until `future` completes & returns its output
+    ///
+    /// Future is polled on the calling CPU on its own, so it does not have to be `Send` & could borrow
+    /// from the caller. Application processors keep running tasks once it has completed.
+    /// Has to be called on the boot CPU
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let index = self.shared.enter_boot_cpu();
+
+        let mut future = pin!(future);
+        let main_waker = MainWaker::new();
+        let waker      = Waker::from(main_waker.clone());
+
+        let _enter     = enter(self.handle());
+        let mut worker = Some(WorkerGuard::new(&self.shared));
+
+        loop {
+            // Calling CPU stops polling tasks once executor is shut down, so they could be dropped
+            if self.shared.is_shut_down() {
+                drop(worker.take());
+            }
+
+            if main_waker.woken.swap(false, Ordering::AcqRel) {
+                let mut context = Context::from_waker(&waker);
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            }
+
+            if !self.shared.run_once(index) {
+                self.shared.sleep_if_idle(index, Some(&main_waker.woken));
+            }
+        }
+    }
+}
+
+impl Default for MultiCoreExecutor {
+    fn default() -> MultiCoreExecutor {
+        MultiCoreExecutor::new()
+    }
+}
+
+impl Drop for MultiCoreExecutor {
+    fn drop(&mut self) {
+        // Workers of application processors & wakers outlive executor, they have to stop once it is gone
+        self.shutdown();
+    }
+}
+
+/// Handle of MultiCoreExecutor, could be cloned & sent to any CPU (or task)
+#[derive(Clone)]
+pub struct ExecutorHandle {
+    shared: Arc<Shared>,
+}
+
+impl ExecutorHandle {
+    /// Queues task to be polled, returned handle resolves with task's output
+    ///
+    /// If executor has been shut down, task is dropped & handle resolves with JoinError::Aborted
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F:         Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.shared.spawn(future)
+    }
+
+    /// Stops all CPUs from polling tasks & drops outstanding ones (their JoinHandles resolve with
+    /// JoinError::Aborted) as soon as no CPU polls any. Tasks spawned afterwards are dropped right away
+    pub fn shutdown(&self) {
+        self.shared.shut_down();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shared.is_shut_down()
+    }
+}
+
+/// Counters of a CPU, see MultiCoreExecutor::stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub cpu:    usize,
+    pub polls:  u64,
+    /// How many times the CPU has taken tasks from another CPU's queue
+    pub steals: u64,
+}
+
+/// Spawns task onto the multi-core executor that runs on the calling CPU
+///
+/// Panics if no multi-core executor runs there, task::spawn() is the single-core counterpart
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F:         Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    current()
+        .expect("multicore::spawn called outside of a running multi-core executor")
+        .spawn(future)
+}
+
+/// Returns handle of the multi-core executor running on the calling CPU
+pub fn current() -> Option<ExecutorHandle> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Makes `handle` the one spawn() uses on the calling CPU, until returned guard is dropped
+fn enter(handle: ExecutorHandle) -> EnterGuard {
+    let previous = CURRENT.with(|current| current.replace(Some(handle)));
+    EnterGuard { previous }
+}
+
+struct EnterGuard {
+    previous: Option<ExecutorHandle>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        let entered  = CURRENT.with(|current| current.replace(previous));
+        // Handle is dropped with interrupts enabled, it may be the last reference to executor
+        drop(entered);
+    }
+}
+
+/// Task as workers share it, it doubles as its own waker
+struct TaskCell {
+    id:         TaskId,
+    // See task states above
+    state:      AtomicU8,
+    // Index of the worker that has polled the task last, woken task is queued there
+    home:       AtomicUsize,
+    // Set when woken task has not fit into its home queue, see Shared::overflowed
+    overflowed: AtomicBool,
+    future:     UnsafeCell<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    // Weak, as executor holds its tasks; waking task of a dropped executor does nothing
+    executor:   Weak<Shared>,
+}
+
+// Safe because future is only touched by the worker that has moved task into RUNNING state,
+// or once no worker runs anymore (see Shared::drop_tasks)
+unsafe impl Sync for TaskCell {}
+
+impl Wake for TaskCell {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let executor = match self.executor.upgrade() {
+            Some(executor) => executor,
+            None           => return,
+        };
+        if executor.is_shut_down() {
+            return;
+        }
+
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE    => SCHEDULED,
+                RUNNING => NOTIFIED,
+                // Already queued (or about to be), or completed
+                _       => return,
+            };
+
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_)        => break,
+                Err(current) => state = current,
+            }
+        }
+
+        // Task being polled is queued again by its worker once poll returns
+        if state == IDLE {
+            let home = self.home.load(Ordering::Relaxed);
+            executor.push(home, self.clone());
+            executor.notify(home);
+        }
+    }
+}
+
+/// CPU that polls tasks, together with its run queue
+struct Worker {
+    cpu:    usize,
+    // Bounded, so waking never allocates (e.g. in interrupt handler)
+    queue:  ArrayQueue<Arc<TaskCell>>,
+    // Set while worker halts, so it has to be woken by IPI
+    idle:   AtomicBool,
+    polls:  AtomicU64,
+    steals: AtomicU64,
+}
+
+impl Worker {
+    fn new(cpu: usize, queue_capacity: usize) -> Worker {
+        Worker {
+            cpu,
+            queue:  ArrayQueue::new(queue_capacity),
+            idle:   AtomicBool::new(false),
+            polls:  AtomicU64::new(0),
+            steals: AtomicU64::new(0),
+        }
+    }
+}
+
+/// State shared by executor, its handles, its workers & its tasks (weakly)
+struct Shared {
+    workers:    Vec<Worker>,
+    // Every task that has not completed yet, so shutdown could drop them & overflowed ones could be found
+    tasks:      IrqSpinLock<BTreeMap<TaskId, Arc<TaskCell>>>,
+    // Set when some woken task has not fit into its queue, tasks are found by their own flag then
+    overflowed: AtomicBool,
+    shutdown:   AtomicBool,
+    // Workers running right now, the last one to stop after shutdown drops the tasks
+    active:     AtomicUsize,
+    // Set once workers of application processors have been started
+    started:    AtomicBool,
+}
+
+impl Shared {
+    fn spawn<F>(self: &Arc<Self>, future: F) -> JoinHandle<F::Output>
+    where
+        F:         Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id      = TaskId::new();
+        let state   = Arc::new(JoinState::new());
+        let handle  = JoinHandle::new(id, state.clone());
+        let harness = Harness::new(future, state);
+
+        // Harness resolves the handle with JoinError::Aborted when dropped
+        if self.is_shut_down() {
+            drop(harness);
+            return handle;
+        }
+
+        // Task starts on the spawning CPU, others steal it if they have nothing better to do
+        let home = self.worker_index(percpu::current_cpu()).unwrap_or(0);
+        let task = Arc::new(TaskCell {
+            id,
+            state:      AtomicU8::new(SCHEDULED),
+            home:       AtomicUsize::new(home),
+            overflowed: AtomicBool::new(false),
+            future:     UnsafeCell::new(Some(Box::pin(harness))),
+            executor:   Arc::downgrade(self),
+        });
+
+        self.tasks.lock().insert(id, task.clone());
+        self.push(home, task);
+        self.notify(home);
+
+        handle
+    }
+
+    fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    fn shut_down(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        // Halted workers have to notice it to stop
+        for worker in self.workers.iter().filter(|worker| worker.idle.load(Ordering::SeqCst)) {
+            smp::wake_cpu(worker.cpu);
+        }
+
+        // Otherwise the last worker to stop drops them
+        if self.active.load(Ordering::SeqCst) == 0 {
+            self.drop_tasks();
+        }
+    }
+
+    fn worker_index(&self, cpu: usize) -> Option<usize> {
+        self.workers.iter().position(|worker| worker.cpu == cpu)
+    }
+
+    /// Returns index of the calling CPU's worker & starts workers of application processors (once)
+    fn enter_boot_cpu(self: &Arc<Self>) -> usize {
+        let cpu   = percpu::current_cpu();
+        let index = self.worker_index(cpu).filter(|_| cpu == 0).expect("multi-core executor has to run on the boot CPU");
+
+        if !self.started.swap(true, Ordering::AcqRel) {
+            for (worker_index, worker) in self.workers.iter().enumerate().filter(|(worker_index, _)| *worker_index != index) {
+                let shared = self.clone();
+
+                // Tasks queued for worker that has not started are stolen by others
+                if let Err(err) = smp::run_on(worker.cpu, move || shared.run_worker(worker_index)) {
+                    warn!("CPU {} does not run tasks: {:?}", worker.cpu, err);
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Worker loop of an application processor, returns once executor is shut down
+    fn run_worker(self: Arc<Self>, index: usize) {
+        let _enter  = enter(ExecutorHandle { shared: self.clone() });
+        let _worker = WorkerGuard::new(&self);
+
+        while !self.is_shut_down() {
+            if !self.run_once(index) {
+                self.sleep_if_idle(index, None);
+            }
+        }
+    }
+
+    /// Polls one task, returns false if there was nothing to poll (or executor is shut down)
+    fn run_once(&self, index: usize) -> bool {
+        // Work deferred by interrupt handlers goes first, as it is likely to wake some tasks
+        deferred::run_pending();
+
+        if self.is_shut_down() {
+            return false;
+        }
+
+        match self.next_task(index) {
+            Some(task) => {
+                self.poll(index, task);
+                true
+            }
+            None       => false,
+        }
+    }
+
+    fn next_task(&self, index: usize) -> Option<Arc<TaskCell>> {
+        if self.overflowed.swap(false, Ordering::AcqRel) {
+            self.take_overflowed(index);
+        }
+
+        self.workers[index].queue.pop().ok().or_else(|| self.steal(index))
+    }
+
+    /// Moves tasks that have not fit into their queues to the worker's queue
+    fn take_overflowed(&self, index: usize) {
+        let tasks = self.tasks.lock()
+            .values()
+            .filter(|task| task.overflowed.swap(false, Ordering::AcqRel))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Ones that do not fit again are marked again
+        for task in tasks {
+            self.push(index, task);
+        }
+    }
+
+    /// Takes half of the first non-empty queue of another worker, returns one of the tasks taken
+    fn steal(&self, index: usize) -> Option<Arc<TaskCell>> {
+        let count = self.workers.len();
+
+        for victim in (1..count).map(|offset| &self.workers[(index + offset) % count]) {
+            let first = match victim.queue.pop() {
+                Ok(task) => task,
+                Err(_)   => continue,
+            };
+
+            for _ in 0..victim.queue.len() / 2 {
+                match victim.queue.pop() {
+                    Ok(task) => self.push(index, task),
+                    Err(_)   => break,
+                }
+            }
+
+            self.workers[index].steals.fetch_add(1, Ordering::Relaxed);
+            return Some(first);
+        }
+        None
+    }
+
+    fn poll(&self, index: usize, task: Arc<TaskCell>) {
+        if task.state.compare_exchange(SCHEDULED, RUNNING, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            // Dropped by shutdown meanwhile
+            return;
+        }
+
+        let worker = &self.workers[index];
+        worker.polls.fetch_add(1, Ordering::Relaxed);
+        task.home.store(index, Ordering::Relaxed);
+
+        let waker       = Waker::from(task.clone());
+        let mut context = Context::from_waker(&waker);
+
+        // Safe because task is RUNNING, thus no other worker touches its future
+        let future = unsafe { &mut *task.future.get() };
+        let ready  = match future.as_mut() {
+            Some(future) => future.as_mut().poll(&mut context).is_ready(),
+            None         => true,
+        };
+
+        if ready {
+            // Task has been completed - drop its future & forget it
+            *future = None;
+            task.state.store(DONE, Ordering::Release);
+
+            let removed = self.tasks.lock().remove(&task.id);
+            drop(removed);
+            return;
+        }
+
+        // Woken while being polled: it is this worker's to queue again
+        if task.state.compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            task.state.store(SCHEDULED, Ordering::Release);
+            self.push(index, task);
+        }
+    }
+
+    /// Queues scheduled task to given worker, or marks it as overflowed if the queue is full
+    fn push(&self, index: usize, task: Arc<TaskCell>) {
+        if let Err(PushError(task)) = self.workers[index].queue.push(task) {
+            task.overflowed.store(true, Ordering::Release);
+            self.overflowed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Makes sure some worker gets to the task just queued to worker at `index`
+    fn notify(&self, index: usize) {
+        // Queue has to be visible to the worker before its idle flag is checked, see sleep_if_idle
+        fence(Ordering::SeqCst);
+
+        let cpu    = percpu::current_cpu();
+        let target = &self.workers[index];
+        if target.idle.load(Ordering::SeqCst) {
+            // Idle worker of the calling CPU has been interrupted, it finds the task once handler returns
+            if target.cpu != cpu {
+                smp::wake_cpu(target.cpu);
+            }
+            return;
+        }
+
+        // Target is busy (calling CPU included), some idle worker could steal the task meanwhile
+        let idle = self.workers.iter().find(|worker| worker.cpu != cpu && worker.idle.load(Ordering::SeqCst));
+        if let Some(worker) = idle {
+            smp::wake_cpu(worker.cpu);
+        }
+    }
+
+    /// Halts until the next interrupt (e.g. wakeup IPI), unless there is something to do
+    fn sleep_if_idle(&self, index: usize, main_woken: Option<&AtomicBool>) {
+        let worker = &self.workers[index];
+
+        disable();
+        worker.idle.store(true, Ordering::SeqCst);
+        // Whoever queues a task checks idle flag after the queue, so either it sees the flag or this sees the task
+        fence(Ordering::SeqCst);
+
+        // Once executor is shut down, workers of application processors stop & block_on only waits for its future
+        let tasks_ready = if self.is_shut_down() {
+            main_woken.is_none()
+        } else {
+            self.overflowed.load(Ordering::Acquire) || self.workers.iter().any(|worker| !worker.queue.is_empty())
+        };
+        let has_work = tasks_ready
+            || deferred::has_pending()
+            || main_woken.filter(|woken| woken.load(Ordering::Acquire)).is_some();
+
+        if has_work {
+            enable();
+        } else {
+            enable_and_hlt();
+        }
+        worker.idle.store(false, Ordering::SeqCst);
+    }
+
+    /// Drops all tasks (their JoinHandles resolve with JoinError::Aborted) & everything queued for them
+    ///
+    /// Must only be called once no worker runs
+    fn drop_tasks(&self) {
+        let tasks = mem::take(&mut *self.tasks.lock());
+
+        for task in tasks.values() {
+            task.state.store(DONE, Ordering::Release);
+            // Safe because no worker runs, thus nobody polls the task. Future is dropped with no lock held,
+            // as it may wake or spawn others
+            let future = unsafe { (*task.future.get()).take() };
+            drop(future);
+        }
+
+        for worker in self.workers.iter() {
+            while worker.queue.pop().is_ok() {}
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Tasks spawned while the last worker was dropping the others
+        self.drop_tasks();
+    }
+}
+
+/// Counts worker as running, the last one to stop after shutdown drops the tasks
+struct WorkerGuard<'a> {
+    shared: &'a Shared,
+}
+
+impl<'a> WorkerGuard<'a> {
+    fn new(shared: &'a Shared) -> WorkerGuard<'a> {
+        shared.active.fetch_add(1, Ordering::SeqCst);
+        WorkerGuard { shared }
+    }
+}
+
+impl Drop for WorkerGuard<'_> {
+    fn drop(&mut self) {
+        let last = self.shared.active.fetch_sub(1, Ordering::SeqCst) == 1;
+
+        if last && self.shared.is_shut_down() {
+            self.shared.drop_tasks();
+        }
+    }
+}
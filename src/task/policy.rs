@@ -0,0 +1,205 @@
+use alloc::{
+    boxed::Box,
+    collections::{ BinaryHeap, VecDeque },
+    vec::Vec,
+};
+use core::cmp::Reverse;
+
+use crate::task::TaskId;
+
+/// How many picks a waiting task has to sit through before its priority goes up by one
+pub const DEFAULT_AGING_ROUNDS: u64 = 8;
+
+/// Importance of a task, higher runs first (only fixed-priority policy cares)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    pub const LOW:    Priority = Priority(0);
+    pub const NORMAL: Priority = Priority(128);
+    pub const HIGH:   Priority = Priority(255);
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::NORMAL
+    }
+}
+
+/// What policy knows about a task when it becomes ready
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedParams {
+    pub priority: Priority,
+    /// Absolute deadline, in timer ticks (see time::ticks)
+    pub deadline: Option<u64>,
+}
+
+/// Decides in which order ready tasks are polled
+///
+/// Executor hands every woken task to `enqueue` & polls whatever `pick_next` returns,
+/// one task at a time, so newly woken tasks are taken into account right after the current poll
+pub trait SchedulingPolicy {
+    fn name(&self) -> &'static str;
+
+    /// Task has become ready, it may already be queued if it was woken more than once
+    fn enqueue(&mut self, task: TaskId, params: SchedParams);
+
+    /// Removes task that should be polled next, None if nothing is ready
+    fn pick_next(&mut self) -> Option<TaskId>;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// Policies that could be selected at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyKind {
+    RoundRobin,
+    FixedPriority,
+    EarliestDeadline,
+}
+
+impl PolicyKind {
+    pub fn create(self) -> Box<dyn SchedulingPolicy> {
+        match self {
+            PolicyKind::RoundRobin       => Box::new(RoundRobin::new()),
+            PolicyKind::FixedPriority    => Box::new(FixedPriority::new(DEFAULT_AGING_ROUNDS)),
+            PolicyKind::EarliestDeadline => Box::new(EarliestDeadline::new()),
+        }
+    }
+}
+
+/// Tasks are polled in the order they were woken
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    queue: VecDeque<TaskId>,
+}
+
+impl RoundRobin {
+    pub fn new() -> RoundRobin {
+        RoundRobin { queue: VecDeque::new() }
+    }
+}
+
+impl SchedulingPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn enqueue(&mut self, task: TaskId, _params: SchedParams) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        self.queue.pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct Waiting {
+    task:     TaskId,
+    priority: Priority,
+    // Value of `round` when task was enqueued
+    since:    u64,
+}
+
+/// Task with the highest priority is polled first, ties are broken in wake order
+///
+/// Priority of waiting task goes up by one every `aging_rounds` picks, so even
+/// the lowest priority task gets polled eventually, however busy the others are
+#[derive(Debug)]
+pub struct FixedPriority {
+    waiting:      Vec<Waiting>,
+    round:        u64,
+    aging_rounds: u64,
+}
+
+impl FixedPriority {
+    pub fn new(aging_rounds: u64) -> FixedPriority {
+        FixedPriority {
+            waiting:      Vec::new(),
+            round:        0,
+            aging_rounds: aging_rounds.max(1),
+        }
+    }
+
+    fn effective_priority(&self, waiting: &Waiting) -> u64 {
+        u64::from(waiting.priority.0) + (self.round - waiting.since) / self.aging_rounds
+    }
+}
+
+impl SchedulingPolicy for FixedPriority {
+    fn name(&self) -> &'static str {
+        "fixed-priority"
+    }
+
+    fn enqueue(&mut self, task: TaskId, params: SchedParams) {
+        self.waiting.push(Waiting { task, priority: params.priority, since: self.round });
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        // Queue only holds tasks that are ready right now, so linear scan is cheap enough.
+        // Waiting tasks are in wake order, thus the first one wins a tie
+        let (index, _) = self.waiting
+            .iter()
+            .enumerate()
+            .max_by_key(|&(index, waiting)| (self.effective_priority(waiting), Reverse(index)))?;
+
+        self.round += 1;
+        Some(self.waiting.remove(index).task)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+}
+
+/// Task with the earliest deadline is polled first, tasks without deadline
+/// only run once no task with deadline is ready (in wake order)
+#[derive(Debug, Default)]
+pub struct EarliestDeadline {
+    // (deadline, wake order) - wake order breaks ties, so equal deadlines are served first come first
+    with_deadline: BinaryHeap<Reverse<(u64, u64, TaskId)>>,
+    background:    VecDeque<TaskId>,
+    woken:         u64,
+}
+
+impl EarliestDeadline {
+    pub fn new() -> EarliestDeadline {
+        EarliestDeadline {
+            with_deadline: BinaryHeap::new(),
+            background:    VecDeque::new(),
+            woken:         0,
+        }
+    }
+}
+
+impl SchedulingPolicy for EarliestDeadline {
+    fn name(&self) -> &'static str {
+        "earliest-deadline-first"
+    }
+
+    fn enqueue(&mut self, task: TaskId, params: SchedParams) {
+        match params.deadline {
+            Some(deadline) => {
+                self.with_deadline.push(Reverse((deadline, self.woken, task)));
+                self.woken += 1;
+            }
+            None           => self.background.push_back(task),
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        match self.with_deadline.pop() {
+            Some(Reverse((_, _, task))) => Some(task),
+            None                        => self.background.pop_front(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.with_deadline.is_empty() && self.background.is_empty()
+    }
+}
@@ -0,0 +1,122 @@
+use alloc::{
+    collections::VecDeque,
+    rc::Rc,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    mem,
+};
+
+use crate::{
+    percpu,
+    task::{
+        join::JoinHandle,
+        table::TaskTable,
+        waker::{ TaskWaker, WakeQueue },
+        RawTask,
+        Task,
+        TaskInfo,
+    },
+};
+
+percpu! {
+    // Spawner of the executor that is running tasks on this CPU right now. It belongs to the running thread:
+    // scheduler keeps it on the thread's stack while other threads run (see suspend() & resume())
+    static CURRENT: Slot = Slot(RefCell::new(None));
+}
+
+/// Spawner is not Send, as tasks are not. Slot of a CPU is only touched on that CPU (see PerCpu::with) & only holds
+/// spawner of the thread running there, so spawner never leaves the thread that has entered its executor
+struct Slot(RefCell<Option<Spawner>>);
+
+unsafe impl Send for Slot {}
+
+/// Handle that spawns tasks onto executor it was obtained from, could be cloned & moved into tasks
+///
+/// Tasks are queued & picked up by executor the next time it looks for ready tasks
+#[derive(Clone)]
+pub struct Spawner {
+    queue:      Rc<RefCell<VecDeque<RawTask>>>,
+    // Executor's wake queue: new task is woken right away, so it is polled in the order it has been spawned
+    // relative to tasks woken meanwhile
+    wake_queue: Arc<WakeQueue>,
+    // Executor's task table: new task is listed (together with its waker) as soon as it is spawned
+    table:      Rc<TaskTable>,
+}
+
+impl Spawner {
+    pub(super) fn new(wake_queue: Arc<WakeQueue>, table: Rc<TaskTable>) -> Spawner {
+        Spawner {
+            queue: Rc::new(RefCell::new(VecDeque::new())),
+            wake_queue,
+            table,
+        }
+    }
+
+    /// Queues task to be polled, returned handle resolves with task's output
+    ///
+    /// If executor has been shut down, task is dropped & handle resolves with JoinError::Aborted
+    pub fn spawn<T>(&self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+
+        if self.wake_queue.is_shut_down() {
+            drop(task);
+            return handle;
+        }
+
+        // Executor adds queued tasks before it looks at woken ones, so the task is there by the time its waker is popped
+        let waker = TaskWaker::new(task.id, self.wake_queue.clone());
+        self.table.insert(task.id, task.name, task.params, waker.clone());
+        self.queue.borrow_mut().push_back(task);
+        waker.wake_task();
+
+        handle
+    }
+
+    /// Takes all queued tasks, so tasks could be spawned while executor adds the taken ones
+    pub(super) fn take_queued(&self) -> VecDeque<RawTask> {
+        mem::take(&mut *self.queue.borrow_mut())
+    }
+
+    /// Returns snapshot of executor's tasks, same as Executor::tasks
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.table.snapshot()
+    }
+}
+
+/// Makes `spawner` the one task::spawn() uses on this thread, until returned guard is dropped
+pub(super) fn enter(spawner: &Spawner) -> EnterGuard {
+    let previous = CURRENT.with(|current| current.0.replace(Some(spawner.clone())));
+
+    EnterGuard { previous }
+}
+
+/// Restores spawner that was current before enter()
+pub(super) struct EnterGuard {
+    previous: Option<Spawner>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let entered = CURRENT.with(|current| current.0.replace(self.previous.take()));
+        // Spawner is dropped with interrupts enabled, it may be the last reference to queued tasks
+        drop(entered);
+    }
+}
+
+/// Returns spawner of the executor running on this thread, None if called outside of a task
+pub fn current() -> Option<Spawner> {
+    CURRENT.with(|current| current.0.borrow().clone())
+}
+
+/// Takes spawner of the thread that is switched away from, scheduler keeps it until the thread runs again
+pub(crate) fn suspend() -> Option<Spawner> {
+    CURRENT.with(|current| current.0.take())
+}
+
+/// Gives spawner taken by suspend() back to its thread, once the thread is switched back to
+pub(crate) fn resume(spawner: Option<Spawner>) {
+    CURRENT.with(|current| current.0.replace(spawner));
+}
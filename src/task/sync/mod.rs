@@ -0,0 +1,65 @@
+// Async-aware locks: waiting tasks are parked via their wakers instead of spinning,
+// so a lock could be held across `.await` without blocking the executor
+//
+// All of them are fair - waiters are served in the order they started waiting
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+
+pub use self::{
+    mutex::{ Mutex, MutexGuard },
+    notify::{ Notified, Notify },
+    rwlock::{ RwLock, RwLockReadGuard, RwLockWriteGuard, MAX_READERS },
+    semaphore::{ Acquire, Semaphore, SemaphorePermit },
+};
+
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    task::Waker,
+};
+use spin::Mutex as SpinMutex;
+
+/// Task waiting in a queue of one of the primitives
+///
+/// Waiter is granted (and woken) by whoever releases the primitive, while the queue lock is held,
+/// so checking `is_granted` under the same lock never misses a wakeup
+struct Waiter {
+    // Number of permits waiter asks for (Notify does not use it)
+    needed:  usize,
+    granted: AtomicBool,
+    waker:   SpinMutex<Option<Waker>>,
+}
+
+impl Waiter {
+    fn new(needed: usize, waker: &Waker) -> Arc<Waiter> {
+        Arc::new(Waiter {
+            needed,
+            granted: AtomicBool::new(false),
+            waker:   SpinMutex::new(Some(waker.clone())),
+        })
+    }
+
+    fn is_granted(&self) -> bool {
+        self.granted.load(Ordering::Acquire)
+    }
+
+    /// Marks waiter granted, returns waker to be called once queue lock is released
+    fn grant(&self) -> Option<Waker> {
+        self.granted.store(true, Ordering::Release);
+        self.waker.lock().take()
+    }
+
+    /// Task may be polled with another waker, e.g. if it has been moved into another task
+    fn set_waker(&self, waker: &Waker) {
+        let mut current = self.waker.lock();
+        if current.as_ref().filter(|current| current.will_wake(waker)).is_none() {
+            *current = Some(waker.clone());
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    ops::{ Deref, DerefMut },
+};
+
+use super::semaphore::{ Semaphore, SemaphorePermit };
+
+/// Mutex that could be held across `.await`: tasks waiting for it are parked, not spinning
+pub struct Mutex<T> {
+    semaphore: Semaphore,
+    value:     UnsafeCell<T>,
+}
+
+// Same bounds as for spin::Mutex: access to the value is serialised by the semaphore
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            value:     UnsafeCell::new(value),
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+        MutexGuard { mutex: self, _permit: permit, _marker: PhantomData }
+    }
+
+    /// Locks mutex if it is free & nobody waits for it already
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+        Some(MutexGuard { mutex: self, _permit: permit, _marker: PhantomData })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Exclusive reference proves nobody else holds the lock
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("value", &*guard).finish(),
+            None        => f.debug_struct("Mutex").field("value", &"<locked>").finish(),
+        }
+    }
+}
+
+/// Unlocks mutex once dropped
+pub struct MutexGuard<'a, T> {
+    mutex:   &'a Mutex<T>,
+    _permit: SemaphorePermit<'a>,
+    // Guard hands out &T, so it must only be Sync if T is (&Mutex<T> alone would be Sync for any T: Send)
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe because guard holds the only permit
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
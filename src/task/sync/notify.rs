@@ -0,0 +1,170 @@
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+use spin::Mutex as SpinMutex;
+
+use super::Waiter;
+
+struct State {
+    // Set by notify_one() that has found nobody waiting, consumed by the next notified()
+    permit:  bool,
+    waiters: VecDeque<Arc<Waiter>>,
+    // Waiters granted by notify_one() that have not completed yet, their notification is passed on if they are dropped
+    handed:  Vec<Arc<Waiter>>,
+}
+
+impl State {
+    /// Grants the waiter that has been waiting the longest (or stores permit), returns waker to be called
+    /// once lock is released
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.waiters.pop_front() {
+            Some(waiter) => {
+                let waker = waiter.grant();
+                self.handed.push(waiter);
+                waker
+            }
+            None         => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+
+    /// Forgets that waiter was granted by notify_one(), returns whether it was
+    fn take_handed(&mut self, waiter: &Arc<Waiter>) -> bool {
+        match self.handed.iter().position(|handed| Arc::ptr_eq(handed, waiter)) {
+            Some(index) => {
+                self.handed.swap_remove(index);
+                true
+            }
+            None        => false,
+        }
+    }
+}
+
+/// Lets task wait until another one (or an interrupt handler's deferred work) tells it to go on
+pub struct Notify {
+    state: SpinMutex<State>,
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify {
+            state: SpinMutex::new(State {
+                permit:  false,
+                waiters: VecDeque::new(),
+                handed:  Vec::new(),
+            }),
+        }
+    }
+
+    /// Wakes the task that has been waiting the longest. If nobody waits, the next
+    /// notified() completes right away (notifications are not counted, though)
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().notify_one();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wakes every task that waits right now, nothing is stored for the ones that start waiting later
+    pub fn notify_waiters(&self) {
+        let wakers: Vec<_> = {
+            let mut state = self.state.lock();
+            state.waiters
+                .drain(..)
+                .filter_map(|waiter| waiter.grant())
+                .collect()
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Completes once task is notified. Notification of notify_one() received by Notified that is dropped
+    /// before being polled again is passed on to the next waiter (or kept for the next notified())
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Notify {
+        Notify::new()
+    }
+}
+
+/// Future returned by Notify::notified
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    // Set once task has joined the queue
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let notify    = self.notify;
+        let mut state = notify.state.lock();
+
+        match &self.waiter {
+            Some(waiter) if waiter.is_granted() => {
+                state.take_handed(waiter);
+            }
+            Some(waiter)                        => {
+                waiter.set_waker(context.waker());
+                return Poll::Pending;
+            }
+            None if state.permit                => state.permit = false,
+            None                                => {
+                let waiter = Waiter::new(0, context.waker());
+                state.waiters.push_back(waiter.clone());
+                self.waiter = Some(waiter);
+                return Poll::Pending;
+            }
+        }
+
+        self.waiter = None;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None         => return,
+        };
+
+        let waker = {
+            let mut state = self.notify.state.lock();
+            if state.take_handed(&waiter) {
+                state.notify_one()
+            } else {
+                state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter));
+                None
+            }
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{ Deref, DerefMut },
+};
+
+use super::semaphore::{ Semaphore, SemaphorePermit };
+
+/// Maximum number of readers that could hold RwLock at once
+pub const MAX_READERS: usize = 32;
+
+/// Reader-writer lock that could be held across `.await`
+///
+/// Reader takes one permit of the semaphore, writer takes all of them. As semaphore is fair,
+/// writer waiting for the lock stops new readers from getting in, so it never starves
+pub struct RwLock<T> {
+    semaphore: Semaphore,
+    value:     UnsafeCell<T>,
+}
+
+// Same bounds as for spin::RwLock: readers on different tasks share &T
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> RwLock<T> {
+        RwLock {
+            semaphore: Semaphore::new(MAX_READERS),
+            value:     UnsafeCell::new(value),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+        RwLockReadGuard { lock: self, _permit: permit }
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let permit = self.semaphore.acquire_many(MAX_READERS).await;
+        RwLockWriteGuard { lock: self, _permit: permit }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+        Some(RwLockReadGuard { lock: self, _permit: permit })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire_many(MAX_READERS)?;
+        Some(RwLockWriteGuard { lock: self, _permit: permit })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Shared access, released once dropped
+pub struct RwLockReadGuard<'a, T> {
+    lock:    &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe because writer could not get all permits while this one is held
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+/// Exclusive access, released once dropped
+pub struct RwLockWriteGuard<'a, T> {
+    lock:    &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe because guard holds every permit
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
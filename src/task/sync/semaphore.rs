@@ -0,0 +1,197 @@
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+use spin::Mutex as SpinMutex;
+
+use super::Waiter;
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl State {
+    /// Hands permits over to waiters from the front of the queue, for as long as there are enough of them.
+    /// Returns wakers of granted waiters
+    fn grant_waiters(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.needed > self.permits {
+                break;
+            }
+
+            self.permits -= waiter.needed;
+            wakers.extend(waiter.grant());
+            self.waiters.pop_front();
+        }
+        wakers
+    }
+}
+
+/// Counting semaphore, tasks wait for permits in FIFO order
+///
+/// Permits are handed over to waiting task directly on release, so a task that has just
+/// started to wait could not jump the queue
+pub struct Semaphore {
+    state: SpinMutex<State>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: SpinMutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Number of permits that could be acquired right now
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits until `permits` permits are available & takes them all at once
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter:    None,
+        }
+    }
+
+    /// Takes a permit if it is available & nobody waits for it already
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if !state.waiters.is_empty() || state.permits < permits {
+            return None;
+        }
+
+        state.permits -= permits;
+        Some(SemaphorePermit { semaphore: self, permits })
+    }
+
+    /// Adds permits, e.g. to return the ones that have been forgotten
+    pub fn add_permits(&self, permits: usize) {
+        let wakers = {
+            let mut state = self.state.lock();
+            state.permits += permits;
+            state.grant_waiters()
+        };
+
+        // Wakers are called with lock released, as they may poll waiting task right away
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Permits taken from semaphore, they are given back once permit is dropped
+#[must_use = "permits are released right away if permit is not held"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits:   usize,
+}
+
+impl SemaphorePermit<'_> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keeps permits taken, they could only be given back with Semaphore::add_permits
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+/// Future returned by Semaphore::acquire
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits:   usize,
+    // Set once task has joined the queue
+    waiter:    Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let semaphore = self.semaphore;
+        let permits   = self.permits;
+        let mut state = semaphore.state.lock();
+
+        match &self.waiter {
+            Some(waiter) if waiter.is_granted() => {}
+            Some(waiter)                        => {
+                waiter.set_waker(context.waker());
+                return Poll::Pending;
+            }
+            None if state.waiters.is_empty() && state.permits >= permits => {
+                state.permits -= permits;
+            }
+            None                                => {
+                let waiter = Waiter::new(permits, context.waker());
+                state.waiters.push_back(waiter.clone());
+                self.waiter = Some(waiter);
+                return Poll::Pending;
+            }
+        }
+
+        // Permits now belong to the returned guard
+        self.waiter = None;
+        Poll::Ready(SemaphorePermit { semaphore, permits })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None         => return,
+        };
+
+        let wakers = {
+            let mut state = self.semaphore.state.lock();
+            if waiter.is_granted() {
+                // Permits have been handed over, but nobody is going to take them
+                state.permits += waiter.needed;
+            } else {
+                state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter));
+            }
+            // Waiter that has left could have been the one blocking the queue
+            state.grant_waiters()
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
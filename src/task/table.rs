@@ -0,0 +1,128 @@
+use alloc::{
+    collections::BTreeMap,
+    rc::Rc,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    task::Waker,
+};
+
+use crate::task::{
+    policy::SchedParams,
+    waker::TaskWaker,
+    TaskId,
+    TaskInfo,
+    TaskState,
+};
+
+struct Entry {
+    name:         Option<&'static str>,
+    params:       SchedParams,
+    waker:        Arc<TaskWaker>,
+    polls:        u64,
+    poll_time_ns: u64,
+    // Task is being polled right now
+    running:      bool,
+    // Task has been handed to scheduling policy & waits to be picked
+    ready:        bool,
+}
+
+/// Executor's record of its tasks, shared with its spawners, so tasks could be listed from within a task
+///
+/// Executor never holds a borrow while polling, so tasks are free to spawn & take snapshots
+pub(super) struct TaskTable {
+    entries: RefCell<BTreeMap<TaskId, Entry>>,
+}
+
+impl TaskTable {
+    pub(super) fn new() -> Rc<TaskTable> {
+        Rc::new(TaskTable { entries: RefCell::new(BTreeMap::new()) })
+    }
+
+    pub(super) fn insert(&self, id: TaskId, name: Option<&'static str>, params: SchedParams, waker: Arc<TaskWaker>) {
+        let entry = Entry {
+            name,
+            params,
+            waker,
+            polls:        0,
+            poll_time_ns: 0,
+            running:      false,
+            ready:        false,
+        };
+
+        if self.entries.borrow_mut().insert(id, entry).is_some() {
+            panic!("task with same ID already exists");
+        }
+    }
+
+    /// Removes task's entry, returns its waker so caller decides where it is dropped
+    pub(super) fn remove(&self, id: TaskId) -> Option<Arc<TaskWaker>> {
+        self.entries.borrow_mut().remove(&id).map(|entry| entry.waker)
+    }
+
+    pub(super) fn clear(&self) {
+        // Entries are taken out first, so they are dropped with table not borrowed
+        let entries = self.entries.take();
+        drop(entries);
+    }
+
+    pub(super) fn set_ready(&self, id: TaskId) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(&id) {
+            entry.ready = true;
+        }
+    }
+
+    /// Takes every task that has been woken but not popped from wake queue (see WakeQueue)
+    pub(super) fn take_woken(&self) -> Vec<TaskId> {
+        let mut woken = Vec::new();
+
+        for (id, entry) in self.entries.borrow().iter() {
+            if entry.waker.take_queued() {
+                woken.push(*id);
+            }
+        }
+        woken
+    }
+
+    /// Marks task as running, returns waker it should be polled with
+    pub(super) fn start_poll(&self, id: TaskId) -> Option<Waker> {
+        let mut entries = self.entries.borrow_mut();
+        let entry       = entries.get_mut(&id)?;
+
+        entry.running = true;
+        entry.ready   = false;
+        Some(Waker::from(entry.waker.clone()))
+    }
+
+    pub(super) fn finish_poll(&self, id: TaskId, elapsed_ns: u64) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(&id) {
+            entry.running       = false;
+            entry.polls        += 1;
+            entry.poll_time_ns += elapsed_ns;
+        }
+    }
+
+    /// Returns snapshot of all tasks, ordered by ID
+    pub(super) fn snapshot(&self) -> Vec<TaskInfo> {
+        self.entries.borrow()
+            .iter()
+            .map(|(id, entry)| TaskInfo {
+                id:            *id,
+                name:          entry.name,
+                state:         if entry.running {
+                    TaskState::Running
+                } else if entry.ready || entry.waker.is_queued() {
+                    TaskState::Ready
+                } else {
+                    TaskState::Waiting
+                },
+                params:        entry.params,
+                polls:         entry.polls,
+                poll_time_ns:  entry.poll_time_ns,
+                last_woken_ns: entry.waker.last_woken_ns(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,131 @@
+use alloc::{
+    sync::Arc,
+    task::Wake,
+};
+use core::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use crossbeam_queue::ArrayQueue;
+
+use crate::{
+    task::TaskId,
+    time,
+};
+
+/// Wake queue executor drains, shared with wakers of its tasks & its spawners
+///
+/// Task is queued at most once until executor pops it (see TaskWaker), so ring only overflows if more tasks
+/// than it could hold are woken at once. Tasks that do not fit stay marked as queued & executor finds them
+/// by that flag, thus waking never fails nor allocates (e.g. in interrupt handler)
+pub(super) struct WakeQueue {
+    ring:       ArrayQueue<Arc<TaskWaker>>,
+    // Set when woken task did not fit into ring
+    overflowed: AtomicBool,
+    // Set once executor is shut down, nothing is queued from then on
+    shutdown:   AtomicBool,
+}
+
+impl WakeQueue {
+    pub(super) fn new(capacity: usize) -> Arc<WakeQueue> {
+        Arc::new(WakeQueue {
+            ring:       ArrayQueue::new(capacity),
+            overflowed: AtomicBool::new(false),
+            shutdown:   AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the next woken task's waker, its queued flag is still set (see TaskWaker::take_queued)
+    pub(super) fn pop(&self) -> Option<Arc<TaskWaker>> {
+        self.ring.pop().ok()
+    }
+
+    /// Whether some woken task has not fit into ring since the last call
+    pub(super) fn take_overflowed(&self) -> bool {
+        self.overflowed.swap(false, Ordering::AcqRel)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.ring.is_empty() && !self.overflowed.load(Ordering::Acquire)
+    }
+
+    pub(super) fn shut_down(&self) {
+        self.shutdown.store(true, Ordering::Release);
+    }
+
+    pub(super) fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Forgets all woken tasks
+    pub(super) fn clear(&self) {
+        while self.ring.pop().is_ok() {}
+        self.overflowed.store(false, Ordering::Release);
+    }
+
+    fn push(&self, waker: Arc<TaskWaker>) {
+        if self.ring.push(waker).is_err() {
+            self.overflowed.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Waker of a single task, created when task is spawned
+pub(super) struct TaskWaker {
+    task_id:    TaskId,
+    // Set from wake until executor takes the task, so task is queued at most once no matter how often it is woken
+    queued:     AtomicBool,
+    // Uptime (see time::uptime_ns) of the last wake that has queued the task
+    last_woken: AtomicU64,
+    queue:      Arc<WakeQueue>,
+}
+
+impl TaskWaker {
+    pub(super) fn new(task_id: TaskId, queue: Arc<WakeQueue>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
+            task_id,
+            queued:     AtomicBool::new(false),
+            last_woken: AtomicU64::new(0),
+            queue,
+        })
+    }
+
+    pub(super) fn task_id(&self) -> TaskId {
+        self.task_id
+    }
+
+    /// Whether task has been woken & not taken by executor yet
+    pub(super) fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+
+    pub(super) fn last_woken_ns(&self) -> u64 {
+        self.last_woken.load(Ordering::Relaxed)
+    }
+
+    /// Clears queued flag, returns whether it has been set, i.e. task has been woken & not taken yet
+    ///
+    /// Flag is cleared before task is polled, so wake during poll queues the task again
+    pub(super) fn take_queued(&self) -> bool {
+        self.queued.swap(false, Ordering::AcqRel)
+    }
+
+    pub(super) fn wake_task(self: &Arc<Self>) {
+        // Task is gone together with executor's queue consumer, so there is nothing to wake
+        if self.queue.is_shut_down() {
+            return;
+        }
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.last_woken.store(time::uptime_ns(), Ordering::Relaxed);
+        self.queue.push(self.clone());
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
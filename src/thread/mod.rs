@@ -0,0 +1,339 @@
+pub mod scheduler;
+
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use core::{
+    arch::global_asm,
+    cell::Cell,
+    fmt,
+    sync::atomic::{
+        AtomicBool,
+        AtomicU64,
+        Ordering,
+    },
+};
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::{ self, without_interrupts },
+    structures::paging::{ mapper::MapToError, FrameAllocator, Mapper, Size4KiB },
+    VirtAddr,
+};
+
+use crate::{
+    memory::{ self, StackBounds },
+    percpu,
+    syscall,
+    task::spawner,
+};
+use self::scheduler::Scheduler;
+
+/// Size of the stack every spawned thread gets (in pages)
+pub const THREAD_STACK_PAGES: u64 = 8;
+/// Time slice used unless init() is told otherwise (in timer ticks)
+pub const DEFAULT_TIME_SLICE: u64 = 5;
+
+// Scheduler is used by timer interrupt, thus it must only be locked with interrupts disabled
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+static INITIALISED: AtomicBool = AtomicBool::new(false);
+// Set by timer interrupt once current thread has used up its time slice
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+static TIME_SLICE:   AtomicU64  = AtomicU64::new(DEFAULT_TIME_SLICE);
+static SLICE_LEFT:   AtomicU64  = AtomicU64::new(DEFAULT_TIME_SLICE);
+
+percpu! {
+    // Thread running on the CPU, so current() does not have to lock the scheduler. Application processors
+    // do not run threads (see smp), they keep running the code they were started with, i.e. their boot thread
+    static CURRENT: Cell<ThreadId> = Cell::new(ThreadId::BOOT);
+}
+
+// context_switch(old_rsp: *mut u64, new_rsp: u64)
+//   Saves callee-saved registers on the current stack, stores stack pointer into `old_rsp`,
+//   then loads `new_rsp` & restores registers saved there. Caller-saved registers are taken care of
+//   by the compiler, as for any other call
+//
+// thread_trampoline is where new threads "return" to on their first switch, see Thread::new
+global_asm!(r#"
+.global context_switch
+context_switch:
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+
+    mov rsp, rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    ret
+
+.global thread_trampoline
+thread_trampoline:
+    call thread_start
+    ud2
+"#);
+
+extern "C" {
+    fn context_switch(old_rsp: *mut u64, new_rsp: u64);
+    fn thread_trampoline();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// Thread that called init(), i.e. the one kernel has booted on
+    pub const BOOT: ThreadId = ThreadId(0);
+
+    fn new() -> ThreadId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting in run queue
+    Ready,
+    /// On CPU right now
+    Running,
+    /// Returned from its entry function, its stack is reused once scheduler switches away from it
+    Finished,
+}
+
+/// Kernel thread: its own stack plus registers saved when it was switched away from
+pub struct Thread {
+    id:    ThreadId,
+    name:  &'static str,
+    state: ThreadState,
+    // Stack pointer saved by context_switch, only valid while thread is not running
+    rsp:   u64,
+    // None for the boot thread, which keeps running on the stack bootloader has set up
+    stack: Option<StackBounds>,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Thread {
+    fn new(name: &'static str, stack: StackBounds, entry: Box<dyn FnOnce() + Send>) -> Thread {
+        /*
+         * Initial stack is laid out as if thread has been switched away from right before
+         * calling thread_trampoline, so the first context_switch into it "returns" there:
+         *
+         * | top - 8         | thread_trampoline                           |
+         * | top - 16 .. -56 | rbx, rbp, r12..r15 (zero), saved rsp -> r15 |
+         *
+         * Stack top is page aligned, so trampoline starts with stack aligned to 16 bytes as ABI expects
+         */
+        let top = stack.top().as_mut_ptr::<u64>();
+        let rsp = unsafe {
+            top.sub(1).write(VirtAddr::from_ptr(thread_trampoline as *const ()).as_u64());
+            for slot in 2..=7 {
+                top.sub(slot).write(0);
+            }
+            top.sub(7)
+        };
+
+        Thread {
+            id:    ThreadId::new(),
+            name,
+            state: ThreadState::Ready,
+            rsp:   rsp as u64,
+            stack: Some(stack),
+            entry: Some(entry),
+        }
+    }
+
+    fn boot() -> Thread {
+        Thread {
+            id:    ThreadId::BOOT,
+            name:  "boot",
+            state: ThreadState::Running,
+            rsp:   0,
+            stack: None,
+            entry: None,
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+}
+
+/// Snapshot of thread table entry
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id:    ThreadId,
+    pub name:  &'static str,
+    pub state: ThreadState,
+}
+
+/// Turns code that runs right now into the boot thread & starts preempting threads
+/// every `time_slice` timer ticks
+///
+/// Must be called once heap is initialised
+pub fn init(time_slice: u64) {
+    set_time_slice(time_slice);
+    without_interrupts(|| SCHEDULER.lock().add_boot(Box::new(Thread::boot())));
+    INITIALISED.store(true, Ordering::Release);
+}
+
+/// Sets how many timer ticks thread runs for before it is preempted, takes effect from the next switch
+pub fn set_time_slice(ticks: u64) {
+    TIME_SLICE.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn time_slice() -> u64 {
+    TIME_SLICE.load(Ordering::Relaxed)
+}
+
+/// Creates thread that runs `entry` on its own stack, thread is added to the end of run queue
+///
+/// Stacks of finished threads are reused, new one is only allocated if there are none
+pub fn spawn(
+    name:            &'static str,
+    entry:           impl FnOnce() + Send + 'static,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<ThreadId, MapToError<Size4KiB>> {
+    let stack = match without_interrupts(|| SCHEDULER.lock().take_free_stack()) {
+        Some(stack) => stack,
+        None        => memory::alloc_stack(THREAD_STACK_PAGES, mapper, frame_allocator)?,
+    };
+
+    let thread = Box::new(Thread::new(name, stack, Box::new(entry)));
+    let id     = thread.id;
+    without_interrupts(|| SCHEDULER.lock().add(thread));
+
+    Ok(id)
+}
+
+/// Gives up the rest of the time slice to the next ready thread (if there is any)
+pub fn yield_now() {
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+    schedule();
+}
+
+/// Finishes current thread, same as returning from its entry function
+pub fn exit() -> ! {
+    interrupts::disable();
+    SCHEDULER.lock().finish_current();
+    schedule();
+
+    unreachable!("finished thread has been scheduled again");
+}
+
+/// Returns thread running on the calling CPU
+pub fn current() -> ThreadId {
+    CURRENT.with(Cell::get)
+}
+
+/// Returns state of the thread, None if there is no such thread (or it has finished & been reaped)
+pub fn state(id: ThreadId) -> Option<ThreadState> {
+    without_interrupts(|| SCHEDULER.lock().get(id).map(Thread::state))
+}
+
+/// Returns snapshot of the thread table, ordered by ID
+pub fn list() -> Vec<ThreadInfo> {
+    without_interrupts(|| {
+        SCHEDULER.lock()
+            .threads()
+            .map(|thread| ThreadInfo {
+                id:    thread.id,
+                name:  thread.name,
+                state: thread.state,
+            })
+            .collect()
+    })
+}
+
+/// Called on every timer tick, asks for reschedule once time slice is used up
+pub(crate) fn timer_tick() {
+    if !INITIALISED.load(Ordering::Acquire) {
+        return;
+    }
+
+    // Only timer interrupt changes SLICE_LEFT (besides switches, which run with interrupts disabled)
+    match SLICE_LEFT.load(Ordering::Relaxed) {
+        0 | 1 => NEED_RESCHED.store(true, Ordering::Relaxed),
+        left  => SLICE_LEFT.store(left - 1, Ordering::Relaxed),
+    }
+}
+
+/// Preempts current thread if it asked for reschedule. Called on the way out of IRQ handler,
+/// after EOI has been sent, as the thread we switch to may not return here for a while
+pub(crate) fn preempt_if_needed() {
+    // Kernel stack for user mode is shared by all threads, so thread that runs user code is not preempted
+    if syscall::in_user_mode() {
+        return;
+    }
+
+    if NEED_RESCHED.swap(false, Ordering::Relaxed) {
+        schedule();
+    }
+}
+
+/// Switches to the next ready thread, returns once current thread is scheduled again
+fn schedule() {
+    if !INITIALISED.load(Ordering::Acquire) {
+        return;
+    }
+
+    without_interrupts(|| {
+        // Lock is released before switch, as the other thread would not unlock it.
+        // Nothing could touch the thread table in between, as interrupts are disabled
+        let (old_rsp, new_rsp) = {
+            let mut scheduler = SCHEDULER.lock();
+            let switch        = match scheduler.switch_next() {
+                Some(switch) => switch,
+                None         => return,
+            };
+            CURRENT.with(|current| current.set(scheduler.current()));
+            switch
+        };
+
+        SLICE_LEFT.store(time_slice(), Ordering::Relaxed);
+        // Spawner of the executor this thread runs (if any) waits on its stack, so other threads do not see it
+        let spawner = spawner::suspend();
+        // Safe because both pointers come from threads in the table, which are boxed & thus never move
+        unsafe { context_switch(old_rsp, new_rsp) };
+        spawner::resume(spawner);
+    });
+}
+
+/// First thing every new thread runs (via thread_trampoline), with interrupts disabled
+#[no_mangle]
+extern "C" fn thread_start() -> ! {
+    let entry = SCHEDULER.lock()
+        .take_current_entry()
+        .expect("new thread without entry function");
+
+    interrupts::enable();
+    entry();
+
+    exit();
+}
@@ -0,0 +1,117 @@
+use alloc::{
+    boxed::Box,
+    collections::{ BTreeMap, VecDeque },
+    vec::Vec,
+};
+
+use crate::memory::StackBounds;
+use super::{ Thread, ThreadId, ThreadState };
+
+/// Thread table with round-robin run queue
+///
+/// Threads are boxed, so saved stack pointer stays at the same address while context_switch writes it
+pub struct Scheduler {
+    threads:     BTreeMap<ThreadId, Box<Thread>>,
+    ready:       VecDeque<ThreadId>,
+    current:     ThreadId,
+    // Stacks of reaped threads, handed out to new threads before anything new is allocated
+    free_stacks: Vec<StackBounds>,
+}
+
+impl Scheduler {
+    pub const fn new() -> Scheduler {
+        Scheduler {
+            threads:     BTreeMap::new(),
+            ready:       VecDeque::new(),
+            current:     ThreadId::BOOT,
+            free_stacks: Vec::new(),
+        }
+    }
+
+    pub fn add_boot(&mut self, thread: Box<Thread>) {
+        self.current = thread.id;
+        self.threads.insert(thread.id, thread);
+    }
+
+    pub fn add(&mut self, thread: Box<Thread>) {
+        self.ready.push_back(thread.id);
+        self.threads.insert(thread.id, thread);
+    }
+
+    pub fn current(&self) -> ThreadId {
+        self.current
+    }
+
+    pub fn get(&self, id: ThreadId) -> Option<&Thread> {
+        self.threads.get(&id).map(|thread| &**thread)
+    }
+
+    pub fn threads(&self) -> impl Iterator<Item = &Thread> {
+        self.threads.values().map(|thread| &**thread)
+    }
+
+    pub fn take_free_stack(&mut self) -> Option<StackBounds> {
+        self.free_stacks.pop()
+    }
+
+    pub fn take_current_entry(&mut self) -> Option<Box<dyn FnOnce() + Send>> {
+        self.threads.get_mut(&self.current)?.entry.take()
+    }
+
+    pub fn finish_current(&mut self) {
+        if let Some(thread) = self.threads.get_mut(&self.current) {
+            thread.state = ThreadState::Finished;
+        }
+    }
+
+    /// Picks the next thread & makes it current, returns where to save current stack pointer
+    /// and stack pointer to switch to. None if current thread should just keep running
+    pub fn switch_next(&mut self) -> Option<(*mut u64, u64)> {
+        self.reap();
+
+        let next_id = match self.ready.pop_front() {
+            Some(id) => id,
+            None     => {
+                assert!(
+                    self.get(self.current).filter(|thread| thread.state == ThreadState::Finished).is_none(),
+                    "no thread left to run"
+                );
+                return None;
+            }
+        };
+
+        let current = self.threads.get_mut(&self.current).expect("current thread is not in the table");
+        if current.state == ThreadState::Running {
+            current.state = ThreadState::Ready;
+            self.ready.push_back(current.id);
+        }
+        let old_rsp = &mut current.rsp as *mut u64;
+
+        let next = self.threads.get_mut(&next_id).expect("ready thread is not in the table");
+        next.state   = ThreadState::Running;
+        self.current = next_id;
+
+        Some((old_rsp, next.rsp))
+    }
+
+    /// Removes finished threads, except of the current one: it still runs on its stack
+    fn reap(&mut self) {
+        let current     = self.current;
+        let free_stacks = &mut self.free_stacks;
+
+        self.threads.retain(|&id, thread| {
+            if id == current || thread.state != ThreadState::Finished {
+                return true;
+            }
+
+            free_stacks.extend(thread.stack);
+            false
+        });
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
@@ -0,0 +1,324 @@
+use conquer_once::spin::OnceCell;
+use core::{
+    ptr,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator,
+        Mapper,
+        Size4KiB,
+    },
+    PhysAddr,
+    VirtAddr,
+};
+
+use crate::{
+    acpi::{ self, AcpiError },
+    interrupts::{ self, InterruptIndex, IrqError },
+    memory,
+};
+
+// Offsets of HPET registers from its base address
+const CONFIGURATION_REG:    usize = 0x010;
+const MAIN_COUNTER_REG:     usize = 0x0f0;
+const TIMER_CONFIG_REG:     usize = 0x100; // + 0x20 * N
+const TIMER_COMPARATOR_REG: usize = 0x108; // + 0x20 * N
+
+// Size of the register block that has to be mapped
+const REGISTERS_SIZE: u64 = 1024;
+
+/*
+ * General Capabilities and ID register
+ * | Bits  | Value                                     |
+ * | 8-12  | Number of the last timer                  |
+ * | 13    | Main counter is 64 bits wide              |
+ * | 15    | Legacy replacement routing is supported   |
+ * | 32-63 | Main counter tick period (femtoseconds)   |
+ */
+const COUNT_SIZE_CAP: u64 = 1 << 13;
+
+// Spec allows periods of up to 100ns (i.e. at least 10MHz), anything longer (or zero) means registers are bogus
+const MAX_PERIOD_FS: u64 = 0x05f5_e100;
+
+// General Configuration register bits
+const ENABLE_CNF: u64 = 1 << 0; // Main counter runs & timers can fire interrupts
+const LEG_RT_CNF: u64 = 1 << 1; // Legacy replacement: timer 0 -> IRQ0, timer 1 -> IRQ8
+
+/*
+ * Timer N Configuration and Capabilities register bits
+ * | Bit | Name            | Meaning                                    |
+ * | 1   | Tn_INT_TYPE_CNF | 0 - edge triggered, 1 - level triggered    |
+ * | 2   | Tn_INT_ENB_CNF  | Fire interrupt when comparator matches     |
+ * | 3   | Tn_TYPE_CNF     | 0 - one-shot, 1 - periodic                 |
+ * | 4   | Tn_PER_INT_CAP  | Timer supports periodic mode               |
+ * | 6   | Tn_VAL_SET_CNF  | Allows to set accumulator of periodic timer |
+ */
+const TN_INT_TYPE_CNF: u64 = 1 << 1;
+const TN_INT_ENB_CNF:  u64 = 1 << 2;
+const TN_TYPE_CNF:     u64 = 1 << 3;
+const TN_PER_INT_CAP:  u64 = 1 << 4;
+const TN_VAL_SET_CNF:  u64 = 1 << 6;
+
+const FEMTOS_PER_NANO: u128 = 1_000_000;
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+// Functions to be called when comparator of legacy routed timer (0 or 1) fires
+static CALLBACKS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    Acpi(AcpiError),
+    /// HPET registers are not memory mapped (should never happen on x86)
+    NotMemoryMapped,
+    /// Mapping of HPET registers into virtual memory failed
+    MappingFailed,
+    /// HPET does not have timer with given number
+    NoSuchTimer(u8),
+    /// Timer does not support periodic mode
+    PeriodicNotSupported(u8),
+    /// Only timers 0 & 1 are routed to PIC (through legacy replacement)
+    NotRouted(u8),
+    /// Line of timer 1 is already used by another device
+    Irq(IrqError),
+    /// Main counter is only 32 bits wide, it would wrap around every few minutes
+    Counter32Bit,
+    /// Main counter tick period (femtoseconds) is either zero or longer than spec allows
+    InvalidPeriod(u64),
+}
+
+/// High Precision Event Timer
+///
+/// Consists of a single up-counting main counter and a number of comparators (timers),
+/// each of which fires an interrupt when main counter reaches its value
+pub struct Hpet {
+    base:      VirtAddr,
+    // Length of a single main counter tick, femtoseconds (10^-15 s)
+    period_fs: u64,
+    timers:    u8,
+}
+
+/// Maps HPET registers (base address is taken from ACPI HPET table) and starts main counter
+///
+/// Timers 0 and 1 are routed to IRQ0 and IRQ8 with legacy replacement (which disconnects PIT)
+/// once either of them is started
+pub fn init(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<&'static Hpet, HpetError> {
+    if let Ok(hpet) = HPET.try_get() {
+        return Ok(hpet);
+    }
+
+    acpi::init().map_err(HpetError::Acpi)?;
+    let table = acpi::hpet_table().map_err(HpetError::Acpi)?;
+
+    let base_address = table.base_address;
+    if base_address.address_space_id != 0 {
+        return Err(HpetError::NotMemoryMapped);
+    }
+
+    let base = memory::map_mmio(PhysAddr::new(base_address.address), REGISTERS_SIZE, mapper, frame_allocator)
+        .map_err(|_| HpetError::MappingFailed)?;
+
+    let capabilities = unsafe { ptr::read_volatile(base.as_ptr::<u64>()) };
+    let (period_fs, timers) = parse_capabilities(capabilities)?;
+    let hpet = Hpet { base, period_fs, timers };
+
+    // Make sure that none of the timers would fire before it is asked to
+    for timer in 0..hpet.timers {
+        hpet.stop(timer);
+    }
+    hpet.write(CONFIGURATION_REG, ENABLE_CNF);
+
+    // Timer 1 takes over RTC line, timer 0 line is handled by the timer interrupt handler
+    interrupts::register_irq(InterruptIndex::RealTimeClock, comparator_interrupt_handler)
+        .map_err(HpetError::Irq)?;
+
+    Ok(HPET.get_or_init(|| hpet))
+}
+
+/// Returns main counter tick period & number of timers, if HPET could be used
+fn parse_capabilities(capabilities: u64) -> Result<(u64, u8), HpetError> {
+    // Without 64-bit counter uptime would wrap around, falling back to PIT is simpler than tracking that
+    if capabilities & COUNT_SIZE_CAP == 0 {
+        return Err(HpetError::Counter32Bit);
+    }
+
+    let period_fs = capabilities >> 32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        return Err(HpetError::InvalidPeriod(period_fs));
+    }
+
+    Ok((period_fs, ((capabilities >> 8) & 0x1f) as u8 + 1))
+}
+
+/// Returns HPET if it has been initialised
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.try_get().ok()
+}
+
+/// Registers function that is called (in interrupt context!) when comparator of given timer fires
+///
+/// Only legacy routed timers (0 and 1) are supported. Timer 0 also drives kernel tick when HPET is the tick source
+pub fn on_comparator(timer: u8, callback: fn()) -> Result<(), HpetError> {
+    let slot = CALLBACKS.get(usize::from(timer)).ok_or(HpetError::NotRouted(timer))?;
+    slot.store(callback as usize, Ordering::Release);
+
+    Ok(())
+}
+
+/// Registered for RTC line, which timer 1 is routed to with legacy replacement
+fn comparator_interrupt_handler(_index: InterruptIndex) {
+    handle_comparator_interrupt(1);
+}
+
+/// Called by interrupt handler of the line that timer is routed to
+///
+/// Must not block or allocate
+pub(crate) fn handle_comparator_interrupt(timer: u8) {
+    if let Some(slot) = CALLBACKS.get(usize::from(timer)) {
+        let callback = slot.load(Ordering::Acquire);
+
+        if callback != 0 {
+            // Safe because only valid `fn()` pointers are ever stored into CALLBACKS
+            let callback: fn() = unsafe { core::mem::transmute(callback) };
+            callback();
+        }
+    }
+}
+
+impl Hpet {
+    /// Returns raw value of the main counter
+    pub fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER_REG)
+    }
+
+    /// Returns value of the main counter converted to nanoseconds
+    pub fn counter_ns(&self) -> u64 {
+        (u128::from(self.counter()) * u128::from(self.period_fs) / FEMTOS_PER_NANO) as u64
+    }
+
+    /// Returns number of main counter ticks per second
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    /// Returns number of comparators (timers) that this HPET has
+    pub fn timers(&self) -> u8 {
+        self.timers
+    }
+
+    /// Returns IRQ line that given timer fires
+    pub fn irq_line(&self, timer: u8) -> Option<InterruptIndex> {
+        match timer {
+            0 => Some(InterruptIndex::Timer),
+            1 => Some(InterruptIndex::RealTimeClock),
+            _ => None,
+        }
+    }
+
+    /// Makes timer fire its interrupt every `period_ns` nanoseconds
+    pub fn start_periodic(&self, timer: u8, period_ns: u64) -> Result<(), HpetError> {
+        self.check_timer(timer)?;
+
+        let config = self.read(timer_reg(TIMER_CONFIG_REG, timer));
+        if config & TN_PER_INT_CAP == 0 {
+            return Err(HpetError::PeriodicNotSupported(timer));
+        }
+
+        let period = self.ns_to_ticks(period_ns);
+
+        // Main counter must be halted while periodic comparator is set up,
+        // otherwise first interrupt might get lost
+        let general_config = self.read(CONFIGURATION_REG) | LEG_RT_CNF;
+        self.write(CONFIGURATION_REG, general_config & !ENABLE_CNF);
+
+        self.write(
+            timer_reg(TIMER_CONFIG_REG, timer),
+            (config & !TN_INT_TYPE_CNF) | TN_INT_ENB_CNF | TN_TYPE_CNF | TN_VAL_SET_CNF
+        );
+        // With Tn_VAL_SET_CNF first write sets comparator, second one sets its accumulator (period)
+        self.write(timer_reg(TIMER_COMPARATOR_REG, timer), self.counter() + period);
+        self.write(timer_reg(TIMER_COMPARATOR_REG, timer), period);
+
+        self.write(CONFIGURATION_REG, general_config | ENABLE_CNF);
+        Ok(())
+    }
+
+    /// Makes timer fire its interrupt once, after `delay_ns` nanoseconds
+    pub fn start_one_shot(&self, timer: u8, delay_ns: u64) -> Result<(), HpetError> {
+        self.check_timer(timer)?;
+
+        self.route_legacy();
+
+        let config = self.read(timer_reg(TIMER_CONFIG_REG, timer));
+        self.write(
+            timer_reg(TIMER_CONFIG_REG, timer),
+            (config & !(TN_INT_TYPE_CNF | TN_TYPE_CNF)) | TN_INT_ENB_CNF
+        );
+        self.write(timer_reg(TIMER_COMPARATOR_REG, timer), self.counter() + self.ns_to_ticks(delay_ns));
+
+        Ok(())
+    }
+
+    /// Stops timer from firing interrupts
+    pub fn stop(&self, timer: u8) {
+        if timer < self.timers {
+            let config = self.read(timer_reg(TIMER_CONFIG_REG, timer));
+            self.write(timer_reg(TIMER_CONFIG_REG, timer), config & !(TN_INT_ENB_CNF | TN_TYPE_CNF));
+        }
+    }
+
+    /// Routes timers 0 & 1 to IRQ0 & IRQ8, PIT stops driving IRQ0 from then on
+    fn route_legacy(&self) {
+        let config = self.read(CONFIGURATION_REG);
+        self.write(CONFIGURATION_REG, config | LEG_RT_CNF);
+    }
+
+    fn check_timer(&self, timer: u8) -> Result<(), HpetError> {
+        if timer >= self.timers {
+            Err(HpetError::NoSuchTimer(timer))
+        } else if self.irq_line(timer).is_none() {
+            Err(HpetError::NotRouted(timer))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ns_to_ticks(&self, ns: u64) -> u64 {
+        let ticks = u128::from(ns) * FEMTOS_PER_NANO / u128::from(self.period_fs);
+
+        (ticks as u64).max(1)
+    }
+
+    fn read(&self, reg: usize) -> u64 {
+        // Unsafe because we read from device memory, which is mapped in init()
+        unsafe { ptr::read_volatile((self.base + reg).as_ptr::<u64>()) }
+    }
+
+    fn write(&self, reg: usize, value: u64) {
+        // Unsafe because we write into device memory, which is mapped in init()
+        unsafe { ptr::write_volatile((self.base + reg).as_mut_ptr::<u64>(), value) }
+    }
+}
+
+fn timer_reg(reg: usize, timer: u8) -> usize {
+    reg + 0x20 * usize::from(timer)
+}
+
+
+#[test_case]
+fn test_capabilities_are_validated() {
+    // 64-bit counter, 3 timers, 100MHz (as QEMU's HPET)
+    let capabilities = (10_000_000 << 32) | COUNT_SIZE_CAP | (2 << 8);
+    assert_eq!(parse_capabilities(capabilities), Ok((10_000_000, 3)));
+
+    assert_eq!(parse_capabilities(capabilities & !COUNT_SIZE_CAP), Err(HpetError::Counter32Bit));
+    assert_eq!(parse_capabilities(COUNT_SIZE_CAP), Err(HpetError::InvalidPeriod(0)));
+    assert_eq!(parse_capabilities(((MAX_PERIOD_FS + 1) << 32) | COUNT_SIZE_CAP), Err(HpetError::InvalidPeriod(MAX_PERIOD_FS + 1)));
+    assert_eq!(parse_capabilities((MAX_PERIOD_FS << 32) | COUNT_SIZE_CAP), Ok((MAX_PERIOD_FS, 1)));
+}
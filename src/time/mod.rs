@@ -0,0 +1,104 @@
+pub mod hpet;
+pub mod pit;
+
+use core::sync::atomic::{
+    AtomicU64,
+    AtomicU8,
+    Ordering,
+};
+use x86_64::structures::paging::{
+    FrameAllocator,
+    Mapper,
+    Size4KiB,
+};
+
+use crate::warn;
+
+/// How many times per second timer interrupt fires, regardless of which device drives it
+pub const TICK_HZ: u64 = 100;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+static TICKS:  AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8  = AtomicU8::new(TickSource::Pit as u8);
+
+/// Device that generates timer interrupt (IRQ0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+    /// Legacy Programmable Interval Timer (8253/8254)
+    Pit = 0,
+    /// High Precision Event Timer in legacy replacement mode (takes over IRQ0 from PIT)
+    Hpet,
+}
+
+/// Programs requested device to fire timer interrupt `TICK_HZ` times per second
+///
+/// If HPET is requested, but could not be found or set up, falls back to PIT.
+/// Returns tick source that is actually in use
+pub fn init(
+    source:          TickSource,
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> TickSource {
+    let source = match source {
+        TickSource::Pit  => TickSource::Pit,
+        TickSource::Hpet => {
+            match hpet::init(mapper, frame_allocator).and_then(|hpet| hpet.start_periodic(0, NANOS_PER_SEC / TICK_HZ)) {
+                Ok(())   => TickSource::Hpet,
+                Err(err) => {
+                    warn!("HPET is not available ({:?}), falling back to PIT", err);
+                    TickSource::Pit
+                }
+            }
+        }
+    };
+
+    if source == TickSource::Pit {
+        pit::init(TICK_HZ);
+    }
+
+    SOURCE.store(source as u8, Ordering::Relaxed);
+    source
+}
+
+/// Called by timer interrupt
+///
+/// Must not block or allocate
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns number of timer interrupts since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns device that currently drives timer interrupt
+pub fn tick_source() -> TickSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        0 => TickSource::Pit,
+        _ => TickSource::Hpet,
+    }
+}
+
+/// Returns monotonic time since boot in nanoseconds
+///
+/// When HPET is available its main counter is used, otherwise precision is limited by `TICK_HZ`
+pub fn uptime_ns() -> u64 {
+    match hpet::hpet() {
+        Some(hpet) => hpet.counter_ns(),
+        None       => ticks() * (NANOS_PER_SEC / TICK_HZ),
+    }
+}
+
+/// Spins for at least `ns` nanoseconds
+///
+/// Without HPET time only moves on timer interrupts, so they must be enabled (or it spins forever)
+pub fn busy_wait_ns(ns: u64) {
+    let deadline = uptime_ns() + ns;
+
+    while uptime_ns() < deadline {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,34 @@
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator that feeds PIT, Hz
+pub const BASE_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT:   u16 = 0x43;
+
+/*
+ * Command byte
+ * | Bits | Value                               |
+ * | 0    | BCD mode (0 - binary)               |
+ * | 1-3  | Operating mode (011 - square wave)  |
+ * | 4-5  | Access mode (11 - low, then high)   |
+ * | 6-7  | Channel (00 - channel 0, i.e. IRQ0) |
+ */
+const CHANNEL_0_SQUARE_WAVE: u8 = 0b0011_0110;
+
+/// Programs channel 0 of PIT to fire IRQ0 `frequency` times per second
+///
+/// PIT can not go slower than ~18.2Hz (divisor is 16 bits), so lower frequencies are clamped
+pub fn init(frequency: u64) {
+    let divisor = (BASE_FREQUENCY / frequency).clamp(1, u64::from(u16::MAX)) as u16;
+
+    let mut command   = Port::<u8>::new(COMMAND_PORT);
+    let mut channel_0 = Port::<u8>::new(CHANNEL_0_PORT);
+
+    // Unsafe because writing to I/O ports could have side effects
+    unsafe {
+        command.write(CHANNEL_0_SQUARE_WAVE);
+        channel_0.write((divisor & 0xff) as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
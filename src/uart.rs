@@ -0,0 +1,768 @@
+use core::{
+    fmt,
+    pin::Pin,
+    sync::atomic::{ AtomicBool, Ordering },
+    task::{ Context, Poll },
+};
+use futures_util::{
+    stream::{
+        Stream,
+        StreamExt,
+    },
+    task::AtomicWaker,
+};
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::{ self, InterruptIndex, IrqError },
+    print,
+    println,
+    serial_uart::{ SERIAL1, SERIAL2 },
+    spinlock::{ IrqSpinLock, IrqSpinLockGuard },
+    warn,
+};
+
+/// Received bytes that could wait to be read, further ones are dropped (see UartStats::rx_dropped)
+pub const RX_BUFFER_SIZE: usize = 1024;
+/// Bytes that could wait to be sent, see Uart::write
+pub const TX_BUFFER_SIZE: usize = 1024;
+
+// Baud rate of divisor 1 (1.8432 MHz clock / 16)
+const MAX_BAUD_RATE: u32 = 115_200;
+// Bytes transmitter FIFO of 16550 holds
+const FIFO_SIZE: usize = 16;
+// Interrupt causes handled in one go, so device that keeps raising them could not hold CPU forever
+const MAX_SERVICE_ROUNDS: usize = 16;
+
+// Register offsets from port base
+const DATA:             u16 = 0; // Divisor latch low byte while LCR_DLAB is set
+const INTERRUPT_ENABLE: u16 = 1; // Divisor latch high byte while LCR_DLAB is set
+const INTERRUPT_ID:     u16 = 2; // FIFO control on write
+const LINE_CONTROL:     u16 = 3;
+const MODEM_CONTROL:    u16 = 4;
+const LINE_STATUS:      u16 = 5;
+const MODEM_STATUS:     u16 = 6;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY:     u8 = 1 << 1;
+const IER_LINE_STATUS:  u8 = 1 << 2;
+
+const IIR_NO_INTERRUPT: u8 = 1 << 0;
+// Interrupt cause, bits 1..=3 of IIR
+const IIR_MODEM_STATUS: u8 = 0b000;
+const IIR_TX_EMPTY:     u8 = 0b001;
+const IIR_RX_AVAILABLE: u8 = 0b010;
+const IIR_LINE_STATUS:  u8 = 0b011;
+const IIR_RX_TIMEOUT:   u8 = 0b110;
+
+// Enable FIFOs, clear both of them & interrupt once 14 bytes are received
+const FCR_ENABLE_CLEAR: u8 = 0xc7;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR:      u8 = 1 << 0;
+const MCR_RTS:      u8 = 1 << 1;
+const MCR_OUT2:     u8 = 1 << 3; // Connects interrupt output of UART to IRQ line
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN:    u8 = 1 << 1;
+const LSR_PARITY:     u8 = 1 << 2;
+const LSR_FRAMING:    u8 = 1 << 3;
+const LSR_TX_EMPTY:   u8 = 1 << 5;
+
+// Sent in loopback mode while probing, has to come back if there is UART at the port
+const PROBE_BYTE: u8 = 0xae;
+
+/// Legacy serial port, COM1 & COM3 share IRQ4, COM2 & COM4 share IRQ3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// Parity bit is always 1
+    Mark,
+    /// Parity bit is always 0
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    /// 1.5 stop bits with 5 data bits
+    Two,
+}
+
+/// Line settings of a port, Config::default() is 115200 8N1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity:    Parity,
+    pub stop_bits: StopBits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    /// Baud rate is 0, above 115200 or too low for 16-bit divisor
+    InvalidBaudRate(u32),
+    AlreadyOpen(ComPort),
+    /// Nothing answers at the port (QEMU only has ports given by `-serial`)
+    NotPresent(ComPort),
+    /// Someone else handles IRQ line of the port, e.g. gdb stub on COM2
+    Irq(IrqError),
+    /// Stream of received bytes exists already, there could only be one reader
+    StreamTaken(ComPort),
+}
+
+/// Counters of a port since it has been opened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UartStats {
+    pub received:       u64,
+    pub sent:           u64,
+    /// Received bytes dropped because RX buffer was full
+    pub rx_dropped:     u64,
+    /// Received bytes UART dropped itself because its FIFO was full
+    pub overruns:       u64,
+    pub parity_errors:  u64,
+    pub framing_errors: u64,
+}
+
+/// Driver of a single port, see open
+pub struct Uart {
+    port:         ComPort,
+    open:         AtomicBool,
+    stream_taken: AtomicBool,
+    state:        IrqSpinLock<State>,
+    rx_waker:     AtomicWaker,
+}
+
+/// Everything that is only touched with the lock held, registers included
+struct State {
+    base:   u16,
+    config: Config,
+    // Values last written to the registers
+    ier:    u8,
+    mcr:    u8,
+    rx:     ByteRing<RX_BUFFER_SIZE>,
+    tx:     ByteRing<TX_BUFFER_SIZE>,
+    stats:  UartStats,
+}
+
+/// Fixed size byte queue, so nothing is allocated in interrupt handler
+struct ByteRing<const N: usize> {
+    bytes: [u8; N],
+    head:  usize,
+    len:   usize,
+}
+
+/// Sends bytes right away by polling the port, see Uart::polled
+pub struct PolledWriter<'a> {
+    state: IrqSpinLockGuard<'a, State>,
+}
+
+/// Bytes received on a port, see Uart::stream
+pub struct SerialStream {
+    uart: &'static Uart,
+}
+
+static PORTS: [Uart; 4] = [
+    Uart::new(ComPort::Com1),
+    Uart::new(ComPort::Com2),
+    Uart::new(ComPort::Com3),
+    Uart::new(ComPort::Com4),
+];
+
+// Serialises open & close, as ports sharing IRQ line also share its handler
+static OPENING: IrqSpinLock<()> = IrqSpinLock::named("uart::OPENING", ());
+
+/// Programs the port, enables its interrupts & returns its driver
+///
+/// COM1 stays the kernel console (serial_print!) once it is open, console output is still sent by polling,
+/// so it is not lost if kernel stops right after
+pub fn open(port: ComPort, config: Config) -> Result<&'static Uart, UartError> {
+    let divisor = config.divisor()?;
+    let _opening = OPENING.lock();
+    let uart     = &PORTS[port.index()];
+
+    if uart.is_open() {
+        return Err(UartError::AlreadyOpen(port));
+    }
+
+    // Polled ports program the UART on first use, which must not happen under the driver
+    match port {
+        ComPort::Com1 => drop(SERIAL1.lock()),
+        ComPort::Com2 => drop(SERIAL2.lock()),
+        _             => {}
+    }
+
+    uart.state.lock().init(config, divisor).map_err(|_| UartError::NotPresent(port))?;
+
+    // Interrupts of UART are enabled once handler serves the port: IRQ is edge triggered, so interrupt
+    // nobody handles keeps the line up & no further one arrives
+    let line_registered = sharing_line(port).any(Uart::is_open);
+    uart.open.store(true, Ordering::Release);
+
+    if !line_registered {
+        if let Err(err) = interrupts::register_irq(port.irq(), serial_interrupt_handler) {
+            uart.open.store(false, Ordering::Release);
+            uart.state.lock().reset();
+            return Err(UartError::Irq(err));
+        }
+    }
+
+    uart.state.lock().set_ier(IER_RX_AVAILABLE | IER_LINE_STATUS);
+    Ok(uart)
+}
+
+/// Driver of the port if it is open
+pub fn get(port: ComPort) -> Option<&'static Uart> {
+    Some(&PORTS[port.index()]).filter(|uart| uart.is_open())
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    /// First of the I/O ports of UART
+    pub const fn base(&self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+            ComPort::Com3 => 0x3e8,
+            ComPort::Com4 => 0x2e8,
+        }
+    }
+
+    pub fn irq(&self) -> InterruptIndex {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => InterruptIndex::SerialPort1,
+            ComPort::Com2 | ComPort::Com4 => InterruptIndex::SerialPort2,
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl Config {
+    /// `baud_rate` with 8 data bits, no parity & 1 stop bit
+    pub const fn new(baud_rate: u32) -> Config {
+        Config {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity:    Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+
+    fn divisor(&self) -> Result<u16, UartError> {
+        if self.baud_rate == 0 || self.baud_rate > MAX_BAUD_RATE {
+            return Err(UartError::InvalidBaudRate(self.baud_rate));
+        }
+
+        // Rounded to the nearest rate UART could do
+        let divisor = (MAX_BAUD_RATE + self.baud_rate / 2) / self.baud_rate;
+        u16::try_from(divisor).map_err(|_| UartError::InvalidBaudRate(self.baud_rate))
+    }
+
+    fn line_control(&self) -> u8 {
+        let data_bits = match self.data_bits {
+            DataBits::Five  => 0b00,
+            DataBits::Six   => 0b01,
+            DataBits::Seven => 0b10,
+            DataBits::Eight => 0b11,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1 << 2,
+        };
+        let parity = match self.parity {
+            Parity::None  => 0b000 << 3,
+            Parity::Odd   => 0b001 << 3,
+            Parity::Even  => 0b011 << 3,
+            Parity::Mark  => 0b101 << 3,
+            Parity::Space => 0b111 << 3,
+        };
+
+        data_bits | stop_bits | parity
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new(MAX_BAUD_RATE)
+    }
+}
+
+impl Uart {
+    const fn new(port: ComPort) -> Uart {
+        Uart {
+            port,
+            open:         AtomicBool::new(false),
+            stream_taken: AtomicBool::new(false),
+            state:        IrqSpinLock::named("uart::Uart::state", State::new(port)),
+            rx_waker:     AtomicWaker::new(),
+        }
+    }
+
+    pub fn port(&self) -> ComPort {
+        self.port
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+
+    pub fn config(&self) -> Config {
+        self.state.lock().config
+    }
+
+    pub fn stats(&self) -> UartStats {
+        self.state.lock().stats
+    }
+
+    /// Disables interrupts of the port & drops whatever is buffered, stream of the port ends
+    pub fn close(&self) {
+        let _opening = OPENING.lock();
+
+        if !self.open.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        self.state.lock().reset();
+
+        if !sharing_line(self.port).any(Uart::is_open) {
+            // Handler has been registered by the port or the one sharing its line
+            let _ = interrupts::unregister_irq(self.port.irq());
+        }
+        self.rx_waker.wake();
+    }
+
+    /// Moves received bytes into `buf`, returns how many of them there were (0 if there were none)
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let mut state = self.state.lock();
+
+        let mut count = 0;
+        while count < buf.len() {
+            match state.rx.pop() {
+                Some(byte) => buf[count] = byte,
+                None       => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Queues bytes to be sent by interrupt handler, returns how many of them have fit into TX buffer
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        if !self.is_open() {
+            return 0;
+        }
+        let mut state = self.state.lock();
+
+        let count = bytes.iter().take_while(|&&byte| state.tx.push(byte).is_ok()).count();
+        if count > 0 && state.ier & IER_TX_EMPTY == 0 {
+            // UART interrupts right away if it has nothing to send
+            let ier = state.ier | IER_TX_EMPTY;
+            state.set_ier(ier);
+        }
+        count
+    }
+
+    /// Sends bytes right away, after everything queued by write()
+    pub fn write_polled(&self, bytes: &[u8]) {
+        let mut writer = self.polled();
+        for &byte in bytes {
+            writer.send(byte);
+        }
+    }
+
+    /// Locks the port for sending by polling, everything queued by write() is sent first
+    ///
+    /// Interrupts stay disabled meanwhile, so it works in interrupt handler & with interrupts disabled
+    /// (queued bytes are only sent by interrupt handler otherwise)
+    pub fn polled(&self) -> PolledWriter<'_> {
+        let mut state = self.state.lock();
+
+        while let Some(byte) = state.tx.pop() {
+            state.send_polled(byte);
+        }
+        PolledWriter { state }
+    }
+
+    /// Returns the only stream of bytes received on the port, it ends once the port is closed
+    pub fn stream(&'static self) -> Result<SerialStream, UartError> {
+        if self.stream_taken.swap(true, Ordering::AcqRel) {
+            return Err(UartError::StreamTaken(self.port));
+        }
+        Ok(SerialStream { uart: self })
+    }
+
+    /// Connects transmitter to receiver inside UART, everything sent is received back instead of going out
+    pub fn set_loopback(&self, enabled: bool) {
+        let mut state = self.state.lock();
+
+        let mcr = if enabled { state.mcr | MCR_LOOPBACK } else { state.mcr & !MCR_LOOPBACK };
+        state.set_mcr(mcr);
+    }
+
+    fn service(&self) {
+        let received = self.state.lock().service();
+
+        // Waker is called with no lock held, as task could be woken right into this CPU's executor
+        if received {
+            self.rx_waker.wake();
+        }
+    }
+}
+
+/// Ports that share IRQ line with given one
+fn sharing_line(port: ComPort) -> impl Iterator<Item = &'static Uart> {
+    PORTS.iter().filter(move |uart| uart.port != port && uart.port.irq() == port.irq())
+}
+
+/// Registered for IRQ line of the first port open on it, serves every open port on the line
+fn serial_interrupt_handler(index: InterruptIndex) {
+    for uart in PORTS.iter().filter(|uart| uart.port.irq() == index && uart.is_open()) {
+        uart.service();
+    }
+}
+
+impl State {
+    const fn new(port: ComPort) -> State {
+        State {
+            base:   port.base(),
+            config: Config::new(MAX_BAUD_RATE),
+            ier:    0,
+            mcr:    0,
+            rx:     ByteRing::new(),
+            tx:     ByteRing::new(),
+            stats:  UartStats {
+                received:       0,
+                sent:           0,
+                rx_dropped:     0,
+                overruns:       0,
+                parity_errors:  0,
+                framing_errors: 0,
+            },
+        }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        // Safe because port belongs to this UART & is only accessed with its lock held
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        // Safe because port belongs to this UART & is only accessed with its lock held
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Programs line settings & checks that UART is there, its interrupts stay disabled
+    fn init(&mut self, config: Config, divisor: u16) -> Result<(), ()> {
+        self.set_ier(0);
+
+        let [divisor_low, divisor_high] = divisor.to_le_bytes();
+        self.write(LINE_CONTROL, LCR_DLAB);
+        self.write(DATA, divisor_low);
+        self.write(INTERRUPT_ENABLE, divisor_high);
+        self.write(LINE_CONTROL, config.line_control());
+        self.write(INTERRUPT_ID, FCR_ENABLE_CLEAR);
+
+        self.set_mcr(MCR_RTS | MCR_OUT2 | MCR_LOOPBACK);
+        self.write(DATA, PROBE_BYTE);
+        if self.read(DATA) != PROBE_BYTE {
+            self.reset();
+            return Err(());
+        }
+        self.set_mcr(MCR_DTR | MCR_RTS | MCR_OUT2);
+
+        // Whatever is pending from before is dropped
+        while self.read(LINE_STATUS) & LSR_DATA_READY != 0 {
+            self.read(DATA);
+        }
+        self.read(MODEM_STATUS);
+
+        self.config = config;
+        self.rx.clear();
+        self.tx.clear();
+        self.stats  = UartStats::default();
+        Ok(())
+    }
+
+    /// Disables interrupts of UART & disconnects it from IRQ line
+    fn reset(&mut self) {
+        self.set_ier(0);
+        self.set_mcr(0);
+        self.rx.clear();
+        self.tx.clear();
+    }
+
+    fn set_ier(&mut self, ier: u8) {
+        self.ier = ier;
+        self.write(INTERRUPT_ENABLE, ier);
+    }
+
+    fn set_mcr(&mut self, mcr: u8) {
+        self.mcr = mcr;
+        self.write(MODEM_CONTROL, mcr);
+    }
+
+    /// Handles pending interrupts of UART, returns true if anything has been received
+    fn service(&mut self) -> bool {
+        let mut received = false;
+
+        for _ in 0..MAX_SERVICE_ROUNDS {
+            let iir = self.read(INTERRUPT_ID);
+            if iir & IIR_NO_INTERRUPT != 0 {
+                break;
+            }
+
+            match (iir >> 1) & 0b111 {
+                IIR_RX_AVAILABLE | IIR_RX_TIMEOUT => received |= self.receive(),
+                IIR_TX_EMPTY                      => self.transmit(),
+                IIR_LINE_STATUS                   => {
+                    let lsr = self.read(LINE_STATUS);
+                    self.count_errors(lsr);
+                }
+                // Modem lines are not used, reading their status acknowledges interrupt
+                IIR_MODEM_STATUS                  => {
+                    self.read(MODEM_STATUS);
+                }
+                _                                 => {}
+            }
+        }
+        received
+    }
+
+    fn receive(&mut self) -> bool {
+        let mut received = false;
+
+        loop {
+            let lsr = self.read(LINE_STATUS);
+            self.count_errors(lsr);
+            if lsr & LSR_DATA_READY == 0 {
+                break;
+            }
+
+            let byte = self.read(DATA);
+            match self.rx.push(byte) {
+                Ok(())  => {
+                    self.stats.received += 1;
+                    received = true;
+                }
+                Err(()) => self.stats.rx_dropped += 1,
+            }
+        }
+        received
+    }
+
+    /// Refills transmitter FIFO, which is empty once its interrupt fires
+    fn transmit(&mut self) {
+        for _ in 0..FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => {
+                    self.write(DATA, byte);
+                    self.stats.sent += 1;
+                }
+                None       => break,
+            }
+        }
+
+        if self.tx.is_empty() {
+            let ier = self.ier & !IER_TX_EMPTY;
+            self.set_ier(ier);
+        }
+    }
+
+    fn send_polled(&mut self, byte: u8) {
+        while self.read(LINE_STATUS) & LSR_TX_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write(DATA, byte);
+        self.stats.sent += 1;
+    }
+
+    fn count_errors(&mut self, lsr: u8) {
+        if lsr & LSR_OVERRUN != 0 {
+            self.stats.overruns += 1;
+        }
+        if lsr & LSR_PARITY != 0 {
+            self.stats.parity_errors += 1;
+        }
+        if lsr & LSR_FRAMING != 0 {
+            self.stats.framing_errors += 1;
+        }
+    }
+}
+
+impl<const N: usize> ByteRing<N> {
+    const fn new() -> ByteRing<N> {
+        ByteRing {
+            bytes: [0; N],
+            head:  0,
+            len:   0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), ()> {
+        if self.len == N {
+            return Err(());
+        }
+        self.bytes[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len  = 0;
+    }
+}
+
+impl PolledWriter<'_> {
+    pub fn send(&mut self, byte: u8) {
+        self.state.send_polled(byte);
+    }
+}
+
+impl fmt::Write for PolledWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let uart = self.uart;
+
+        // register() is not free, so it is skipped if there is something to read already
+        let mut byte = [0];
+        if uart.read(&mut byte) == 1 {
+            return Poll::Ready(Some(byte[0]));
+        }
+
+        uart.rx_waker.register(cx.waker());
+        if uart.read(&mut byte) == 1 {
+            uart.rx_waker.take();
+            return Poll::Ready(Some(byte[0]));
+        }
+        if !uart.is_open() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for SerialStream {
+    fn drop(&mut self) {
+        self.uart.stream_taken.store(false, Ordering::Release);
+    }
+}
+
+/// Echoes bytes received on the port back & shows them on the screen, so kernel takes input
+/// under headless QEMU (`-display none`) too
+pub async fn echo_input(uart: &'static Uart) {
+    let mut stream = match uart.stream() {
+        Ok(stream) => stream,
+        Err(err)   => {
+            warn!("Serial input of {:?} is not echoed: {:?}", uart.port(), err);
+            return;
+        }
+    };
+
+    while let Some(byte) = stream.next().await {
+        match byte {
+            // Terminals send CR on Enter
+            b'\r'        => {
+                uart.write(b"\r\n");
+                println!();
+            }
+            // Backspace & Delete erase the last character of the terminal
+            0x08 | 0x7f  => {
+                uart.write(b"\x08 \x08");
+            }
+            byte         => {
+                uart.write(&[byte]);
+                print!("{}", char::from(byte));
+            }
+        }
+    }
+}
+
+
+#[test_case]
+fn test_byte_ring_wraps_around() {
+    let mut ring = ByteRing::<4>::new();
+
+    for byte in 0..4 {
+        ring.push(byte).unwrap();
+    }
+    assert_eq!(ring.push(4), Err(()));
+    assert_eq!(ring.pop(), Some(0));
+    assert_eq!(ring.pop(), Some(1));
+
+    ring.push(4).unwrap();
+    ring.push(5).unwrap();
+    assert_eq!([ring.pop(), ring.pop(), ring.pop(), ring.pop()], [Some(2), Some(3), Some(4), Some(5)]);
+    assert!(ring.is_empty());
+    assert_eq!(ring.pop(), None);
+}
+
+#[test_case]
+fn test_config_divisor() {
+    assert_eq!(Config::default().divisor(), Ok(1));
+    assert_eq!(Config::new(9600).divisor(), Ok(12));
+    // 1047.27 is rounded down
+    assert_eq!(Config::new(110).divisor(), Ok(1047));
+    assert_eq!(Config::new(0).divisor(), Err(UartError::InvalidBaudRate(0)));
+    assert_eq!(Config::new(230_400).divisor(), Err(UartError::InvalidBaudRate(230_400)));
+    assert_eq!(Config::new(1).divisor(), Err(UartError::InvalidBaudRate(1)));
+}
+
+#[test_case]
+fn test_line_control() {
+    let config = Config {
+        baud_rate: 9600,
+        data_bits: DataBits::Seven,
+        parity:    Parity::Even,
+        stop_bits: StopBits::Two,
+    };
+    assert_eq!(config.line_control(), 0b0001_1110);
+    assert_eq!(Config::default().line_control(), 0b0000_0011);
+}
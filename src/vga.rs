@@ -1,8 +1,9 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile; // Need to use volatile so that compiler will not optimise away writes to Buffer.chars
 
+use crate::spinlock::IrqSpinLock;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)] // To ensure enum is C-like: each enum variant is stored as u8 value
@@ -144,22 +145,18 @@ impl fmt::Write for Writer {
 pub fn _print(args: fmt::Arguments) {
     // Only to be used by macros!
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-
-    // This is required to ensure that we lock WRITER only in interrupt-free cases,
-    // thus avoiding dead-locks
-    interrupts::without_interrupts(|| {
-        WRITER
-            .lock()
-            .write_fmt(args)
-            .unwrap(); // Assumably alright to unwrap, because we always return Ok(()) from write_str()
-    });
+
+    // WRITER keeps interrupts disabled while locked, so printing from interrupt handler could not dead-lock
+    WRITER
+        .lock()
+        .write_fmt(args)
+        .unwrap(); // Assumably alright to unwrap, because we always return Ok(()) from write_str()
 }
 
 
 lazy_static! {
     // Create static WRITER so it could be imported by other modules
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: IrqSpinLock<Writer> = IrqSpinLock::named("vga::WRITER", Writer {
         column_pos:  0,
         colour_code: ColourCode::new(Colour::Yellow, Colour::Black),
         /*
@@ -200,17 +197,14 @@ lazy_static! {
 #[test_case]
 fn test_println_output() {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     let s = "Some test string that fits on a single line";
 
-    interrupts::without_interrupts(|| {
-        let mut writer = WRITER.lock();
+    let mut writer = WRITER.lock();
 
-        writeln!(writer, "\n{}", s).expect("writeln failed");
-        for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
-            assert_eq!(char::from(screen_char.ascii_char), c);
-        }
-    });
+    writeln!(writer, "\n{}", s).expect("writeln failed");
+    for (i, c) in s.chars().enumerate() {
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        assert_eq!(char::from(screen_char.ascii_char), c);
+    }
 }
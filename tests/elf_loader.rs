@@ -0,0 +1,158 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::panic::PanicInfo;
+use x86_64::{
+    structures::paging::{
+        mapper::TranslateResult,
+        FrameAllocator,
+        FrameDeallocator,
+        PageTableFlags,
+        PhysFrame,
+        Size4KiB,
+        Translate,
+    },
+    VirtAddr,
+};
+
+use radius_os::{
+    elf::{ self, ElfError, ElfFile, PF_W, PF_X, PT_LOAD },
+    memory::{ self, AddressSpace, BootInfoFrameAllocator },
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    syscall,
+    test_panic_handler,
+};
+
+// Built from tests/programs/hello.S: prints argv[1] & exits with argc
+static HELLO: &[u8] = include_bytes!("programs/hello");
+
+/// Keeps count of frames that have been allocated, but not given back yet
+struct CountingAllocator<'a> {
+    inner:       &'a mut BootInfoFrameAllocator,
+    outstanding: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for CountingAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.inner.allocate_frame()?;
+        self.outstanding += 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for CountingAllocator<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.outstanding -= 1;
+        self.inner.deallocate_frame(frame);
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    syscall::init(&mut mapper, &mut frame_allocator).expect("syscall initialisation failed");
+
+    serial_println!("elf_loader::rejects_invalid_images...\t");
+    rejects_invalid_images();
+    serial_println!("[ok]!");
+
+    serial_println!("elf_loader::segments_have_correct_permissions...\t");
+    segments_have_correct_permissions(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("elf_loader::runs_program_with_arguments...\t");
+    runs_program_with_arguments(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("elf_loader::destroy_gives_frames_back...\t");
+    destroy_gives_frames_back(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn rejects_invalid_images() {
+    assert!(matches!(ElfFile::parse(b"\x7fELF"), Err(ElfError::TooShort)));
+    assert!(matches!(ElfFile::parse(&[0; 64]), Err(ElfError::BadMagic)));
+
+    let mut image = [0u8; 64];
+    image.copy_from_slice(&HELLO[..64]);
+    image[18] = 3; // EM_386
+    assert!(matches!(ElfFile::parse(&image), Err(ElfError::WrongMachine(3))));
+
+    assert!(ElfFile::parse(HELLO).is_ok());
+}
+
+fn segments_have_correct_permissions(frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let mut address_space = AddressSpace::new(frame_allocator).expect("failed to create address space");
+    elf::load(HELLO, &["hello"], &mut address_space, frame_allocator).expect("failed to load program");
+
+    let elf    = ElfFile::parse(HELLO).expect("failed to parse program");
+    let mapper = address_space.mapper();
+    for ph in elf.program_headers().filter(|ph| ph.p_type == PT_LOAD) {
+        let flags = match mapper.translate(VirtAddr::new(ph.vaddr)) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _                                     => panic!("segment at {:#x} is not mapped", ph.vaddr),
+        };
+
+        assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+        assert_eq!(flags.contains(PageTableFlags::WRITABLE), ph.flags & PF_W != 0, "segment at {:#x}", ph.vaddr);
+        if ph.flags & PF_X != 0 {
+            assert!(!flags.contains(PageTableFlags::NO_EXECUTE), "code segment at {:#x} is not executable", ph.vaddr);
+        }
+    }
+
+    // Nothing of the program leaks into the active address space
+    assert!(memory::translate_addr(elf.entry()).is_none());
+}
+
+fn runs_program_with_arguments(frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let mut address_space = AddressSpace::new(frame_allocator).expect("failed to create address space");
+    let program           = elf::load(HELLO, &["hello", "argv from kernel"], &mut address_space, frame_allocator)
+        .expect("failed to load program");
+
+    let exit_code = unsafe {
+        address_space.activate();
+        let exit_code = syscall::enter_user_mode(program.entry, program.stack_pointer);
+        memory::activate_kernel_address_space();
+        exit_code
+    };
+
+    // Program exits with 0xff if .bss is not zeroed or auxv is wrong, with argc otherwise
+    assert_eq!(exit_code, 2);
+}
+
+fn destroy_gives_frames_back(frame_allocator: &mut BootInfoFrameAllocator) {
+    let mut counting      = CountingAllocator { inner: frame_allocator, outstanding: 0 };
+    let mut address_space = AddressSpace::new(&mut counting).expect("failed to create address space");
+    elf::load(HELLO, &["hello", "argument"], &mut address_space, &mut counting).expect("failed to load program");
+
+    // Level 4 table, tables of program & stack, their pages
+    assert!(counting.outstanding > 4, "only {} frames are allocated", counting.outstanding);
+
+    let level_4_frame = address_space.level_4_frame();
+    address_space.destroy(&mut counting);
+    assert_eq!(counting.outstanding, 0);
+
+    // Given back frames are handed out again, the last one given back goes first
+    let frame = frame_allocator.allocate_frame().expect("out of frames");
+    assert_eq!(frame, level_4_frame);
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
@@ -1,44 +1,51 @@
 #![no_std]
 #![no_main]
-#![feature(panic_info_message)]
 
 extern crate alloc;
 
-use alloc::string::String;
+use alloc::{
+    boxed::Box,
+    rc::Rc,
+    string::String,
+};
 use bootloader::{ entry_point, BootInfo };
-use core::fmt;
-use core::panic::PanicInfo;
+use core::{
+    cell::{ Cell, RefCell },
+    fmt,
+    future,
+    panic::PanicInfo,
+    sync::atomic::{ AtomicBool, Ordering },
+    task::{ Poll, Waker },
+};
 use radius_os::{
     memory::{ self, BootInfoFrameAllocator },
     task::{
+        self,
         executor::Executor,
+        join::{ JoinError, JoinHandle },
+        policy::RoundRobin,
+        spawner,
         Task,
+        TaskState,
     },
     vga::{ WRITER, BUFFER_HEIGHT },
     allocator,
+    gdt,
+    hlt_loop,
     init,
     println,
     qemu_codes,
     serial_println,
+    test_panic_handler,
 };
 use x86_64::VirtAddr;
 
+static ABORTED_DROPPED: AtomicBool = AtomicBool::new(false);
+static DETACHED_DONE:   AtomicBool = AtomicBool::new(false);
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    let expected_output = "async number: 42";
-    let mut output      = String::new();
-
-    if let Some(msg) = info.message() {
-        fmt::write(&mut output, *msg).unwrap();
-    }
-
-    if expected_output == output {
-        serial_println!("executor_test::simple_task_completes... \n[ok]!");
-        qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
-    }
-    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
-
-    loop {}
+    test_panic_handler(info)
 }
 
 entry_point!(main);
@@ -53,22 +60,240 @@ fn main(boot_info: &'static BootInfo) -> ! {
     let mut mapper          = unsafe { memory::init(phys_memory_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
     // <<<<<<<<
 
-    simple_task_completes();
-    serial_println!("[test did not panic]");
-    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
+    let mut executor = Executor::new();
+    let number       = executor.spawn(Task::new(example_task()));
+    let never_done   = executor.spawn(Task::new(never_completes()));
+    executor.spawn(Task::new(detached_task())).detach();
+
+    executor.block_on(check_results(number, never_done));
+
+    serial_println!("executor_test::block_on_returns_output...\t");
+    block_on_returns_output();
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::run_until_idle_leaves_waiting_tasks...\t");
+    run_until_idle_leaves_waiting_tasks();
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::repeated_wakeups_are_deduplicated...\t");
+    repeated_wakeups_are_deduplicated();
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::wakeups_beyond_capacity_are_not_lost...\t");
+    wakeups_beyond_capacity_are_not_lost();
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::tasks_are_listed...\t");
+    tasks_are_listed();
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::shutdown_drops_outstanding_tasks...\t");
+    shutdown_drops_outstanding_tasks();
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    hlt_loop();
+}
+
+async fn check_results(number: JoinHandle<u32>, never_done: JoinHandle<()>) {
+    serial_println!("executor_test::simple_task_completes...\t");
+    simple_task_completes(number).await;
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::aborted_task_is_dropped...\t");
+    aborted_task_is_dropped(never_done).await;
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::detached_task_keeps_running...\t");
+    detached_task_keeps_running().await;
+    serial_println!("[ok]!");
+
+    serial_println!("executor_test::tasks_spawn_tasks...\t");
+    tasks_spawn_tasks().await;
+    serial_println!("[ok]!");
+}
+
+async fn simple_task_completes(number: JoinHandle<u32>) {
+    assert_eq!(number.await, Ok(42));
+
+    let expected_output   = "async number: 42";
+    let writer            = WRITER.lock();
+    let mut actual_output = String::new();
+
+    for (i, _c) in expected_output.chars().enumerate() {
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        fmt::write(&mut actual_output, format_args!("{}", char::from(screen_char.ascii_char))).unwrap();
+    }
+    assert_eq!(actual_output, expected_output);
+}
+
+async fn aborted_task_is_dropped(never_done: JoinHandle<()>) {
+    assert!(!never_done.is_finished());
+
+    never_done.abort();
+    assert_eq!(never_done.await, Err(JoinError::Aborted));
+    assert!(ABORTED_DROPPED.load(Ordering::Relaxed));
+}
+
+async fn detached_task_keeps_running() {
+    for _ in 0..10 {
+        if DETACHED_DONE.load(Ordering::Relaxed) {
+            return;
+        }
+        task::yield_now().await;
+    }
+    panic!("detached task did not complete");
+}
+
+async fn tasks_spawn_tasks() {
+    // Spawned task spawns one more through cloned spawner it has been given
+    let spawner = spawner::current().expect("no spawner inside of a task");
+    let nested  = task::spawn(async move {
+        let inner = spawner.spawn(Task::new(async_number()));
+        inner.await.expect("inner task has been aborted") + 1
+    });
 
-    loop {}
+    assert_eq!(nested.await, Ok(43));
 }
 
-fn simple_task_completes() {
+fn block_on_returns_output() {
     let mut executor = Executor::new();
-    executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(read_task()));
 
-    executor.run();
+    // Future could borrow from the caller, as it is not spawned
+    let mut local = 1;
+    let output    = executor.block_on(async {
+        let spawned = task::spawn(async_number());
+        local      += spawned.await.expect("spawned task has been aborted");
+        local
+    });
+    assert_eq!(output, 43);
+}
+
+fn run_until_idle_leaves_waiting_tasks() {
+    let mut executor = Executor::new();
+    let runs         = Rc::new(Cell::new(0));
+
+    let counter  = runs.clone();
+    let finished = executor.spawn(Task::new(async move {
+        for _ in 0..3 {
+            counter.set(counter.get() + 1);
+            task::yield_now().await;
+        }
+    }));
+    let waiting  = executor.spawn(Task::new(future::pending::<()>()));
+
+    executor.run_until_idle();
+    assert_eq!(runs.get(), 3);
+    assert!(finished.is_finished());
+    assert!(!waiting.is_finished());
+}
+
+fn repeated_wakeups_are_deduplicated() {
+    let mut executor = Executor::new();
+    let polls        = Rc::new(Cell::new(0));
+
+    let counter = polls.clone();
+    let woken   = executor.spawn(Task::new(future::poll_fn(move |context| {
+        counter.set(counter.get() + 1);
+        if counter.get() == 1 {
+            // Far more wakeups than wake queue could hold, task is still queued only once
+            for _ in 0..1000 {
+                context.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    })));
+
+    executor.run_until_idle();
+    assert!(woken.is_finished());
+    assert_eq!(polls.get(), 2);
+}
+
+fn wakeups_beyond_capacity_are_not_lost() {
+    let mut executor = Executor::with_wake_capacity(Box::new(RoundRobin::new()), 2);
+    let done         = Rc::new(Cell::new(0));
+
+    for _ in 0..20 {
+        let done = done.clone();
+        executor.spawn(Task::new(async move {
+            task::yield_now().await;
+            done.set(done.get() + 1);
+        })).detach();
+    }
+
+    executor.run_until_idle();
+    assert_eq!(done.get(), 20);
+}
+
+fn tasks_are_listed() {
+    let mut executor = Executor::new();
+
+    let waiting = executor.spawn(Task::new(future::pending::<()>()).with_name("waiting"));
+    executor.spawn(Task::new(async {
+        task::yield_now().await;
+    }).with_name("short")).detach();
+    let lister  = executor.spawn(Task::new(async {
+        let tasks = spawner::current().expect("no spawner inside of a task").tasks();
+        let me    = tasks.iter().find(|info| info.name == Some("lister")).expect("lister is not listed");
+        me.state
+    }).with_name("lister"));
+
+    let listed = executor.tasks();
+    assert_eq!(listed.len(), 3);
+    assert!(listed.iter().all(|info| info.state == TaskState::Ready && info.polls == 0));
+
+    executor.run_until_idle();
+
+    // Completed tasks are gone, the one that waits has been polled once
+    let listed = executor.tasks();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, waiting.id());
+    assert_eq!(listed[0].name, Some("waiting"));
+    assert_eq!(listed[0].state, TaskState::Waiting);
+    assert_eq!(listed[0].polls, 1);
+
+    assert_eq!(executor.block_on(lister), Ok(TaskState::Running));
+}
+
+fn shutdown_drops_outstanding_tasks() {
+    let mut executor = Executor::new();
+    let shutdown     = executor.shutdown_handle();
+    let stale_waker  = Rc::new(RefCell::new(None::<Waker>));
+
+    let waker_slot = stale_waker.clone();
+    let waiting    = executor.spawn(Task::new(future::poll_fn(move |context| {
+        *waker_slot.borrow_mut() = Some(context.waker().clone());
+        Poll::<()>::Pending
+    })));
+    let stopper    = executor.spawn(Task::new(async move {
+        shutdown.shutdown();
+        // Executor drops this task instead of polling it again
+        task::yield_now().await;
+        panic!("task polled after shutdown");
+    }));
+
+    executor.run_until_idle();
+    assert!(executor.is_shut_down());
+
+    let waiting = executor.block_on(waiting);
+    let stopper = executor.block_on(stopper);
+    assert_eq!(waiting, Err(JoinError::Aborted));
+    assert_eq!(stopper, Err(JoinError::Aborted));
+
+    // Waker outlives its task, waking it (more times than wake queue could hold) does nothing
+    let waker = stale_waker.borrow_mut().take().expect("waiting task has not been polled");
+    for _ in 0..1000 {
+        waker.wake_by_ref();
+    }
+
+    // Tasks spawned after shutdown are dropped right away
+    let late = executor.spawn(Task::new(async_number()));
+    assert_eq!(executor.block_on(late), Err(JoinError::Aborted));
 }
 
 
@@ -76,22 +301,26 @@ async fn async_number() -> u32 {
     42
 }
 
-async fn example_task() {
+async fn example_task() -> u32 {
     let number = async_number().await;
     println!("async number: {}", number);
+    number
 }
 
-async fn read_task() {
-    let assumed_output    = "async number: 42";
-    let writer            = WRITER.lock();
-    let mut actual_output = String::new();
+struct DropFlag;
 
-    // TODO: re-write to make it better :)
-    for (i, _c) in assumed_output.chars().enumerate() {
-        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
-        fmt::write(&mut actual_output, format_args!("{}", char::from(screen_char.ascii_char))).unwrap();
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        ABORTED_DROPPED.store(true, Ordering::Relaxed);
     }
+}
 
-    panic!("{}", actual_output);
+async fn never_completes() {
+    let _flag = DropFlag;
+    future::pending::<()>().await;
 }
 
+async fn detached_task() {
+    task::yield_now().await;
+    DETACHED_DONE.store(true, Ordering::Relaxed);
+}
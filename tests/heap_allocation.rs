@@ -15,6 +15,7 @@ use core::panic::PanicInfo;
 use radius_os::{
     memory::{ self, BootInfoFrameAllocator },
     allocator,
+    gdt,
     init,
     test_panic_handler,
 };
@@ -37,6 +38,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
     let mut mapper          = unsafe { memory::init(phys_memory_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
     // <<<<<<<<
 
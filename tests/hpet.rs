@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    time::{ self, hpet::{ self, Hpet }, TickSource, TICK_HZ },
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+
+const NANOS_PER_TICK: u64 = 1_000_000_000 / TICK_HZ;
+// Long enough for a single tick of difference not to matter much
+const MEASURED_TICKS: u64 = 20;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    // PIT keeps driving timer interrupt until one of HPET's legacy routed timers is started
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    let hpet = hpet::init(&mut mapper, &mut frame_allocator).expect("HPET initialisation failed");
+
+    serial_println!("hpet::uptime_advances...\t");
+    uptime_advances(hpet);
+    serial_println!("[ok]!");
+
+    serial_println!("hpet::uptime_matches_pit...\t");
+    uptime_matches_pit(hpet);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn uptime_advances(hpet: &Hpet) {
+    assert!(hpet.frequency() >= 10_000_000, "HPET runs at {}Hz", hpet.frequency());
+
+    // Uptime is taken from HPET main counter, so it moves on between timer interrupts too
+    let before    = time::uptime_ns();
+    let mut after = time::uptime_ns();
+    while after == before {
+        after = time::uptime_ns();
+    }
+    assert!(after > before, "uptime went back from {} to {}", before, after);
+    assert!(after - before < NANOS_PER_TICK, "uptime only moves on timer interrupts");
+}
+
+fn uptime_matches_pit(hpet: &Hpet) {
+    // Measurement starts right at a tick, so PIT time is exact up to the tick it ends at
+    let ticks = time::ticks();
+    while time::ticks() == ticks {
+        core::hint::spin_loop();
+    }
+    let start_ticks = time::ticks();
+    let start_ns    = hpet.counter_ns();
+
+    while time::ticks() < start_ticks + MEASURED_TICKS {
+        core::hint::spin_loop();
+    }
+    let elapsed_ns = hpet.counter_ns() - start_ns;
+
+    // PIT divisor is rounded, so its ticks are a bit off from TICK_HZ. Allow 5% on top of that
+    let expected_ns  = MEASURED_TICKS * NANOS_PER_TICK;
+    let tolerance_ns = expected_ns / 20 + NANOS_PER_TICK;
+    assert!(
+        elapsed_ns.abs_diff(expected_ns) <= tolerance_ns,
+        "HPET measured {}ns over {} PIT ticks, expected {}ns", elapsed_ns, MEASURED_TICKS, expected_ns
+    );
+}
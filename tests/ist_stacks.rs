@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+};
+use volatile::Volatile;
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{ InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode },
+    VirtAddr,
+};
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    gdt,
+    serial_println,
+    test_panic_handler,
+    qemu_codes,
+};
+
+// Every test continues from the handler of the previous one, as there is no way back after stack overflow:
+// NMI -> machine check -> overflow of machine check stack (page fault) -> overflow of page fault stack (double fault)
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(test_nmi_handler)
+                .set_stack_index(gdt::NMI_IST_IDX);
+            idt.machine_check
+                .set_handler_fn(test_machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_IDX);
+            idt.page_fault
+                .set_handler_fn(test_page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_IDX);
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_IDX);
+        }
+
+        idt
+    };
+
+    // Without page fault handler page fault could not be delivered, thus becomes double fault
+    static ref DOUBLE_FAULT_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_IDX);
+        }
+
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_nmi_handler(_stack_frame: InterruptStackFrame) {
+    assert_on_ist_stack(gdt::NMI_IST_IDX);
+    serial_println!("[ok]!");
+}
+
+extern "x86-interrupt" fn test_machine_check_handler(_stack_frame: InterruptStackFrame) -> ! {
+    assert_on_ist_stack(gdt::MACHINE_CHECK_IST_IDX);
+    serial_println!("[ok]!");
+
+    serial_println!("ist_stacks::page_fault_stack...\t");
+    stack_overflow();
+    panic!("Execution continued after machine check stack overflow");
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(_stack_frame: InterruptStackFrame, _error_code: PageFaultErrorCode) {
+    assert_on_ist_stack(gdt::PAGE_FAULT_IST_IDX);
+
+    // Overflown machine check stack must have hit its guard page
+    let guard_page = gdt::ist_stack(gdt::MACHINE_CHECK_IST_IDX).expect("IST stacks are not allocated").guard_page();
+    assert_eq!(guard_page.start_address(), Cr2::read().align_down(4096u64));
+    serial_println!("[ok]!");
+
+    serial_println!("ist_stacks::double_fault_stack...\t");
+    DOUBLE_FAULT_IDT.load();
+    stack_overflow();
+    panic!("Execution continued after page fault stack overflow");
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(_stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    assert_on_ist_stack(gdt::DOUBLE_FAULT_IST_IDX);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn assert_on_ist_stack(index: u16) {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+
+    let stack = gdt::ist_stack(index).expect("IST stacks are not allocated");
+    assert!(stack.contains(VirtAddr::new(rsp)), "handler runs on {:#x}, outside of IST stack {:?}", rsp, stack);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    gdt::init();
+    TEST_IDT.load();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+
+    // Software interrupts are delivered through the same IDT entries, so they switch stacks the same way
+    serial_println!("ist_stacks::nmi_stack...\t");
+    unsafe { asm!("int 2") };
+
+    serial_println!("ist_stacks::machine_check_stack...\t");
+    unsafe { asm!("int 18") };
+
+    panic!("Execution continued after machine check");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    Volatile::new(0).read(); // to prevent tail recursion optimisation (tail call elimination)
+                             // (among other things, it may transform function into normal for loop (sic!)
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    fmt::{ self, Write },
+    panic::PanicInfo,
+};
+
+use radius_os::{
+    init,
+    qemu_codes,
+    serial_println,
+    spinlock::IrqSpinLock,
+};
+
+static A: IrqSpinLock<u8> = IrqSpinLock::named("lockdep::A", 0);
+static B: IrqSpinLock<u8> = IrqSpinLock::named("lockdep::B", 0);
+static C: IrqSpinLock<u8> = IrqSpinLock::named("lockdep::C", 0);
+
+/// Keeps the beginning of panic message, so it could be checked it is the lockdep report
+struct Message {
+    bytes: [u8; 256],
+    len:   usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message { bytes: [0; 256], len: 0 };
+    let _ = write!(message, "{}", info.message());
+
+    if message.bytes[..message.len].starts_with(b"lockdep: lock order inversion") {
+        serial_println!("[ok]!");
+        qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n{}", info);
+        qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
+    }
+    loop {}
+}
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    init();
+
+    serial_println!("lockdep::consistent_order_is_fine...\t");
+    consistent_order_is_fine();
+    serial_println!("[ok]!");
+
+    serial_println!("lockdep::inversion_panics...\t");
+    inversion_panics();
+    serial_println!("[test did not panic]");
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
+
+    loop {}
+}
+
+fn consistent_order_is_fine() {
+    for _ in 0..2 {
+        let _a = A.lock();
+        let _b = B.lock();
+    }
+    // B is not held anymore, so taking A is fine
+    let b = B.lock();
+    drop(b);
+    let _a = A.lock();
+}
+
+/// A is taken before B & B before C, so taking A while holding C could dead-lock (even if A & C were
+/// never held together before)
+fn inversion_panics() {
+    {
+        let _b = B.lock();
+        let _c = C.lock();
+    }
+
+    let _c = C.lock();
+    let _a = A.lock();
+}
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    fmt::{ self, Write },
+    panic::PanicInfo,
+};
+
+use radius_os::{
+    init,
+    qemu_codes,
+    serial_println,
+    spinlock::IrqSpinLock,
+};
+
+static A: IrqSpinLock<u8> = IrqSpinLock::named("lockdep_recursive::A", 0);
+
+/// Keeps the beginning of panic message, so it could be checked it is the lockdep report
+struct Message {
+    bytes: [u8; 256],
+    len:   usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message { bytes: [0; 256], len: 0 };
+    let _ = write!(message, "{}", info.message());
+
+    if message.bytes[..message.len].starts_with(b"lockdep: recursive locking detected on CPU 0") {
+        serial_println!("[ok]!");
+        qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n{}", info);
+        qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
+    }
+    loop {}
+}
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    init();
+
+    serial_println!("lockdep_recursive::try_lock_of_held_lock_is_fine...\t");
+    try_lock_of_held_lock_is_fine();
+    serial_println!("[ok]!");
+
+    serial_println!("lockdep_recursive::recursive_lock_panics...\t");
+    recursive_lock_panics();
+    serial_println!("[test did not panic]");
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Failure);
+
+    loop {}
+}
+
+/// Trylock can not dead-lock, it just fails
+fn try_lock_of_held_lock_is_fine() {
+    let _a = A.lock();
+    assert!(A.try_lock().is_none());
+}
+
+/// Second lock() would spin forever, lockdep reports it instead
+fn recursive_lock_panics() {
+    let _a = A.lock();
+    let _again = A.lock();
+}
@@ -0,0 +1,230 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    fmt::{ self, Write },
+    panic::PanicInfo,
+    sync::atomic::{ AtomicBool, Ordering },
+};
+use x86_64::VirtAddr;
+
+use radius_os::{
+    log::{ self, Level, LevelFilter, LogError, Record, Sink },
+    memory::{ self, BootInfoFrameAllocator },
+    spinlock::IrqSpinLock,
+    time::{ self, TickSource },
+    allocator,
+    debug,
+    error,
+    gdt,
+    info,
+    init,
+    qemu_codes,
+    serial_println,
+    smp,
+    test_panic_handler,
+    warn,
+};
+
+const TIMEOUT_NS: u64 = 1_000_000_000;
+
+/// Last record that reached the sink
+#[derive(Clone, Copy)]
+struct Captured {
+    count:        usize,
+    level:        Option<Level>,
+    module:       &'static str,
+    cpu:          usize,
+    timestamp_ns: u64,
+    message:      [u8; 64],
+    len:          usize,
+}
+
+struct CaptureSink {
+    captured: IrqSpinLock<Captured>,
+}
+
+static CAPTURE: CaptureSink = CaptureSink {
+    captured: IrqSpinLock::new(Captured {
+        count:        0,
+        level:        None,
+        module:       "",
+        cpu:          0,
+        timestamp_ns: 0,
+        message:      [0; 64],
+        len:          0,
+    }),
+};
+
+impl Sink for CaptureSink {
+    fn name(&self) -> &'static str {
+        "capture"
+    }
+
+    fn write(&self, record: &Record) {
+        let mut captured = self.captured.lock();
+        captured.count       += 1;
+        captured.level        = Some(record.level);
+        captured.module       = record.module;
+        captured.cpu          = record.cpu;
+        captured.timestamp_ns = record.timestamp_ns;
+        captured.len          = 0;
+        let _ = write!(captured, "{}", record.args);
+    }
+}
+
+impl Write for Captured {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.message.len() - self.len);
+        self.message[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn message(&self) -> &[u8] {
+        &self.message[..self.len]
+    }
+}
+
+fn captured() -> Captured {
+    *CAPTURE.captured.lock()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    // Screen is left alone, COM1 already shows test progress
+    assert!(log::remove_sink("vga"));
+    log::add_sink(&CAPTURE).expect("failed to add sink");
+    assert_eq!(log::add_sink(&CAPTURE), Err(LogError::SinkExists));
+
+    serial_println!("log::records_reach_sinks...\t");
+    records_reach_sinks();
+    serial_println!("[ok]!");
+
+    serial_println!("log::level_is_adjustable...\t");
+    level_is_adjustable();
+    serial_println!("[ok]!");
+
+    serial_println!("log::module_filters...\t");
+    module_filters();
+    serial_println!("[ok]!");
+
+    serial_println!("log::records_go_to_dmesg...\t");
+    records_go_to_dmesg();
+    serial_println!("[ok]!");
+
+    let cpus = smp::init(&boot_info.memory_map, &mut mapper, &mut frame_allocator).expect("SMP initialisation failed");
+    assert!(cpus > 1, "test needs more than one CPU");
+
+    serial_println!("log::records_carry_cpu...\t");
+    records_carry_cpu(cpus - 1);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn records_reach_sinks() {
+    let before = captured();
+    time::busy_wait_ns(20_000_000);
+    info!("answer is {}", 42);
+
+    let after = captured();
+    assert_eq!(after.count, before.count + 1);
+    assert_eq!(after.level, Some(Level::Info));
+    assert_eq!(after.module, "log");
+    assert_eq!(after.message(), b"answer is 42");
+    assert!(after.timestamp_ns >= 20_000_000);
+}
+
+fn level_is_adjustable() {
+    assert_eq!(log::level(), LevelFilter::Info);
+    let before = captured().count;
+
+    debug!("dropped");
+    assert_eq!(captured().count, before);
+
+    log::set_level(LevelFilter::Debug);
+    debug!("kept");
+    assert_eq!(captured().count, before + 1);
+
+    log::set_level(LevelFilter::Error);
+    warn!("dropped");
+    error!("kept");
+    assert_eq!(captured().count, before + 2);
+    assert!(!log::enabled(Level::Warn, module_path!()));
+
+    log::set_level(LevelFilter::Info);
+}
+
+fn module_filters() {
+    log::set_module_level("log", LevelFilter::Off).expect("failed to set filter");
+    assert!(!log::enabled(Level::Error, "log"));
+    assert!(!log::enabled(Level::Error, "log::inner"));
+    // Only whole path components match
+    assert!(log::enabled(Level::Error, "logger"));
+
+    log::set_module_level("log::inner", LevelFilter::Trace).expect("failed to set filter");
+    assert!(log::enabled(Level::Trace, "log::inner"));
+    assert!(!log::enabled(Level::Error, "log"));
+
+    assert!(log::clear_module_level("log"));
+    assert!(log::clear_module_level("log::inner"));
+    assert!(!log::clear_module_level("log"));
+    assert!(log::enabled(Level::Info, "log"));
+    assert!(!log::enabled(Level::Trace, "log::inner"));
+}
+
+fn records_go_to_dmesg() {
+    let mut buf = [0; 512];
+
+    log::clear_dmesg();
+    assert_eq!(log::read_dmesg(&mut buf), "");
+
+    info!("first");
+    warn!("second");
+
+    let text      = log::read_dmesg(&mut buf);
+    let mut lines = text.lines();
+    assert!(lines.next().expect("first line is missing").ends_with(" cpu0 INFO  log: first"));
+    assert!(lines.next().expect("second line is missing").ends_with(" cpu0 WARN  log: second"));
+    assert!(lines.next().is_none());
+}
+
+fn records_carry_cpu(cpu: usize) {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    smp::run_on(cpu, || {
+        info!("from application processor");
+        DONE.store(true, Ordering::Release);
+    }).expect("failed to queue job");
+
+    let deadline = time::uptime_ns() + TIMEOUT_NS;
+    while !DONE.load(Ordering::Acquire) {
+        assert!(time::uptime_ns() < deadline, "job has not run in time");
+        core::hint::spin_loop();
+    }
+
+    let captured = captured();
+    assert_eq!(captured.message(), b"from application processor");
+    assert_eq!(captured.cpu, cpu);
+}
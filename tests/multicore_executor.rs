@@ -0,0 +1,177 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{ AtomicU64, AtomicUsize, Ordering },
+};
+use x86_64::VirtAddr;
+
+use radius_os::{
+    interrupts::{ self, WAKEUP_VECTOR },
+    memory::{ self, BootInfoFrameAllocator },
+    task::{
+        channel::oneshot,
+        join::JoinError,
+        multicore::{ self, MultiCoreExecutor },
+    },
+    time::{ self, TickSource },
+    allocator,
+    gdt,
+    init,
+    percpu,
+    qemu_codes,
+    serial_println,
+    smp,
+    test_panic_handler,
+};
+
+const TIMEOUT_NS: u64 = 1_000_000_000;
+
+percpu! {
+    // Tasks polled to completion on the CPU, see tasks_spread_over_cpus
+    static COMPLETED: AtomicU64 = AtomicU64::new(0);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    let cpus = smp::init(&boot_info.memory_map, &mut mapper, &mut frame_allocator).expect("SMP initialisation failed");
+    assert!(cpus > 1, "test needs more than one CPU");
+
+    serial_println!("multicore_executor::tasks_spread_over_cpus...\t");
+    tasks_spread_over_cpus(cpus);
+    serial_println!("[ok]!");
+
+    serial_println!("multicore_executor::wakeup_reaches_other_cpu...\t");
+    wakeup_reaches_other_cpu();
+    serial_println!("[ok]!");
+
+    serial_println!("multicore_executor::tasks_spawn_tasks...\t");
+    tasks_spawn_tasks();
+    serial_println!("[ok]!");
+
+    serial_println!("multicore_executor::shutdown_aborts_waiting_tasks...\t");
+    shutdown_aborts_waiting_tasks();
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn tasks_spread_over_cpus(cpus: usize) {
+    const TASKS: usize = 32;
+
+    let executor = MultiCoreExecutor::new();
+    assert_eq!(executor.cpu_count(), cpus);
+
+    // All tasks start in the boot CPU's queue, busy enough that idle CPUs steal them
+    let handles = (0..TASKS)
+        .map(|_| executor.spawn(async {
+            time::busy_wait_ns(2_000_000);
+            COMPLETED.get().fetch_add(1, Ordering::Relaxed);
+        }))
+        .collect::<Vec<_>>();
+
+    executor.block_on(async {
+        for handle in handles {
+            handle.await.expect("task has been aborted");
+        }
+    });
+
+    let completed = (0..cpus).map(|cpu| COMPLETED.for_cpu(cpu).load(Ordering::Relaxed)).collect::<Vec<_>>();
+    assert_eq!(completed.iter().sum::<u64>(), TASKS as u64);
+    assert!(completed.iter().filter(|count| **count > 0).count() > 1, "all tasks ran on one CPU: {:?}", completed);
+
+    let stats = executor.stats();
+    assert!(stats.iter().map(|stats| stats.steals).sum::<u64>() > 0, "no task has been stolen: {:?}", stats);
+    assert!(stats.iter().map(|stats| stats.polls).sum::<u64>() >= TASKS as u64);
+}
+
+fn wakeup_reaches_other_cpu() {
+    static POLLED_ON: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    let executor           = MultiCoreExecutor::new();
+    let (sender, receiver) = oneshot::channel::<u32>();
+
+    let waiting = executor.spawn(async move {
+        POLLED_ON.store(percpu::current_cpu(), Ordering::Release);
+        receiver.await.expect("sender has gone")
+    });
+
+    let value = executor.block_on(async {
+        // Boot CPU is busy right here, so the task has to be stolen by an application processor
+        let deadline = time::uptime_ns() + TIMEOUT_NS;
+        while POLLED_ON.load(Ordering::Acquire) == usize::MAX {
+            assert!(time::uptime_ns() < deadline, "task has not been stolen");
+            core::hint::spin_loop();
+        }
+        // Let it park on the receiver & its CPU halt
+        time::busy_wait_ns(20_000_000);
+
+        let ipis = interrupts::stats().count(WAKEUP_VECTOR);
+        sender.send(42).expect("receiver has gone");
+        let result = waiting.await.expect("task has been aborted");
+
+        assert!(interrupts::stats().count(WAKEUP_VECTOR) > ipis, "no wakeup IPI has been sent");
+        result
+    });
+
+    assert_ne!(POLLED_ON.load(Ordering::Acquire), 0);
+    assert_eq!(value, 42);
+}
+
+fn tasks_spawn_tasks() {
+    let executor = MultiCoreExecutor::new();
+
+    let sum = executor.block_on(executor.spawn(async {
+        let handles = (1..=10u64)
+            .map(|value| multicore::spawn(async move { value * 2 }))
+            .collect::<Vec<_>>();
+
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await.expect("task has been aborted");
+        }
+        sum
+    }));
+
+    assert_eq!(sum, Ok(110));
+}
+
+fn shutdown_aborts_waiting_tasks() {
+    let executor            = MultiCoreExecutor::new();
+    let (_sender, receiver) = oneshot::channel::<u32>();
+
+    let waiting = executor.spawn(receiver);
+    executor.block_on(async {
+        // Let the task park on the receiver
+        time::busy_wait_ns(20_000_000);
+    });
+
+    executor.shutdown();
+    assert!(executor.is_shut_down());
+    assert!(matches!(executor.block_on(waiting), Err(JoinError::Aborted)));
+
+    let late = executor.spawn(async {});
+    assert!(matches!(executor.block_on(late), Err(JoinError::Aborted)));
+}
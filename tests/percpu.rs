@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    cell::Cell,
+    panic::PanicInfo,
+    sync::atomic::{ AtomicU64, AtomicUsize, Ordering },
+};
+use x86_64::VirtAddr;
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    time::{ self, TickSource },
+    allocator,
+    gdt,
+    init,
+    percpu,
+    qemu_codes,
+    serial_println,
+    smp,
+    test_panic_handler,
+};
+
+const TIMEOUT_NS: u64 = 1_000_000_000;
+
+percpu! {
+    static SEEN_INDEX: AtomicUsize = AtomicUsize::new(usize::MAX);
+    static STACK_TOP:  AtomicU64   = AtomicU64::new(0);
+    static COUNTER:    Cell<u64>   = Cell::new(0);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    serial_println!("percpu::boot_cpu_is_first...\t");
+    boot_cpu_is_first();
+    serial_println!("[ok]!");
+
+    let cpus = smp::init(&boot_info.memory_map, &mut mapper, &mut frame_allocator).expect("SMP initialisation failed");
+    assert!(cpus > 1, "test needs more than one CPU");
+
+    serial_println!("percpu::every_cpu_sees_its_own_copy...\t");
+    every_cpu_sees_its_own_copy(cpus);
+    serial_println!("[ok]!");
+
+    serial_println!("percpu::every_cpu_has_its_own_ist_stacks...\t");
+    every_cpu_has_its_own_ist_stacks(cpus);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn boot_cpu_is_first() {
+    assert_eq!(percpu::current_cpu(), 0);
+
+    COUNTER.with(|counter| counter.set(counter.get() + 1));
+    COUNTER.with(|counter| assert_eq!(counter.get(), 1));
+}
+
+fn every_cpu_sees_its_own_copy(cpus: usize) {
+    SEEN_INDEX.get().store(percpu::current_cpu(), Ordering::Release);
+    run_everywhere(cpus, || {
+        COUNTER.with(|counter| counter.set(counter.get() + 10));
+        SEEN_INDEX.get().store(percpu::current_cpu(), Ordering::Release);
+    });
+
+    for cpu in 0..cpus {
+        assert_eq!(SEEN_INDEX.for_cpu(cpu).load(Ordering::Acquire), cpu);
+    }
+    // Jobs of other CPUs have not touched the boot CPU's copy
+    COUNTER.with(|counter| assert_eq!(counter.get(), 1));
+}
+
+fn every_cpu_has_its_own_ist_stacks(cpus: usize) {
+    let double_fault_stack_top = || gdt::ist_stack(gdt::DOUBLE_FAULT_IST_IDX).expect("IST stacks are not allocated").top();
+
+    STACK_TOP.get().store(double_fault_stack_top().as_u64(), Ordering::Release);
+    run_everywhere(cpus, move || STACK_TOP.get().store(double_fault_stack_top().as_u64(), Ordering::Release));
+
+    for cpu in 0..cpus {
+        let top = STACK_TOP.for_cpu(cpu).load(Ordering::Acquire);
+        assert_ne!(top, 0);
+        assert!((0..cpu).all(|other| STACK_TOP.for_cpu(other).load(Ordering::Acquire) != top));
+    }
+}
+
+/// Runs `job` on every application processor & waits until all of them are done
+fn run_everywhere(cpus: usize, job: impl Fn() + Clone + Send + 'static) {
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    DONE.store(0, Ordering::Release);
+
+    for cpu in 1..cpus {
+        let job = job.clone();
+        smp::run_on(cpu, move || {
+            job();
+            DONE.fetch_add(1, Ordering::AcqRel);
+        }).expect("failed to queue job");
+    }
+
+    let deadline = time::uptime_ns() + TIMEOUT_NS;
+    while DONE.load(Ordering::Acquire) < cpus - 1 {
+        assert!(time::uptime_ns() < deadline, "jobs have not run in time");
+        core::hint::spin_loop();
+    }
+}
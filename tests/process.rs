@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use bootloader::{ entry_point, BootInfo };
+use core::panic::PanicInfo;
+use x86_64::{
+    structures::paging::{ FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB },
+    VirtAddr,
+};
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    process::{ self, fd::{ self, ConsoleSink, File, STDOUT }, Pid, ProcessError, ProcessState },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    syscall,
+    test_panic_handler,
+};
+
+// Built from tests/programs/hello.S: prints argv[1] & exits with argc
+static HELLO: &[u8] = include_bytes!("programs/hello");
+
+// Loop is long enough to run out of frames if any of them leaked, even if only one per process
+const SPAWN_REAP_ROUNDS: usize = 512;
+// Frames that could be allocated at once, a few processes' worth
+const FRAME_LIMIT:       usize = 256;
+
+/// Hands out at most FRAME_LIMIT frames that have not been given back yet
+struct LimitedAllocator<'a> {
+    inner:       &'a mut BootInfoFrameAllocator,
+    outstanding: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for LimitedAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.outstanding == FRAME_LIMIT {
+            return None;
+        }
+
+        let frame = self.inner.allocate_frame()?;
+        self.outstanding += 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for LimitedAllocator<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.outstanding -= 1;
+        self.inner.deallocate_frame(frame);
+    }
+}
+
+/// Drops console output, so the loop does not flood serial
+struct Discard;
+
+impl ConsoleSink for Discard {
+    fn write(&self, _bytes: &[u8]) {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    syscall::init(&mut mapper, &mut frame_allocator).expect("syscall initialisation failed");
+
+    serial_println!("process::spawn_adds_process_to_table...\t");
+    spawn_adds_process_to_table(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("process::exited_process_is_zombie_until_waited...\t");
+    exited_process_is_zombie_until_waited(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("process::wait_runs_child...\t");
+    wait_runs_child(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("process::reaping_frees_frames...\t");
+    reaping_frees_frames(&mut frame_allocator);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn spawn_adds_process_to_table(frame_allocator: &mut BootInfoFrameAllocator) {
+    let first  = process::spawn(HELLO, &["hello", "first"], frame_allocator).expect("failed to spawn process");
+    let second = process::spawn(HELLO, &["hello", "second"], frame_allocator).expect("failed to spawn process");
+    assert!(first < second);
+
+    let processes = process::list();
+    assert_eq!(processes.len(), 2);
+    for (info, pid) in processes.iter().zip([first, second]) {
+        assert_eq!(info.pid, pid);
+        assert_eq!(info.parent, Pid::KERNEL);
+        assert_eq!(info.name, "hello");
+        assert_eq!(info.state, ProcessState::Running);
+        assert_eq!(info.exit_status, None);
+    }
+    assert_eq!(process::with_process(first, |process| process.fds().get(STDOUT)), Some(Some(File::Console)));
+
+    // Clean up, so other tests start with empty table
+    assert_eq!(process::wait(first, frame_allocator).expect("failed to wait for process"), 2);
+    assert_eq!(process::wait(second, frame_allocator).expect("failed to wait for process"), 2);
+    assert!(process::list().is_empty());
+}
+
+fn exited_process_is_zombie_until_waited(frame_allocator: &mut BootInfoFrameAllocator) {
+    let pid = process::spawn(HELLO, &["hello", "zombie"], frame_allocator).expect("failed to spawn process");
+
+    process::run(pid).expect("failed to run process");
+    assert_eq!(process::state(pid), Some(ProcessState::Zombie));
+    assert!(matches!(process::run(pid), Err(ProcessError::NotRunnable(_))));
+
+    assert_eq!(process::wait(pid, frame_allocator).expect("failed to wait for process"), 2);
+    assert_eq!(process::state(pid), None);
+    assert!(matches!(process::wait(pid, frame_allocator), Err(ProcessError::NoSuchProcess(_))));
+}
+
+fn wait_runs_child(frame_allocator: &mut BootInfoFrameAllocator) {
+    // Program exits with 0xff when it gets less than 2 arguments
+    let pid = process::spawn(HELLO, &["hello"], frame_allocator).expect("failed to spawn process");
+
+    assert_eq!(process::wait(pid, frame_allocator).expect("failed to wait for process"), 0xff);
+    assert_eq!(process::current_pid(), Pid::KERNEL);
+}
+
+fn reaping_frees_frames(frame_allocator: &mut BootInfoFrameAllocator) {
+    let mut limited = LimitedAllocator { inner: frame_allocator, outstanding: 0 };
+
+    fd::set_console_sink(Some(&Discard));
+    for _ in 0..SPAWN_REAP_ROUNDS {
+        let pid = process::spawn(HELLO, &["hello", "again"], &mut limited).expect("failed to spawn process");
+        assert!(limited.outstanding > 0);
+
+        assert_eq!(process::wait(pid, &mut limited).expect("failed to wait for process"), 2);
+        assert_eq!(limited.outstanding, 0);
+    }
+    fd::set_console_sink(None);
+}
@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{ AtomicU64, Ordering },
+};
+use x86_64::VirtAddr;
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    task::{
+        self,
+        executor::Executor,
+        policy::{ EarliestDeadline, FixedPriority, PolicyKind, Priority, RoundRobin, SchedParams, SchedulingPolicy },
+        Task,
+        TaskId,
+    },
+    time::{ self, TickSource },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+
+// Every hog burns a whole tick before it yields, so with round-robin urgent task
+// would wait for HOGS ticks before each of its steps
+const HOGS:            usize = 8;
+const URGENT_STEPS:    usize = 3;
+const URGENT_DEADLINE: u64   = 2; // ticks after spawn
+
+static HOG_CHUNKS: AtomicU64 = AtomicU64::new(0);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    serial_println!("scheduling_policy::round_robin_keeps_wake_order...\t");
+    round_robin_keeps_wake_order();
+    serial_println!("[ok]!");
+
+    serial_println!("scheduling_policy::fixed_priority_ages_waiting_tasks...\t");
+    fixed_priority_ages_waiting_tasks();
+    serial_println!("[ok]!");
+
+    serial_println!("scheduling_policy::edf_picks_earliest_deadline...\t");
+    edf_picks_earliest_deadline();
+    serial_println!("[ok]!");
+
+    // Executor never returns, urgent task exits QEMU once it is done
+    serial_println!("scheduling_policy::urgent_task_meets_deadline_among_hogs...\t");
+    urgent_task_meets_deadline_among_hogs();
+}
+
+fn task_ids(count: usize) -> Vec<TaskId> {
+    (0..count).map(|_| Task::new(async {}).id()).collect()
+}
+
+fn drain(policy: &mut dyn SchedulingPolicy) -> Vec<TaskId> {
+    core::iter::from_fn(|| policy.pick_next()).collect()
+}
+
+fn round_robin_keeps_wake_order() {
+    let ids        = task_ids(3);
+    let mut policy = RoundRobin::new();
+
+    for (&id, priority) in ids.iter().zip([Priority::LOW, Priority::HIGH, Priority::NORMAL]) {
+        policy.enqueue(id, SchedParams { priority, deadline: None });
+    }
+    assert_eq!(drain(&mut policy), ids);
+    assert!(policy.is_empty());
+}
+
+fn fixed_priority_ages_waiting_tasks() {
+    const AGING_ROUNDS: u64 = 2;
+    let low  = Priority(0);
+    let high = Priority(4);
+
+    let ids        = task_ids(2);
+    let mut policy = FixedPriority::new(AGING_ROUNDS);
+
+    policy.enqueue(ids[0], SchedParams { priority: Priority::NORMAL, deadline: None });
+    policy.enqueue(ids[1], SchedParams { priority: Priority::HIGH, deadline: None });
+    assert_eq!(drain(&mut policy), [ids[1], ids[0]]);
+
+    // Fresh high priority task is always ready, yet low priority one gets its turn
+    // once it has waited long enough to catch up
+    let starved = task_ids(1)[0];
+    policy.enqueue(starved, SchedParams { priority: low, deadline: None });
+
+    let max_picks = u64::from(high.0 - low.0) * AGING_ROUNDS + 1;
+    let picked_at = (1..=max_picks).find(|_| {
+        policy.enqueue(task_ids(1)[0], SchedParams { priority: high, deadline: None });
+        policy.pick_next() == Some(starved)
+    });
+    assert_eq!(picked_at, Some(max_picks));
+}
+
+fn edf_picks_earliest_deadline() {
+    let ids        = task_ids(4);
+    let mut policy = EarliestDeadline::new();
+
+    for (&id, deadline) in ids.iter().zip([Some(30), None, Some(10), Some(10)]) {
+        policy.enqueue(id, SchedParams { priority: Priority::NORMAL, deadline });
+    }
+    assert_eq!(drain(&mut policy), [ids[2], ids[3], ids[0], ids[1]]);
+}
+
+fn urgent_task_meets_deadline_among_hogs() -> ! {
+    let mut executor = Executor::with_policy(PolicyKind::FixedPriority.create());
+    assert_eq!(executor.policy_name(), "fixed-priority");
+
+    for _ in 0..HOGS {
+        executor.spawn(Task::new(hog()).with_priority(Priority::LOW));
+    }
+
+    // Urgent task is spawned last, so it would be the last one to run under round-robin
+    let deadline = time::ticks() + URGENT_DEADLINE;
+    executor.spawn(Task::new(urgent(deadline)).with_priority(Priority::HIGH).with_deadline(deadline));
+
+    executor.run();
+}
+
+async fn hog() {
+    loop {
+        let tick = time::ticks();
+        while time::ticks() == tick {
+            core::hint::spin_loop();
+        }
+
+        HOG_CHUNKS.fetch_add(1, Ordering::Relaxed);
+        task::yield_now().await;
+    }
+}
+
+async fn urgent(deadline: u64) {
+    for _ in 0..URGENT_STEPS {
+        task::yield_now().await;
+    }
+
+    let now = time::ticks();
+    assert!(now <= deadline, "missed deadline: done at tick {}, deadline {}", now, deadline);
+    assert!(HOG_CHUNKS.load(Ordering::Relaxed) < HOGS as u64);
+
+    serial_println!("[ok]!");
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+}
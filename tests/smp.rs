@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{ AtomicUsize, Ordering },
+};
+use x86_64::VirtAddr;
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    smp::{ self, SmpError },
+    time::{ self, TickSource },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+
+// Matches "-smp" of test-args in Cargo.toml
+const EXPECTED_CPUS: usize = 4;
+const TIMEOUT_NS:    u64   = 1_000_000_000;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    serial_println!("smp::application_processors_come_online...\t");
+    let cpus = smp::init(&boot_info.memory_map, &mut mapper, &mut frame_allocator).expect("SMP initialisation failed");
+    application_processors_come_online(cpus);
+    serial_println!("[ok]!");
+
+    serial_println!("smp::jobs_run_on_requested_cpu...\t");
+    jobs_run_on_requested_cpu();
+    serial_println!("[ok]!");
+
+    serial_println!("smp::boot_cpu_rejects_jobs...\t");
+    boot_cpu_rejects_jobs();
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn application_processors_come_online(cpus: usize) {
+    assert_eq!(cpus, EXPECTED_CPUS);
+    assert_eq!(smp::cpu_count(), EXPECTED_CPUS);
+    assert_eq!(smp::current_cpu(), 0);
+
+    let infos = smp::cpus();
+    assert_eq!(infos.len(), EXPECTED_CPUS);
+    for (index, info) in infos.iter().enumerate() {
+        assert_eq!(info.index, index);
+        assert!(info.online, "CPU {} is offline", index);
+        assert!(infos[..index].iter().all(|other| other.apic_id != info.apic_id));
+    }
+}
+
+fn jobs_run_on_requested_cpu() {
+    // CPU index + 1 every job has seen, 0 until the job has run
+    static RAN_ON: [AtomicUsize; EXPECTED_CPUS] = [
+        AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    ];
+
+    for (cpu, ran_on) in RAN_ON.iter().enumerate().skip(1) {
+        smp::run_on(cpu, move || ran_on.store(smp::current_cpu() + 1, Ordering::Release)).expect("failed to queue job");
+    }
+
+    let deadline = time::uptime_ns() + TIMEOUT_NS;
+    while RAN_ON[1..].iter().any(|ran_on| ran_on.load(Ordering::Acquire) == 0) {
+        assert!(time::uptime_ns() < deadline, "jobs have not run in time");
+        core::hint::spin_loop();
+    }
+
+    for (cpu, ran_on) in RAN_ON.iter().enumerate().skip(1) {
+        assert_eq!(ran_on.load(Ordering::Acquire), cpu + 1);
+    }
+}
+
+fn boot_cpu_rejects_jobs() {
+    assert!(matches!(smp::run_on(0, || {}), Err(SmpError::BootCpu)));
+    assert!(matches!(smp::run_on(EXPECTED_CPUS, || {}), Err(SmpError::NoSuchCpu(EXPECTED_CPUS))));
+}
@@ -2,12 +2,22 @@
 #![no_main]
 #![feature(abi_x86_interrupt)]
 
+use bootloader::{ entry_point, BootInfo };
 use volatile::Volatile;
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{ InterruptDescriptorTable, InterruptStackFrame };
+use x86_64::{
+    structures::idt::{ InterruptDescriptorTable, InterruptStackFrame },
+    VirtAddr,
+};
 
-use radius_os::{ gdt, serial_println, test_panic_handler, qemu_codes };
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    gdt,
+    serial_println,
+    test_panic_handler,
+    qemu_codes,
+};
 
 lazy_static! {
     static ref TEST_IDT: InterruptDescriptorTable = {
@@ -38,13 +48,22 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+entry_point!(main);
+
+// Kernel stack overflow hits guard page, page fault has no handler there, so it becomes double fault,
+// which could only be handled on its own IST stack
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_println!("stack_overflow::stack_overflow...\t");
 
     gdt::init();
     init_test_idt();
 
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+
     stack_overflow();
     panic!("Execution continued after stack overflow");
 }
@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    arch::global_asm,
+    panic::PanicInfo,
+    ptr,
+};
+use x86_64::{
+    structures::paging::{ FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB },
+    VirtAddr,
+};
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    process::fd::{ self, ConsoleSink },
+    spinlock::IrqSpinLock,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    syscall,
+    test_panic_handler,
+};
+
+// Kernel never maps anything into user region, so page tables for it are created user accessible
+const USER_CODE:  u64 = memory::USER_START;
+const USER_STACK: u64 = memory::USER_START + 0x1_0000;
+
+const USER_MESSAGE: &str = "hello from ring 3\n";
+
+// Position independent program, which is copied into user page: writes message to stdout
+// and exits with whatever write returned (i.e. number of bytes written)
+global_asm!(r#"
+.global user_program_start
+.global user_program_end
+user_program_start:
+    mov eax, 0
+    mov edi, 1
+    lea rsi, [rip + user_message]
+    mov edx, user_message_end - user_message
+    syscall
+
+    mov rdi, rax
+    mov eax, 1
+    syscall
+    ud2
+
+user_message:
+    .ascii "hello from ring 3\n"
+user_message_end:
+user_program_end:
+"#);
+
+/// Keeps console output, so the test could check what user program has written
+struct Capture {
+    bytes: IrqSpinLock<([u8; 64], usize)>,
+}
+
+static CAPTURE: Capture = Capture { bytes: IrqSpinLock::new(([0; 64], 0)) };
+
+impl ConsoleSink for Capture {
+    fn write(&self, bytes: &[u8]) {
+        let mut captured = self.bytes.lock();
+        let (buf, len)   = &mut *captured;
+        let count        = bytes.len().min(buf.len() - *len);
+        buf[*len..*len + count].copy_from_slice(&bytes[..count]);
+        *len += count;
+    }
+}
+
+extern "C" {
+    static user_program_start: u8;
+    static user_program_end:   u8;
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    syscall::init(&mut mapper, &mut frame_allocator).expect("syscall initialisation failed");
+
+    serial_println!("syscall::write_from_user_mode...\t");
+    write_from_user_mode(&mut mapper, &mut frame_allocator);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn write_from_user_mode(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    for addr in [USER_CODE, USER_STACK] {
+        let page  = Page::containing_address(VirtAddr::new(addr));
+        let frame = frame_allocator.allocate_frame().expect("out of frames");
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator).expect("failed to map user page").flush() };
+    }
+
+    fd::set_console_sink(Some(&CAPTURE));
+    let exit_code = unsafe {
+        let start = &user_program_start as *const u8;
+        let len   = (&user_program_end as *const u8).offset_from(start) as usize;
+        ptr::copy_nonoverlapping(start, USER_CODE as *mut u8, len);
+
+        syscall::enter_user_mode(VirtAddr::new(USER_CODE), VirtAddr::new(USER_STACK + 4096))
+    };
+    fd::set_console_sink(None);
+
+    assert_eq!(exit_code, USER_MESSAGE.len() as u64, "write from user mode failed");
+    let (buf, len) = *CAPTURE.bytes.lock();
+    assert_eq!(&buf[..len], USER_MESSAGE.as_bytes());
+}
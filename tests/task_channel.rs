@@ -0,0 +1,209 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{ entry_point, BootInfo };
+use conquer_once::spin::OnceCell;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{ AtomicU64, Ordering },
+};
+use radius_os::{
+    interrupts::{ self, InterruptIndex, IrqHandler },
+    memory::{ self, BootInfoFrameAllocator },
+    task::{
+        self,
+        channel::{ broadcast, mpsc, oneshot, TryRecvError, TrySendError },
+        executor::Executor,
+        Task,
+    },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+use x86_64::VirtAddr;
+
+// Timer handler the test one chains to & the sender it reports ticks with
+static TIMER_HANDLER: OnceCell<IrqHandler>        = OnceCell::uninit();
+static TICKS:         OnceCell<mpsc::Sender<u64>> = OnceCell::uninit();
+static TICK_COUNT:    AtomicU64                   = AtomicU64::new(0);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+
+    // Executor never returns, so the only task runs tests one by one & exits QEMU
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests()));
+    executor.run();
+}
+
+async fn run_tests() {
+    serial_println!("task_channel::bounded_sender_waits_for_room...\t");
+    bounded_sender_waits_for_room().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_channel::receiver_sees_senders_gone...\t");
+    receiver_sees_senders_gone().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_channel::oneshot_delivers_value...\t");
+    oneshot_delivers_value().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_channel::broadcast_reaches_every_receiver...\t");
+    broadcast_reaches_every_receiver().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_channel::interrupt_handler_sends...\t");
+    interrupt_handler_sends().await;
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+}
+
+async fn bounded_sender_waits_for_room() {
+    let (sender, mut receiver) = mpsc::channel(2);
+
+    let producer = task::spawn(async move {
+        for value in 0..10 {
+            sender.send(value).await.expect("receiver is gone");
+        }
+        // Channel stays full until consumer catches up
+        assert!(matches!(sender.try_send(10), Err(TrySendError::Full(10))));
+    });
+
+    task::yield_now().await;
+    let mut received = Vec::new();
+    while let Some(value) = receiver.recv().await {
+        received.push(value);
+    }
+
+    assert_eq!(received, (0..10).collect::<Vec<_>>());
+    assert_eq!(producer.await, Ok(()));
+}
+
+async fn receiver_sees_senders_gone() {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let other                  = sender.clone();
+
+    sender.send(1).expect("receiver is gone");
+    drop(sender);
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+    task::spawn(async move {
+        other.send(2).expect("receiver is gone");
+    }).detach();
+
+    assert_eq!(receiver.recv().await, Some(2));
+    assert_eq!(receiver.recv().await, None);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+
+    let (sender, receiver) = mpsc::channel::<u32>(1);
+    drop(receiver);
+    assert!(sender.is_closed());
+}
+
+async fn oneshot_delivers_value() {
+    let (sender, receiver) = oneshot::channel();
+    task::spawn(async move {
+        task::yield_now().await;
+        sender.send(7).expect("receiver is gone");
+    }).detach();
+    assert_eq!(receiver.await, Ok(7));
+
+    let (sender, receiver) = oneshot::channel::<u32>();
+    drop(sender);
+    assert_eq!(receiver.await, Err(oneshot::RecvError));
+
+    let (sender, receiver) = oneshot::channel();
+    drop(receiver);
+    assert_eq!(sender.send(7), Err(7));
+}
+
+async fn broadcast_reaches_every_receiver() {
+    let (sender, mut first) = broadcast::channel(4);
+    let mut second          = sender.subscribe();
+
+    let listener = task::spawn(async move {
+        let mut received = Vec::new();
+        while let Ok(value) = second.recv().await {
+            received.push(value);
+        }
+        received
+    });
+
+    task::yield_now().await;
+    for value in 0..3 {
+        assert_eq!(sender.send(value), Ok(2));
+        task::yield_now().await;
+    }
+
+    // Receiver that is not keeping up loses the oldest values
+    let mut late = sender.subscribe();
+    for value in 3..9 {
+        sender.send(value).expect("no receivers");
+    }
+    assert_eq!(late.recv().await, Err(broadcast::RecvError::Lagged(2)));
+    assert_eq!(late.recv().await, Ok(5));
+
+    drop(sender);
+    assert_eq!(first.recv().await, Err(broadcast::RecvError::Lagged(5)));
+    for value in 5..9 {
+        assert_eq!(first.recv().await, Ok(value));
+    }
+    assert_eq!(first.recv().await, Err(broadcast::RecvError::Closed));
+
+    let received = listener.await.expect("listener has been aborted");
+    assert_eq!(received[..3], [0, 1, 2]);
+}
+
+fn ticking_timer_handler(index: InterruptIndex) {
+    if let Ok(handler) = TIMER_HANDLER.try_get() {
+        handler(index);
+    }
+
+    // Consumer may fall behind, ticks that do not fit are simply not reported
+    let tick = TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+    if let Ok(ticks) = TICKS.try_get() {
+        let _ = ticks.try_send(tick);
+    }
+}
+
+async fn interrupt_handler_sends() {
+    let (sender, mut receiver) = mpsc::channel(8);
+    TICKS.init_once(|| sender);
+
+    let original = interrupts::unregister_irq(InterruptIndex::Timer).expect("timer handler is not registered");
+    TIMER_HANDLER.init_once(|| original);
+    interrupts::register_irq(InterruptIndex::Timer, ticking_timer_handler).expect("timer handler is registered");
+
+    let mut last = None;
+    for _ in 0..3 {
+        let tick = receiver.recv().await.expect("timer sender is gone");
+        assert!(last.filter(|&last| last >= tick).is_none(), "ticks arrived out of order");
+        last = Some(tick);
+    }
+
+    interrupts::unregister_irq(InterruptIndex::Timer).expect("timer handler is not registered");
+    interrupts::register_irq(InterruptIndex::Timer, original).expect("timer handler is registered");
+}
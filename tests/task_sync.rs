@@ -0,0 +1,211 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    future::{ self, Future },
+    panic::PanicInfo,
+    pin::Pin,
+    sync::atomic::{ AtomicUsize, Ordering },
+    task::Poll,
+};
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    task::{
+        self,
+        executor::Executor,
+        sync::{ Mutex, Notify, RwLock, Semaphore },
+        Task,
+    },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+use x86_64::VirtAddr;
+
+const WORKERS: usize = 4;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+
+    // Executor never returns, so the only task runs tests one by one & exits QEMU
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests()));
+    executor.run();
+}
+
+async fn run_tests() {
+    serial_println!("task_sync::mutex_is_held_across_await...\t");
+    mutex_is_held_across_await().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_sync::rwlock_writer_is_not_starved...\t");
+    rwlock_writer_is_not_starved().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_sync::semaphore_limits_concurrency...\t");
+    semaphore_limits_concurrency().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_sync::notify_wakes_waiters...\t");
+    notify_wakes_waiters().await;
+    serial_println!("[ok]!");
+
+    serial_println!("task_sync::dropped_notified_passes_notification_on...\t");
+    dropped_notified_passes_notification_on().await;
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+}
+
+async fn mutex_is_held_across_await() {
+    static COUNTER: Mutex<usize> = Mutex::new(0);
+
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| task::spawn(async {
+            for _ in 0..3 {
+                let mut counter = COUNTER.lock().await;
+                let seen        = *counter;
+
+                // Other workers run meanwhile, but none of them could get in
+                task::yield_now().await;
+                assert_eq!(*counter, seen);
+                *counter = seen + 1;
+            }
+        }))
+        .collect();
+
+    for worker in workers {
+        worker.await.expect("worker has been aborted");
+    }
+    assert_eq!(*COUNTER.lock().await, WORKERS * 3);
+}
+
+async fn rwlock_writer_is_not_starved() {
+    static LOCK: RwLock<u32> = RwLock::new(0);
+
+    let reader = LOCK.read().await;
+    assert!(LOCK.try_read().is_some());
+
+    let writer = task::spawn(async {
+        *LOCK.write().await = 1;
+    });
+    task::yield_now().await;
+
+    // Writer waits for the reader, new readers have to queue up behind the writer
+    assert!(!writer.is_finished());
+    assert!(LOCK.try_read().is_none());
+
+    drop(reader);
+    assert_eq!(*LOCK.read().await, 1);
+    assert!(writer.is_finished());
+}
+
+async fn semaphore_limits_concurrency() {
+    static SEMAPHORE: Semaphore   = Semaphore::new(2);
+    static INSIDE:    AtomicUsize = AtomicUsize::new(0);
+    static MAX:       AtomicUsize = AtomicUsize::new(0);
+
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| task::spawn(async {
+            let _permit = SEMAPHORE.acquire().await;
+            let inside  = INSIDE.fetch_add(1, Ordering::Relaxed) + 1;
+            MAX.fetch_max(inside, Ordering::Relaxed);
+
+            task::yield_now().await;
+            INSIDE.fetch_sub(1, Ordering::Relaxed);
+        }))
+        .collect();
+
+    for worker in workers {
+        worker.await.expect("worker has been aborted");
+    }
+    assert_eq!(MAX.load(Ordering::Relaxed), 2);
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+}
+
+async fn notify_wakes_waiters() {
+    static NOTIFY: Notify      = Notify::new();
+    static WOKEN:  AtomicUsize = AtomicUsize::new(0);
+
+    // Notification sent while nobody waits is kept for the next waiter
+    NOTIFY.notify_one();
+    NOTIFY.notified().await;
+
+    let waiters: Vec<_> = (0..WORKERS)
+        .map(|_| task::spawn(async {
+            NOTIFY.notified().await;
+            WOKEN.fetch_add(1, Ordering::Relaxed);
+        }))
+        .collect();
+    task::yield_now().await;
+    assert_eq!(WOKEN.load(Ordering::Relaxed), 0);
+
+    NOTIFY.notify_one();
+    task::yield_now().await;
+    assert_eq!(WOKEN.load(Ordering::Relaxed), 1);
+
+    NOTIFY.notify_waiters();
+    for waiter in waiters {
+        waiter.await.expect("waiter has been aborted");
+    }
+    assert_eq!(WOKEN.load(Ordering::Relaxed), WORKERS);
+}
+
+async fn dropped_notified_passes_notification_on() {
+    static NOTIFY: Notify = Notify::new();
+
+    let mut first  = Box::pin(NOTIFY.notified());
+    let mut second = Box::pin(NOTIFY.notified());
+    assert!(poll_once(&mut first).await.is_pending());
+    assert!(poll_once(&mut second).await.is_pending());
+
+    // First waiter is notified, but dropped before it sees that
+    NOTIFY.notify_one();
+    drop(first);
+    assert!(poll_once(&mut second).await.is_ready());
+
+    // With nobody else waiting, notification is kept for the next waiter
+    let mut third = Box::pin(NOTIFY.notified());
+    assert!(poll_once(&mut third).await.is_pending());
+    NOTIFY.notify_one();
+    drop(third);
+    assert!(poll_once(&mut Box::pin(NOTIFY.notified())).await.is_ready());
+
+    // Notification of notify_waiters is not passed on
+    let mut fourth = Box::pin(NOTIFY.notified());
+    let mut fifth  = Box::pin(NOTIFY.notified());
+    assert!(poll_once(&mut fourth).await.is_pending());
+    NOTIFY.notify_waiters();
+    assert!(poll_once(&mut fifth).await.is_pending());
+    drop(fourth);
+    assert!(poll_once(&mut fifth).await.is_pending());
+}
+
+/// Polls `future` just once with the waker of the calling task
+async fn poll_once<F: Future>(future: &mut Pin<Box<F>>) -> Poll<F::Output> {
+    future::poll_fn(|context| Poll::Ready(future.as_mut().poll(context))).await
+}
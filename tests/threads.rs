@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{ entry_point, BootInfo };
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{ AtomicBool, AtomicU64, Ordering },
+};
+use x86_64::{
+    structures::paging::{ FrameAllocator, Mapper, Size4KiB },
+    VirtAddr,
+};
+
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    task::{ executor::Executor, spawner },
+    thread::{ self, ThreadId, ThreadState },
+    time::{ self, TickSource },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_println,
+    test_panic_handler,
+};
+
+// Long enough for several time slices to pass
+const TIMEOUT_TICKS: u64 = 100;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+    thread::init(2);
+
+    serial_println!("threads::spawned_thread_runs_and_is_reaped...\t");
+    spawned_thread_runs_and_is_reaped(&mut mapper, &mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("threads::busy_threads_are_preempted...\t");
+    busy_threads_are_preempted(&mut mapper, &mut frame_allocator);
+    serial_println!("[ok]!");
+
+    serial_println!("threads::spawner_stays_with_its_thread...\t");
+    spawner_stays_with_its_thread(&mut mapper, &mut frame_allocator);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn spawned_thread_runs_and_is_reaped(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) {
+    static RAN_ON: AtomicU64 = AtomicU64::new(0);
+
+    let id = thread::spawn("worker", || RAN_ON.store(thread::current().as_u64(), Ordering::Relaxed), mapper, frame_allocator)
+        .expect("failed to spawn thread");
+    assert_eq!(thread::state(id), Some(ThreadState::Ready));
+
+    // Worker finishes within its first slice, then it is reaped on the switch after that
+    wait_until(|| thread::state(id).is_none());
+    assert_eq!(RAN_ON.load(Ordering::Relaxed), id.as_u64());
+    assert_eq!(thread::current(), ThreadId::BOOT);
+}
+
+fn busy_threads_are_preempted(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) {
+    static STOP:     AtomicBool = AtomicBool::new(false);
+    static COUNTERS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+    // Neither thread ever yields, so boot thread only gets CPU back if they are preempted
+    let hogs = [0, 1].map(|index| {
+        let hog = move || {
+            while !STOP.load(Ordering::Relaxed) {
+                COUNTERS[index].fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        thread::spawn("hog", hog, mapper, frame_allocator).expect("failed to spawn thread")
+    });
+    thread::yield_now();
+
+    wait_until(|| COUNTERS.iter().all(|counter| counter.load(Ordering::Relaxed) > 0));
+    assert_eq!(thread::list().iter().filter(|info| info.name == "hog").count(), 2);
+
+    STOP.store(true, Ordering::Relaxed);
+    wait_until(|| hogs.iter().all(|&hog| thread::state(hog).is_none()));
+}
+
+fn spawner_stays_with_its_thread(
+    mapper:          &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) {
+    static SAW_SPAWNER: AtomicBool = AtomicBool::new(true);
+
+    // Boot thread switches to the other one while it runs a task, i.e. while its executor is entered
+    let mut executor = Executor::new();
+    executor.block_on(async {
+        let other = thread::spawn("other", || SAW_SPAWNER.store(spawner::current().is_some(), Ordering::Relaxed), mapper, frame_allocator)
+            .expect("failed to spawn thread");
+        wait_until(|| thread::state(other).is_none());
+
+        assert!(spawner::current().is_some(), "spawner is gone after switching back");
+    });
+
+    assert!(!SAW_SPAWNER.load(Ordering::Relaxed), "other thread got spawner of the boot thread");
+    assert!(spawner::current().is_none());
+}
+
+fn wait_until(condition: impl Fn() -> bool) {
+    let deadline = time::ticks() + TIMEOUT_TICKS;
+
+    while !condition() {
+        assert!(time::ticks() < deadline, "timed out");
+        thread::yield_now();
+    }
+}
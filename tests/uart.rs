@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{ entry_point, BootInfo };
+use core::panic::PanicInfo;
+use futures_util::stream::StreamExt;
+use radius_os::{
+    memory::{ self, BootInfoFrameAllocator },
+    task::executor::Executor,
+    time::{ self, TickSource },
+    uart::{ self, ComPort, Config, DataBits, Parity, StopBits, Uart, UartError },
+    allocator,
+    gdt,
+    init,
+    qemu_codes,
+    serial_print,
+    serial_println,
+    test_panic_handler,
+};
+use x86_64::VirtAddr;
+
+const TIMEOUT_NS: u64 = 1_000_000_000;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init();
+
+    let phys_memory_offset  = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper          = unsafe { memory::init(phys_memory_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    gdt::init_ist_stacks(&mut mapper, &mut frame_allocator).expect("IST stacks initialisation failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialisation failed");
+    time::init(TickSource::Pit, &mut mapper, &mut frame_allocator);
+
+    serial_println!("uart::invalid_baud_rate_is_rejected...\t");
+    invalid_baud_rate_is_rejected();
+    serial_println!("[ok]!");
+
+    serial_println!("uart::com1_opens_once...\t");
+    let com1 = com1_opens_once();
+    serial_println!("[ok]!");
+
+    serial_println!("uart::console_goes_through_driver...\t");
+    console_goes_through_driver(com1);
+    serial_println!("[ok]!");
+
+    // Test output goes to COM1 too, so nothing is printed while it is in loopback mode
+    serial_println!("uart::written_bytes_are_received...\t");
+    written_bytes_are_received(com1);
+    serial_println!("[ok]!");
+
+    serial_println!("uart::stream_yields_received_bytes...\t");
+    stream_yields_received_bytes(com1);
+    serial_println!("[ok]!");
+
+    serial_println!("uart::reopen_with_other_config...\t");
+    reopen_with_other_config(com1);
+    serial_println!("[ok]!");
+
+    qemu_codes::exit_qemu(qemu_codes::QemuExitCode::Success);
+    loop {}
+}
+
+fn invalid_baud_rate_is_rejected() {
+    assert_eq!(uart::open(ComPort::Com1, Config::new(0)).err(), Some(UartError::InvalidBaudRate(0)));
+    assert_eq!(uart::open(ComPort::Com1, Config::new(1_000_000)).err(), Some(UartError::InvalidBaudRate(1_000_000)));
+    assert!(uart::get(ComPort::Com1).is_none());
+}
+
+fn com1_opens_once() -> &'static Uart {
+    let com1 = uart::open(ComPort::Com1, Config::default()).expect("failed to open COM1");
+
+    assert_eq!(uart::open(ComPort::Com1, Config::default()).err(), Some(UartError::AlreadyOpen(ComPort::Com1)));
+    assert!(uart::get(ComPort::Com1).is_some());
+    assert_eq!(com1.config(), Config::default());
+    com1
+}
+
+fn console_goes_through_driver(com1: &'static Uart) {
+    let sent = com1.stats().sent;
+    serial_print!("12345");
+    assert_eq!(com1.stats().sent, sent + 5);
+}
+
+fn written_bytes_are_received(com1: &'static Uart) {
+    com1.set_loopback(true);
+    assert_eq!(com1.write(b"ping"), 4);
+
+    let mut received = Vec::new();
+    let deadline     = time::uptime_ns() + TIMEOUT_NS;
+    while received.len() < 4 && time::uptime_ns() < deadline {
+        let mut buf = [0; 8];
+        let count   = com1.read(&mut buf);
+        received.extend_from_slice(&buf[..count]);
+    }
+    com1.set_loopback(false);
+
+    assert_eq!(received, b"ping");
+    assert!(com1.stats().received >= 4);
+}
+
+fn stream_yields_received_bytes(com1: &'static Uart) {
+    let mut stream = com1.stream().expect("stream is taken");
+    assert_eq!(com1.stream().err(), Some(UartError::StreamTaken(ComPort::Com1)));
+
+    com1.set_loopback(true);
+    com1.write(b"hello");
+    // Task waits on stream until receive interrupt wakes it up
+    let received = Executor::new().block_on(async {
+        let mut received = Vec::new();
+        while received.len() < 5 {
+            received.push(stream.next().await.expect("stream has ended"));
+        }
+        received
+    });
+    com1.set_loopback(false);
+
+    assert_eq!(received, b"hello");
+    drop(stream);
+    assert!(com1.stream().is_ok());
+}
+
+fn reopen_with_other_config(com1: &'static Uart) {
+    com1.close();
+    assert!(uart::get(ComPort::Com1).is_none());
+    // Console falls back to polled port
+    serial_print!("closed ");
+
+    let config = Config {
+        baud_rate: 38_400,
+        data_bits: DataBits::Seven,
+        parity:    Parity::Even,
+        stop_bits: StopBits::Two,
+    };
+    let com1 = uart::open(ComPort::Com1, config).expect("failed to reopen COM1");
+    assert_eq!(com1.config(), config);
+    com1.close();
+
+    uart::open(ComPort::Com1, Config::default()).expect("failed to reopen COM1");
+}